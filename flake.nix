@@ -100,6 +100,9 @@
             authenticate.exec = ''
               cargo run --bin acp -- authenticate --server-address "127.0.0.1:8080" --user $1
             '';
+            change-password.exec = ''
+              cargo run --bin acp -- change-password --server-address "127.0.0.1:8080" --user $1
+            '';
             server.exec = ''
               cargo run --bin acp -- server --port 8080
             '';
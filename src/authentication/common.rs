@@ -1,5 +1,6 @@
 use num_bigint::BigUint;
 use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha512};
 
 // Define a function to generate a random alphanumeric string of a given length.
 pub fn generate_random_string_of_length(size: usize) -> String {
@@ -12,6 +13,13 @@ pub fn generate_random_string_of_length(size: usize) -> String {
         .collect() // Collect elements into a `String`.
 }
 
+// Define a function to generate a vector of `size` random bytes.
+pub fn generate_random_bytes(size: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; size];
+    thread_rng().fill(bytes.as_mut_slice());
+    bytes
+}
+
 // Define a function to generate a random `BigUint` within a specified upper bound.
 pub fn get_random_int_within_bound(upper_bound: &BigUint) -> BigUint {
     let mut rng = thread_rng(); // Get a thread-local random number generator.
@@ -19,3 +27,34 @@ pub fn get_random_int_within_bound(upper_bound: &BigUint) -> BigUint {
                                            // Generate a random `BigUint` between `lower_bound` (inclusive) and `upper_bound` (exclusive).
     rng.gen_range(lower_bound..upper_bound.clone())
 }
+
+// Public seed used to derive the second generator `h` of every built in group, so that anyone can
+// recompute `h` and check that nobody chose it with a known discrete logarithm.
+pub const DEFAULT_GENERATOR_SEED: &str = "adapted-chaum-pedersen second generator";
+
+// Encode each part with a 64-bit big endian length prefix, so that distinct lists of parts can
+// never produce the same byte string.
+pub fn length_prefixed(parts: &[&[u8]]) -> Vec<u8> {
+    parts
+        .iter()
+        .flat_map(|part| {
+            (part.len() as u64)
+                .to_be_bytes()
+                .into_iter()
+                .chain(part.iter().copied())
+        })
+        .collect()
+}
+
+// Expand the input into `length` pseudo-random bytes by hashing a domain separation tag, a block
+// counter and the input with SHA-512, concatenating blocks until there are enough bytes.
+pub fn expand_hash(domain: &[u8], input: &[u8], length: usize) -> Vec<u8> {
+    (0u32..)
+        .flat_map(|block| {
+            Sha512::new()
+                .chain_update(length_prefixed(&[domain, &block.to_be_bytes(), input]))
+                .finalize()
+        })
+        .take(length)
+        .collect()
+}
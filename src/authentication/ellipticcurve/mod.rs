@@ -1,62 +1,267 @@
-use super::common::{generate_random_string_of_length, get_random_int_within_bound};
-use super::Authenticate;
+use super::common::{expand_hash, length_prefixed, DEFAULT_GENERATOR_SEED};
+use super::Group;
+use crate::errors::AuthenticationError;
 use crate::zkp_auth::AuthenticationType;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
 use num_bigint::BigUint;
+use rand::thread_rng;
 
+// Domain separation tag for hashing onto the curve when deriving `h`.
+const H_DERIVATION_DOMAIN: &[u8] = b"adapted-chaum-pedersen/ristretto255/h";
+
+// Description of the group, included when deriving `h` so it can never collide with another group.
+const GROUP_DESCRIPTION: &[u8] = b"ristretto255";
+
+// Size in bytes of both an encoded Ristretto point and a scalar.
+const ENCODED_SIZE: usize = 32;
+
+// Define a structure `EllipticCurve` for the Ristretto255 based authentication mechanism.
 #[derive(Debug)]
 pub struct EllipticCurve {
-    pub p: BigUint,
-    pub q: BigUint,
-    pub g: BigUint,
-    pub h: BigUint,
+    pub g: RistrettoPoint, // Generator of the prime order group, the Ristretto basepoint.
+    pub h: RistrettoPoint, // Independent generator, hashed onto the curve so that log_g(h) is unknown.
 }
 
-impl Authenticate for EllipticCurve {
+// Implement the `Group` trait for the `EllipticCurve` struct, written additively on the curve.
+impl Group for EllipticCurve {
+    type Element = RistrettoPoint;
+    type Scalar = Scalar;
+
+    // Return the authentication type, indicating this uses elliptic curve based authentication.
     fn auth_type(&self) -> AuthenticationType {
         AuthenticationType::EllipticCurve
     }
-    fn auth_id(&self) -> String {
-        unimplemented!("No support for Elliptic Curves yet")
+
+    // The group is described by its name and both generators.
+    fn description(&self) -> Vec<u8> {
+        length_prefixed(&[
+            GROUP_DESCRIPTION,
+            self.g.compress().as_bytes(),
+            self.h.compress().as_bytes(),
+        ])
+    }
+
+    fn g(&self) -> &RistrettoPoint {
+        &self.g
+    }
+
+    fn h(&self) -> &RistrettoPoint {
+        &self.h
+    }
+
+    // Scalar multiplication of a point.
+    fn exp(&self, base: &RistrettoPoint, exponent: &Scalar) -> RistrettoPoint {
+        base * exponent
+    }
+
+    // Point addition.
+    fn mul(&self, a: &RistrettoPoint, b: &RistrettoPoint) -> RistrettoPoint {
+        a + b
+    }
+
+    // Draw random scalars until a non-zero one is found, zero would leak the secret in a response.
+    fn random_scalar(&self) -> Scalar {
+        let mut rng = thread_rng();
+        loop {
+            let scalar = Scalar::random(&mut rng);
+            if scalar != Scalar::ZERO {
+                return scalar;
+            }
+        }
+    }
+
+    // Interpret the bytes as a big endian integer and map it into [1, l) for the group order `l`.
+    fn scalar_from_bytes(&self, bytes: &[u8]) -> Scalar {
+        let reduced = BigUint::from_bytes_be(bytes) % (group_order() - 1u32) + 1u32;
+        let mut le = reduced.to_bytes_le();
+        le.resize(ENCODED_SIZE, 0);
+        let mut wide = [0u8; ENCODED_SIZE];
+        wide.copy_from_slice(&le);
+        Scalar::from_bytes_mod_order(wide)
     }
-    fn session_id(&self) -> String {
-        unimplemented!("No support for Elliptic Curves yet")
+
+    fn scalar_size(&self) -> usize {
+        ENCODED_SIZE
+    }
+
+    fn scalar_mul(&self, a: &Scalar, b: &Scalar) -> Scalar {
+        a * b
     }
-    fn get_random(&self) -> BigUint {
-        unimplemented!("No support for Elliptic Curves yet")
+
+    fn scalar_sub(&self, a: &Scalar, b: &Scalar) -> Scalar {
+        a - b
     }
 
-    fn registration(&self, _secret: &BigUint) -> (BigUint, BigUint) {
-        unimplemented!("No support for Elliptic Curves yet")
+    // Points are encoded in their 32 byte compressed Ristretto form.
+    fn encode_element(&self, element: &RistrettoPoint) -> Vec<u8> {
+        element.compress().to_bytes().to_vec()
     }
-    fn challenge(&self) -> BigUint {
-        unimplemented!("No support for Elliptic Curves yet")
+
+    // Decompression rejects anything that is not the canonical encoding of a group element.
+    fn decode_element(&self, bytes: &[u8]) -> Result<RistrettoPoint, AuthenticationError> {
+        CompressedRistretto::from_slice(bytes)
+            .ok()
+            .and_then(|compressed| compressed.decompress())
+            .ok_or(AuthenticationError::InvalidElementEncoding)
     }
-    fn response(&self, _nonce: &BigUint, _secret: &BigUint, _challenge: &BigUint) -> BigUint {
-        unimplemented!("No support for Elliptic Curves yet")
+
+    // Every decoded Ristretto point is in the prime order group, so only the identity is refused.
+    fn validate_element(&self, element: &RistrettoPoint) -> Result<(), AuthenticationError> {
+        if element.is_identity() {
+            return Err(AuthenticationError::InvalidElement);
+        }
+        Ok(())
     }
-    fn authentication(&self, _nonce: &BigUint) -> (BigUint, BigUint) {
-        unimplemented!("No support for Elliptic Curves yet")
+
+    // Scalars are encoded as 32 little endian bytes, as is usual for the curve.
+    fn encode_scalar(&self, scalar: &Scalar) -> Vec<u8> {
+        scalar.to_bytes().to_vec()
     }
-    fn verify(
-        &self,
-        y1: &BigUint,
-        y2: &BigUint,
-        r1: &BigUint,
-        r2: &BigUint,
-        s: &BigUint,
-        c: &BigUint,
-    ) -> bool {
-        unimplemented!("No support for Elliptic Curves yet")
+
+    // Only fully reduced scalars are accepted.
+    fn decode_scalar(&self, bytes: &[u8]) -> Result<Scalar, AuthenticationError> {
+        let bytes: [u8; ENCODED_SIZE] = bytes
+            .try_into()
+            .map_err(|_| AuthenticationError::InvalidScalarEncoding)?;
+        Option::from(Scalar::from_canonical_bytes(bytes))
+            .ok_or(AuthenticationError::InvalidScalarEncoding)
     }
 }
 
 impl EllipticCurve {
     pub fn new() -> Self {
-        EllipticCurve {
-            p: BigUint::from(0u32),
-            q: BigUint::from(0u32),
-            g: BigUint::from(0u32),
-            h: BigUint::from(0u32),
+        // Derive `h` from the default public seed
+        Self::with_seed(DEFAULT_GENERATOR_SEED)
+    }
+
+    pub fn with_seed(seed: &str) -> Self {
+        // Use the Ristretto basepoint for `g` and hash the seed onto the curve for `h`
+        let g = RISTRETTO_BASEPOINT_POINT;
+        let h = Self::derive_h(&g, seed);
+        EllipticCurve { g, h }
+    }
+
+    // Deterministically derive the second generator `h` from `g`, the group description and a
+    // public seed, using the Ristretto255 hash-to-group map on 64 uniform bytes. Since `h` is a
+    // hash output, nobody can know log_g(h) without solving a discrete logarithm.
+    pub fn derive_h(g: &RistrettoPoint, seed: &str) -> RistrettoPoint {
+        let input = length_prefixed(&[GROUP_DESCRIPTION, g.compress().as_bytes(), seed.as_bytes()]);
+        let mut uniform = [0u8; 64];
+        uniform.copy_from_slice(&expand_hash(H_DERIVATION_DOMAIN, &input, 64));
+        RistrettoPoint::from_uniform_bytes(&uniform)
+    }
+}
+
+impl Default for EllipticCurve {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The order `l` of the Ristretto255 group as an integer, recovered from the scalar `-1`.
+fn group_order() -> BigUint {
+    BigUint::from_bytes_le((-Scalar::ONE).as_bytes()) + 1u32
+}
+
+// Unit and property-based tests for the `EllipticCurve` authentication mechanism.
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::authentication::{common::generate_random_string_of_length, Authenticate},
+        proptest::prelude::*,
+        test_case::test_case,
+    };
+
+    // The two generators must be distinct and neither can be the identity.
+    #[test]
+    fn generators_should_be_independent() {
+        let e = EllipticCurve::new();
+        assert_ne!(e.g, e.h, "g and h must be distinct generators");
+        assert_ne!(e.h, RistrettoPoint::default(), "h must not be the identity");
+    }
+
+    // `h` must be reproducible from the public seed and change with the seed.
+    #[test]
+    fn h_should_be_derived_from_the_seed() {
+        let e = EllipticCurve::new();
+        assert_eq!(EllipticCurve::derive_h(&e.g, DEFAULT_GENERATOR_SEED), e.h);
+        assert_ne!(EllipticCurve::with_seed("another seed").h, e.h);
+    }
+
+    // Points must survive the round trip through their encoding.
+    #[test]
+    fn points_should_round_trip_through_encoding() {
+        let e = EllipticCurve::new();
+        let point = e.h * e.random_scalar();
+        assert_eq!(e.decode_element(&e.encode_element(&point)).unwrap(), point);
+    }
+
+    // Secrets wider than a scalar must be mapped into [1, l), so that zero is never a secret.
+    #[test_case(&[0xab; 100]; "when secret is wide")]
+    #[test_case(&[0x00; 32]; "when secret is zero")]
+    fn secrets_should_be_mapped_into_non_zero_scalars(secret: &[u8]) {
+        let e = EllipticCurve::new();
+        let reduced = BigUint::from_bytes_be(secret) % (group_order() - 1u32) + 1u32;
+        assert_eq!(
+            BigUint::from_bytes_le(e.scalar_from_bytes(secret).as_bytes()),
+            reduced,
+            "Secret was not mapped into [1, l)"
+        );
+    }
+
+    // Values that are not canonical encodings must be rejected rather than verified.
+    #[test]
+    fn verify_should_reject_invalid_encodings() {
+        let e = EllipticCurve::new();
+        let secret = e.secret(b"42");
+        let (y1, y2) = e.registration(&secret).unwrap();
+        let k = e.get_random();
+        let (_, r2) = e.authentication(&k).unwrap();
+        let c = e.challenge();
+        let s = e.response(&k, &secret, &c).unwrap();
+        let invalid = [0xff; ENCODED_SIZE];
+        assert!(e.verify(&y1, &y2, &invalid, &r2, &s, &c).is_err());
+        assert!(e.verify(&y1, &y2, &y1[..31], &r2, &s, &c).is_err());
+        assert!(e.verify(&y1, &y2, &y1, &r2, &invalid, &c).is_err());
+    }
+
+    // The identity must be refused, even though it is a canonical encoding.
+    #[test]
+    fn identity_should_be_rejected() {
+        let e = EllipticCurve::new();
+        let identity = e.encode_element(&RistrettoPoint::default());
+        assert!(e.decode_element(&identity).is_ok());
+        assert!(e.check_element(&identity).is_err());
+        assert!(e.check_element(&e.encode_element(&e.h)).is_ok());
+    }
+
+    // Define a strategy for generating random passwords for testing.
+    fn password_strategy() -> impl Strategy<Value = Vec<u8>> {
+        (1_0u32..=2_0)
+            .prop_map(|n| n as usize)
+            .prop_map(generate_random_string_of_length)
+            .prop_map(|s| s.trim().as_bytes().to_vec())
+    }
+
+    // Property-based test to verify the full authentication process.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+        #[test]
+        fn test_full_authentication(password in password_strategy()) {
+            let e = EllipticCurve::new();
+            let secret = e.secret(&password);
+            let (y1, y2) = e.registration(&secret).unwrap();
+            let  k = e.get_random();
+            let (r1,r2) = e.authentication(&k).unwrap();
+            let c = e.challenge();
+            let s = e.response(&k,&secret,&c).unwrap();
+            let auth = e.verify(&y1,&y2,&r1,&r2,&s,&c).unwrap();
+
+            prop_assert!(&auth, "Authentication should have been successful: secret:{:?}, y1:{:?}, y2:{:?}, r1:{:?}, r2:{:?}, k:{:?}, s:{:?}, c:{:?}", &secret,&y1,&y2,&r1,&r2,&k,&s,&c);
         }
     }
 }
@@ -0,0 +1,161 @@
+use clap::ValueEnum;
+use num_bigint::BigUint;
+
+// Identifiers for the standardised groups the `Exponentiation` authenticator can be built on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum GroupId {
+    // RFC 3526 2048-bit MODP group, a safe prime group with q = (p-1)/2.
+    #[default]
+    #[value(name = "modp2048")]
+    Modp2048,
+    // RFC 3526 3072-bit MODP group, a safe prime group with q = (p-1)/2.
+    #[value(name = "modp3072")]
+    Modp3072,
+    // RFC 3526 4096-bit MODP group, a safe prime group with q = (p-1)/2.
+    #[value(name = "modp4096")]
+    Modp4096,
+    // RFC 5114 2048-bit MODP group with a 224-bit prime order subgroup.
+    #[value(name = "rfc5114-2048-224")]
+    Rfc5114Modp2048Q224,
+    // RFC 5114 2048-bit MODP group with a 256-bit prime order subgroup.
+    #[value(name = "rfc5114-2048-256")]
+    Rfc5114Modp2048Q256,
+}
+
+impl GroupId {
+    // Return the published modulus `p`, subgroup order `q` and generator `g` of the group.
+    pub fn parameters(&self) -> (BigUint, BigUint, BigUint) {
+        match self {
+            GroupId::Modp2048 => safe_prime_group(MODP_2048_P),
+            GroupId::Modp3072 => safe_prime_group(MODP_3072_P),
+            GroupId::Modp4096 => safe_prime_group(MODP_4096_P),
+            GroupId::Rfc5114Modp2048Q224 => (
+                from_hex(RFC5114_2048_224_P),
+                from_hex(RFC5114_2048_224_Q),
+                from_hex(RFC5114_2048_224_G),
+            ),
+            GroupId::Rfc5114Modp2048Q256 => (
+                from_hex(RFC5114_2048_256_P),
+                from_hex(RFC5114_2048_256_Q),
+                from_hex(RFC5114_2048_256_G),
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for GroupId {
+    // Display the group using the same name that is accepted on the command line.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self
+            .to_possible_value()
+            .map(|v| v.get_name().to_string())
+            .unwrap_or_default();
+        write!(f, "{}", name)
+    }
+}
+
+// RFC 3526 groups are safe prime groups generated by 2, so the subgroup of squares has order (p-1)/2.
+fn safe_prime_group(p: &str) -> (BigUint, BigUint, BigUint) {
+    let p = from_hex(p);
+    let q = (&p - 1u32) >> 1;
+    (p, q, BigUint::from(2u32))
+}
+
+// Parse one of the hexadecimal constants below, they are all known to be valid.
+fn from_hex(hex: &str) -> BigUint {
+    BigUint::parse_bytes(hex.as_bytes(), 16).expect("Group constants are valid hexadecimal")
+}
+
+// RFC 3526 section 3, 2048-bit MODP group (group 14).
+const MODP_2048_P: &str = concat!(
+    "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+    "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+    "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+    "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+    "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+    "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+    "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+    "3995497CEA956AE515D2261898FA051015728E5A8AACAA68FFFFFFFFFFFFFFFF",
+);
+
+// RFC 3526 section 4, 3072-bit MODP group (group 15).
+const MODP_3072_P: &str = concat!(
+    "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+    "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+    "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+    "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+    "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+    "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+    "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+    "3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33",
+    "A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7",
+    "ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864",
+    "D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2",
+    "08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF",
+);
+
+// RFC 3526 section 5, 4096-bit MODP group (group 16).
+const MODP_4096_P: &str = concat!(
+    "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+    "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+    "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+    "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+    "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+    "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+    "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+    "3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33",
+    "A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7",
+    "ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864",
+    "D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2",
+    "08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A92108011A723C12A787E6D7",
+    "88719A10BDBA5B2699C327186AF4E23C1A946834B6150BDA2583E9CA2AD44CE8",
+    "DBBBC2DB04DE8EF92E8EFC141FBECAA6287C59474E6BC05D99B2964FA090C3A2",
+    "233BA186515BE7ED1F612970CEE2D7AFB81BDD762170481CD0069127D5B05AA9",
+    "93B4EA988D8FDDC186FFB7DC90A6C08F4DF435C934063199FFFFFFFFFFFFFFFF",
+);
+
+// RFC 5114 section 2.2, 2048-bit MODP group with 224-bit prime order subgroup.
+const RFC5114_2048_224_P: &str = concat!(
+    "AD107E1E9123A9D0D660FAA79559C51FA20D64E5683B9FD1B54B1597B61D0A75",
+    "E6FA141DF95A56DBAF9A3C407BA1DF15EB3D688A309C180E1DE6B85A1274A0A6",
+    "6D3F8152AD6AC2129037C9EDEFDA4DF8D91E8FEF55B7394B7AD5B7D0B6C12207",
+    "C9F98D11ED34DBF6C6BA0B2C8BBC27BE6A00E0A0B9C49708B3BF8A3170918836",
+    "81286130BC8985DB1602E714415D9330278273C7DE31EFDC7310F7121FD5A074",
+    "15987D9ADC0A486DCDF93ACC44328387315D75E198C641A480CD86A1B9E587E8",
+    "BE60E69CC928B2B9C52172E413042E9B23F10B0E16E79763C9B53DCF4BA80A29",
+    "E3FB73C16B8E75B97EF363E2FFA31F71CF9DE5384E71B81C0AC4DFFE0C10E64F",
+);
+const RFC5114_2048_224_Q: &str = "801C0D34C58D93FE997177101F80535A4738CEBCBF389A99B36371EB";
+const RFC5114_2048_224_G: &str = concat!(
+    "AC4032EF4F2D9AE39DF30B5C8FFDAC506CDEBE7B89998CAF74866A08CFE4FFE3",
+    "A6824A4E10B9A6F0DD921F01A70C4AFAAB739D7700C29F52C57DB17C620A8652",
+    "BE5E9001A8D66AD7C17669101999024AF4D027275AC1348BB8A762D0521BC98A",
+    "E247150422EA1ED409939D54DA7460CDB5F6C6B250717CBEF180EB34118E98D1",
+    "19529A45D6F834566E3025E316A330EFBB77A86F0C1AB15B051AE3D428C8F8AC",
+    "B70A8137150B8EEB10E183EDD19963DDD9E263E4770589EF6AA21E7F5F2FF381",
+    "B539CCE3409D13CD566AFBB48D6C019181E1BCFE94B30269EDFE72FE9B6AA4BD",
+    "7B5A0F1C71CFFF4C19C418E1F6EC017981BC087F2A7065B384B890D3191F2BFA",
+);
+
+// RFC 5114 section 2.3, 2048-bit MODP group with 256-bit prime order subgroup.
+const RFC5114_2048_256_P: &str = concat!(
+    "87A8E61DB4B6663CFFBBD19C651959998CEEF608660DD0F25D2CEED4435E3B00",
+    "E00DF8F1D61957D4FAF7DF4561B2AA3016C3D91134096FAA3BF4296D830E9A7C",
+    "209E0C6497517ABD5A8A9D306BCF67ED91F9E6725B4758C022E0B1EF4275BF7B",
+    "6C5BFC11D45F9088B941F54EB1E59BB8BC39A0BF12307F5C4FDB70C581B23F76",
+    "B63ACAE1CAA6B7902D52526735488A0EF13C6D9A51BFA4AB3AD8347796524D8E",
+    "F6A167B5A41825D967E144E5140564251CCACB83E6B486F6B3CA3F7971506026",
+    "C0B857F689962856DED4010ABD0BE621C3A3960A54E710C375F26375D7014103",
+    "A4B54330C198AF126116D2276E11715F693877FAD7EF09CADB094AE91E1A1597",
+);
+const RFC5114_2048_256_Q: &str = "8CF83642A709A097B447997640129DA299B1A47D1EB3750BA308B0FE64F5FBD3";
+const RFC5114_2048_256_G: &str = concat!(
+    "3FB32C9B73134D0B2E77506660EDBD484CA7B18F21EF205407F4793A1A0BA125",
+    "10DBC15077BE463FFF4FED4AAC0BB555BE3A6C1B0C6B47B1BC3773BF7E8C6F62",
+    "901228F8C28CBB18A55AE31341000A650196F931C77A57F2DDF463E5E9EC144B",
+    "777DE62AAAB8A8628AC376D282D6ED3864E67982428EBC831D14348F6F2F9193",
+    "B5045AF2767164E1DFC967C1FB3F2E55A4BD1BFFE83B9C80D052B985D182EA0A",
+    "DB2A3B7313D3FE14C8484B1E052588B9B7D2BBD2DF016199ECD06E1557CD0915",
+    "B3353BBB64E0EC377FD028370DF92B52C7891428CDC67EB6184B523D1DB246C3",
+    "2F63078490F00EF8D647D148D47954515E2327CFEF98C582664B4C0F6CC41659",
+);
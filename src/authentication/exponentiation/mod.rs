@@ -1,104 +1,213 @@
-use super::common::{generate_random_string_of_length, get_random_int_within_bound};
-use super::Authenticate;
+pub mod groups;
+pub mod params;
+pub mod primes;
+
+use super::common::{
+    expand_hash, get_random_int_within_bound, length_prefixed, DEFAULT_GENERATOR_SEED,
+};
+use super::Group;
+use crate::errors::AuthenticationError;
 use crate::zkp_auth::AuthenticationType;
-use num_bigint::{BigInt, BigUint};
-use num_traits::One;
+pub use groups::GroupId;
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+pub use params::generate_parameters;
+pub use primes::is_probable_prime;
+use sha2::{Digest, Sha256};
 
 // Define a structure `Exponentiation` for the exponentiation-based authentication mechanism.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Exponentiation {
-    pub p: BigUint, // Prime number, part of the public key.
-    pub q: BigUint, // Prime number, a divisor of p-1, part of the private key.
-    pub g: BigUint, // Generator of the cyclic group.
+    pub p: BigUint,           // Prime number, part of the public key.
+    pub q: BigUint,           // Prime number, a divisor of p-1, part of the private key.
+    pub g: BigUint,           // Generator of the cyclic group.
     pub h: BigUint, // Another generator of the cyclic group, used in the cryptographic scheme.
+    pub seed: Option<String>, // Public seed `h` was derived from, if it was derived rather than chosen.
 }
 
-// Implement the `Authenticate` trait for the `Exponentiation` struct.
-impl Authenticate for Exponentiation {
+// Domain separation tag for hashing into the order q subgroup when deriving `h`.
+const H_DERIVATION_DOMAIN: &[u8] = b"adapted-chaum-pedersen/exponentiation/h";
+
+// Implement the `Group` trait for the `Exponentiation` struct, the order q subgroup of the integers mod p.
+impl Group for Exponentiation {
+    type Element = BigUint;
+    type Scalar = BigUint;
+
     // Return the authentication type, indicating this uses exponentiation-based authentication.
     fn auth_type(&self) -> AuthenticationType {
         AuthenticationType::Exponentiation
     }
 
-    // Generate a random identifier for an authentication session.
-    fn auth_id(&self) -> String {
-        generate_random_string_of_length(50)
+    // The group is described by its modulus, subgroup order and both generators.
+    fn description(&self) -> Vec<u8> {
+        length_prefixed(&[
+            &self.p.to_bytes_be(),
+            &self.q.to_bytes_be(),
+            &self.g.to_bytes_be(),
+            &self.h.to_bytes_be(),
+        ])
+    }
+
+    fn g(&self) -> &BigUint {
+        &self.g
+    }
+
+    fn h(&self) -> &BigUint {
+        &self.h
     }
 
-    // Generate a unique identifier for a session after successful authentication.
-    fn session_id(&self) -> String {
-        generate_random_string_of_length(100)
+    // Modular exponentiation `base^exponent mod p`.
+    fn exp(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        base.modpow(exponent, &self.p)
+    }
+
+    // Modular multiplication `a * b mod p`.
+    fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        (a * b) % &self.p
     }
 
     // Get a random `BigUint` within the range of `1` to `q`.
-    fn get_random(&self) -> BigUint {
+    fn random_scalar(&self) -> BigUint {
         get_random_int_within_bound(&self.q)
     }
 
-    // Registration function that calculates `y1` and `y2` based on a given `secret`.
-    fn registration(&self, secret: &BigUint) -> (BigUint, BigUint) {
-        let y1 = self.g.modpow(&secret, &self.p);
-        let y2 = self.h.modpow(&secret, &self.p);
-        (y1, y2)
+    fn scalar_from_bytes(&self, bytes: &[u8]) -> BigUint {
+        BigUint::from_bytes_be(bytes) % (&self.q - 1u32) + 1u32
     }
 
-    // Generate a random challenge for the authentication process.
-    fn challenge(&self) -> BigUint {
-        get_random_int_within_bound(&self.q)
+    fn scalar_size(&self) -> usize {
+        (self.q.bits() as usize).div_ceil(8)
+    }
+
+    fn scalar_mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        (a * b) % &self.q
     }
 
-    // Calculate the response to a challenge during authentication.
-    fn response(&self, nonce: &BigUint, secret: &BigUint, challenge: &BigUint) -> BigUint {
-        let cs = BigInt::from(challenge * secret);
-        let n = BigInt::from(nonce.clone());
-        let q = BigInt::from(self.q.clone());
-        let s = (&n - &cs).modpow(&BigInt::one(), &q);
-        s.to_biguint().unwrap()
-    }
-
-    // Registration function that calculates `r1` and `r2` based on a given `nonce`.
-    fn authentication(&self, nonce: &BigUint) -> (BigUint, BigUint) {
-        let r1 = self.g.modpow(&nonce, &self.p);
-        let r2 = self.h.modpow(&nonce, &self.p);
-        (r1, r2)
-    }
-
-    // Verify the authentication attempt using the generated and received values.
-    fn verify(
-        &self,
-        y1: &BigUint,
-        y2: &BigUint,
-        r1: &BigUint,
-        r2: &BigUint,
-        s: &BigUint,
-        c: &BigUint,
-    ) -> bool {
-        let ver1 = (self.g.modpow(s, &self.p) * y1.modpow(c, &self.p)) % &self.p;
-        let ver2 = (self.h.modpow(s, &self.p) * y2.modpow(c, &self.p)) % &self.p;
-        ver1 == *r1 && ver2 == *r2
+    // Subtraction mod q, adding q first so the intermediate value never goes negative.
+    fn scalar_sub(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        (a + &self.q - (b % &self.q)) % &self.q
     }
+
+    // Elements are encoded big endian, left padded to the byte length of p.
+    fn encode_element(&self, element: &BigUint) -> Vec<u8> {
+        encode_fixed(element, &self.p)
+    }
+
+    fn decode_element(&self, bytes: &[u8]) -> Result<BigUint, AuthenticationError> {
+        decode_fixed(bytes, &self.p).ok_or(AuthenticationError::InvalidElementEncoding)
+    }
+
+    // Elements must lie in (1, p) and have order q, which rules out zero, the identity and every
+    // element of the small subgroups of the cofactor.
+    fn validate_element(&self, element: &BigUint) -> Result<(), AuthenticationError> {
+        if element <= &BigUint::one() || element >= &self.p || !self.exp(element, &self.q).is_one()
+        {
+            return Err(AuthenticationError::InvalidElement);
+        }
+        Ok(())
+    }
+
+    // Scalars are encoded big endian, left padded to the byte length of q.
+    fn encode_scalar(&self, scalar: &BigUint) -> Vec<u8> {
+        encode_fixed(scalar, &self.q)
+    }
+
+    fn decode_scalar(&self, bytes: &[u8]) -> Result<BigUint, AuthenticationError> {
+        decode_fixed(bytes, &self.q).ok_or(AuthenticationError::InvalidScalarEncoding)
+    }
+}
+
+// Encode a value below `modulus` as big endian bytes, left padded to the byte length of `modulus`.
+fn encode_fixed(value: &BigUint, modulus: &BigUint) -> Vec<u8> {
+    let length = (modulus.bits() as usize).div_ceil(8);
+    let bytes = value.to_bytes_be();
+    let mut encoded = vec![0u8; length.saturating_sub(bytes.len())];
+    encoded.extend_from_slice(&bytes);
+    encoded
+}
+
+// Decode a value encoded by `encode_fixed`, only accepting the exact length and values below `modulus`.
+fn decode_fixed(bytes: &[u8], modulus: &BigUint) -> Option<BigUint> {
+    let length = (modulus.bits() as usize).div_ceil(8);
+    let value = BigUint::from_bytes_be(bytes);
+    (bytes.len() == length && &value < modulus).then_some(value)
 }
 
 impl Exponentiation {
     pub fn new() -> Self {
-        // Create the Exponentiation with definied initial parameters
+        // Create the Exponentiation over the default standardised group
+        Self::with_group(GroupId::default())
+    }
+
+    pub fn with_group(group: GroupId) -> Self {
+        // Create the Exponentiation from the published parameters of the chosen group
+        let (p, q, g) = group.parameters();
+        let h = Self::derive_h(&p, &q, &g, DEFAULT_GENERATOR_SEED);
         Exponentiation {
-            p: BigUint::from(10009u32),
-            q: BigUint::from(5004u32),
-            g: BigUint::from(3u32),
-            h: BigUint::from(2892u32),
+            p,
+            q,
+            g,
+            h,
+            seed: Some(DEFAULT_GENERATOR_SEED.to_string()),
         }
     }
+
+    // Deterministically derive the second generator `h` from the group description (p, q, g) and a
+    // public seed. The hash output is reduced mod p and raised to the cofactor (p-1)/q, landing in
+    // the order q subgroup; a counter is bumped in the unlikely case the result is 0, 1 or g.
+    // Since `h` is a hash output, nobody can know log_g(h) without solving a discrete logarithm.
+    pub fn derive_h(p: &BigUint, q: &BigUint, g: &BigUint, seed: &str) -> BigUint {
+        let cofactor = (p - 1u32) / q;
+        // 128 extra bits make the reduction mod p statistically close to uniform
+        let length = (p.bits() as usize + 128).div_ceil(8);
+        let (p_bytes, q_bytes, g_bytes) = (p.to_bytes_be(), q.to_bytes_be(), g.to_bytes_be());
+        (0u32..)
+            .map(|counter| {
+                let input = length_prefixed(&[
+                    &p_bytes,
+                    &q_bytes,
+                    &g_bytes,
+                    seed.as_bytes(),
+                    &counter.to_be_bytes(),
+                ]);
+                let x = BigUint::from_bytes_be(&expand_hash(H_DERIVATION_DOMAIN, &input, length));
+                (x % p).modpow(&cofactor, p)
+            })
+            .find(|h| !h.is_zero() && !h.is_one() && h != g)
+            .expect("The counter space is never exhausted")
+    }
+
+    // A short identifier of the group, the SHA-256 hash of its description in hexadecimal, which a
+    // client can pin to refuse any other group a server advertises.
+    pub fn fingerprint(&self) -> String {
+        hex::encode(Sha256::digest(self.description()))
+    }
+}
+
+impl Default for Exponentiation {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // Unit and property-based tests for the `Exponentiation` authentication mechanism.
+#[cfg(test)]
 mod tests {
-    use {super::*, proptest::prelude::*};
+    use {
+        super::*,
+        crate::authentication::{common::generate_random_string_of_length, Authenticate},
+        proptest::prelude::*,
+        test_case::test_case,
+    };
 
     // Test to ensure `g` is a valid generator of the cyclic group.
-    #[test]
-    fn g_should_be_a_generator_of_prime_order() {
-        let e = Exponentiation::new();
+    #[test_case(GroupId::Modp2048; "when group is modp2048")]
+    #[test_case(GroupId::Modp3072; "when group is modp3072")]
+    #[test_case(GroupId::Modp4096; "when group is modp4096")]
+    #[test_case(GroupId::Rfc5114Modp2048Q224; "when group is rfc5114 2048 224")]
+    #[test_case(GroupId::Rfc5114Modp2048Q256; "when group is rfc5114 2048 256")]
+    fn g_should_be_a_generator_of_prime_order(group: GroupId) {
+        let e = Exponentiation::with_group(group);
         let should_be_one = e.g.modpow(&e.q, &e.p);
         assert_eq!(
             should_be_one,
@@ -107,40 +216,135 @@ mod tests {
         );
     }
 
-    // Similar test for `h`, ensuring it's also a valid generator.
-    #[test]
-    fn h_should_be_a_generator_of_prime_order() {
-        let e = Exponentiation::new();
+    // Similar test for `h`, ensuring it's also a valid generator distinct from `g`.
+    #[test_case(GroupId::Modp2048; "when group is modp2048")]
+    #[test_case(GroupId::Modp3072; "when group is modp3072")]
+    #[test_case(GroupId::Modp4096; "when group is modp4096")]
+    #[test_case(GroupId::Rfc5114Modp2048Q224; "when group is rfc5114 2048 224")]
+    #[test_case(GroupId::Rfc5114Modp2048Q256; "when group is rfc5114 2048 256")]
+    fn h_should_be_a_generator_of_prime_order(group: GroupId) {
+        let e = Exponentiation::with_group(group);
         let should_be_one = e.h.modpow(&e.q, &e.p);
         assert_eq!(
             should_be_one,
             BigUint::from(1u32),
             "h is not a generator of the group"
         );
+        assert_ne!(e.g, e.h, "g and h must be distinct generators");
+    }
+
+    // `h` must be reproducible from the public seed, and change with the seed and the group.
+    #[test]
+    fn h_should_be_derived_from_the_seed() {
+        let e = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256);
+        let seed = e.seed.as_deref().unwrap();
+        assert_eq!(Exponentiation::derive_h(&e.p, &e.q, &e.g, seed), e.h);
+        assert_ne!(
+            Exponentiation::derive_h(&e.p, &e.q, &e.g, "another seed"),
+            e.h
+        );
+        let other = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q224);
+        assert_ne!(other.h, e.h, "groups sharing a seed must not share h");
+    }
+
+    // Fingerprints must be stable for a group and differ between groups.
+    #[test]
+    fn fingerprints_should_identify_the_group() {
+        let e = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256);
+        assert_eq!(e.fingerprint(), e.clone().fingerprint());
+        assert_eq!(e.fingerprint().len(), 64);
+        let other = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q224);
+        assert_ne!(other.fingerprint(), e.fingerprint());
+    }
+
+    // A single full authentication run against each of the standardised groups.
+    #[test_case(GroupId::Modp2048; "when group is modp2048")]
+    #[test_case(GroupId::Modp3072; "when group is modp3072")]
+    #[test_case(GroupId::Modp4096; "when group is modp4096")]
+    #[test_case(GroupId::Rfc5114Modp2048Q224; "when group is rfc5114 2048 224")]
+    #[test_case(GroupId::Rfc5114Modp2048Q256; "when group is rfc5114 2048 256")]
+    fn test_full_authentication_for_group(group: GroupId) {
+        let e = Exponentiation::with_group(group);
+        let secret = e.secret(b"correct horse battery staple");
+        let (y1, y2) = e.registration(&secret).unwrap();
+        let k = e.get_random();
+        let (r1, r2) = e.authentication(&k).unwrap();
+        let c = e.challenge();
+        let s = e.response(&k, &secret, &c).unwrap();
+        assert!(
+            e.verify(&y1, &y2, &r1, &r2, &s, &c).unwrap(),
+            "Authentication should have been successful for {}",
+            group
+        );
+    }
+
+    // Encodings are fixed length and only accept values below the modulus.
+    #[test]
+    fn encodings_should_be_canonical() {
+        let e = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256);
+        let one = BigUint::one();
+        let encoded = e.encode_element(&one);
+        assert_eq!(
+            encoded.len(),
+            256,
+            "elements should be padded to the size of p"
+        );
+        assert_eq!(e.decode_element(&encoded).unwrap(), one);
+        assert!(
+            e.decode_element(&[1u8]).is_err(),
+            "short encodings must be rejected"
+        );
+        assert!(e.decode_element(&encode_fixed(&e.p, &e.p)).is_err());
+        assert_eq!(e.encode_scalar(&one).len(), 32);
+        assert!(e.decode_scalar(&encode_fixed(&e.q, &e.q)).is_err());
+    }
+
+    // Zero, the identity, elements of order two and non-residues must all be refused, for the safe
+    // prime groups as well as the groups with a large cofactor.
+    #[test_case(GroupId::Modp2048; "when group is modp2048")]
+    #[test_case(GroupId::Rfc5114Modp2048Q256; "when group is rfc5114 2048 256")]
+    fn elements_outside_the_subgroup_should_be_rejected(group: GroupId) {
+        let e = Exponentiation::with_group(group);
+        assert!(e.validate_element(&e.g).is_ok());
+        assert!(e.validate_element(&e.h).is_ok());
+        let order_two = &e.p - 1u32;
+        let non_residue = (2u32..)
+            .map(BigUint::from)
+            .find(|x| !e.exp(x, &e.q).is_one())
+            .unwrap();
+        for invalid in [BigUint::zero(), BigUint::one(), order_two, non_residue] {
+            assert!(
+                e.validate_element(&invalid).is_err(),
+                "{} must not be accepted as a group element",
+                invalid
+            );
+            assert!(e.check_element(&e.encode_element(&invalid)).is_err());
+        }
     }
 
-    // Define a strategy for generating random `BigUint` values for testing.
-    fn password_as_biguint_strategy() -> impl Strategy<Value = BigUint> {
+    // Define a strategy for generating random passwords for testing.
+    fn password_strategy() -> impl Strategy<Value = Vec<u8>> {
         (1_0u32..=2_0)
             .prop_map(|n| n as usize)
-            .prop_map(|n| generate_random_string_of_length(n))
-            .prop_map(|s| BigUint::from_bytes_be(s.trim().as_bytes()))
+            .prop_map(generate_random_string_of_length)
+            .prop_map(|s| s.trim().as_bytes().to_vec())
     }
 
     // Property-based test to verify the full authentication process.
     proptest! {
         #![proptest_config(ProptestConfig::with_cases(1000))]
         #[test]
-        fn test_full_authentication(secret in password_as_biguint_strategy()) {
-            let e = Exponentiation::new();
-            let (y1, y2) = e.registration(&secret);
+        fn test_full_authentication(password in password_strategy()) {
+            let e = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256);
+            let secret = e.secret(&password);
+            let (y1, y2) = e.registration(&secret).unwrap();
             let  k = e.get_random();
-            let (r1,r2) = e.authentication(&k);
+            let (r1,r2) = e.authentication(&k).unwrap();
             let c = e.challenge();
-            let s = e.response(&k,&secret,&c);
-            let auth = e.verify(&y1,&y2,&r1,&r2,&s,&c);
+            let s = e.response(&k,&secret,&c).unwrap();
+            let auth = e.verify(&y1,&y2,&r1,&r2,&s,&c).unwrap();
 
-            prop_assert!(&auth, "Authentication should have been successful: secret:{}, y1:{}, y2:{}, r1:{}, r2:{}, k:{}, s:{}, c:{}", &secret,&y1,&y2,&r1,&r2,&k,&s,&c);
+            prop_assert!(&auth, "Authentication should have been successful: secret:{:?}, y1:{:?}, y2:{:?}, r1:{:?}, r2:{:?}, k:{:?}, s:{:?}, c:{:?}", &secret,&y1,&y2,&r1,&r2,&k,&s,&c);
         }
     }
 }
@@ -0,0 +1,510 @@
+use super::primes::{is_probable_prime, miller_rabin, small_primes, small_remainder};
+use super::Exponentiation;
+use crate::errors::ParameterError;
+use crate::zkp_auth::GroupParameters;
+use num_bigint::{BigUint, RandBigInt};
+use num_traits::{Num, One, Zero};
+use rand::{rngs::ThreadRng, thread_rng};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+// Smallest subgroup order the generator will produce, anything smaller has no practical use.
+const MIN_SUBGROUP_BITS: u64 = 16;
+
+// How many candidates for p are tried, per bit of p, before a new q is picked. Some q have no
+// prime p = kq + 1 of the right size, and when q is nearly as large as p there are few k to try.
+const CANDIDATES_PER_SUBGROUP_BIT: u64 = 8;
+
+// Smallest modulus accepted when validating parameters for use by the server or client.
+pub const MIN_MODULUS_BITS: u64 = 2048;
+
+// Smallest subgroup order accepted when validating parameters, matching RFC 5114 section 2.2.
+pub const MIN_ORDER_BITS: u64 = 224;
+
+// On disk representation of a group, with every parameter written as a hexadecimal string.
+#[derive(Debug, Serialize, Deserialize)]
+struct ParameterFile {
+    p: String,
+    q: String,
+    g: String,
+    h: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<String>,
+}
+
+// Generate a fresh group with a `bits` sized modulus `p` and two generators `g` and `h` of the
+// prime order `q` subgroup. Without `subgroup_bits` the modulus is a safe prime p = 2q + 1,
+// otherwise q has `subgroup_bits` bits and p = kq + 1 for an even k. The generator `h` is derived
+// from the group and the public `seed`.
+pub fn generate_parameters(
+    bits: u64,
+    subgroup_bits: Option<u64>,
+    seed: &str,
+) -> Result<Exponentiation, ParameterError> {
+    let q_bits = subgroup_bits.unwrap_or(bits.saturating_sub(1));
+    if q_bits < MIN_SUBGROUP_BITS {
+        return Err(ParameterError::InvalidSize(format!(
+            "the subgroup order must have at least {} bits",
+            MIN_SUBGROUP_BITS
+        )));
+    }
+    if q_bits >= bits {
+        return Err(ParameterError::InvalidSize(
+            "the subgroup order must be smaller than the modulus".to_string(),
+        ));
+    }
+    // With a q one bit smaller than p the only candidate is the safe prime 2q + 1, which is what
+    // leaving the subgroup size out generates
+    if subgroup_bits.is_some() && q_bits + 1 >= bits {
+        return Err(ParameterError::InvalidSize(
+            "the subgroup order must be at least two bits smaller than the modulus, leave it out for a safe prime".to_string(),
+        ));
+    }
+
+    let mut rng = thread_rng();
+    let (p, q) = match subgroup_bits {
+        None => generate_safe_prime(&mut rng, bits),
+        Some(q_bits) => generate_schnorr_prime(&mut rng, bits, q_bits),
+    };
+
+    // Hashing `h` out of the group and seed means nobody learns the discrete logarithm of `h` to
+    // the base `g`, and anyone can check this by deriving it again
+    let g = random_subgroup_element(&mut rng, &p, &q);
+    let h = Exponentiation::derive_h(&p, &q, &g, seed);
+    Ok(Exponentiation {
+        p,
+        q,
+        g,
+        h,
+        seed: Some(seed.to_string()),
+    })
+}
+
+// Check that a modulus and subgroup order of the given sizes would pass the default validation.
+pub fn check_minimum_sizes(modulus_bits: u64, order_bits: u64) -> Result<(), ParameterError> {
+    check_sizes(modulus_bits, order_bits, MIN_MODULUS_BITS, MIN_ORDER_BITS)
+}
+
+// Compare the sizes of p and q against the given minimums.
+fn check_sizes(
+    modulus_bits: u64,
+    order_bits: u64,
+    min_modulus_bits: u64,
+    min_order_bits: u64,
+) -> Result<(), ParameterError> {
+    for (parameter, bits, minimum) in [
+        ("p", modulus_bits, min_modulus_bits),
+        ("q", order_bits, min_order_bits),
+    ] {
+        if bits < minimum {
+            return Err(ParameterError::TooSmall {
+                parameter,
+                bits,
+                minimum,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Search for a safe prime p = 2q + 1, sieving both q and p by the small primes before running
+// the (comparatively expensive) Miller-Rabin tests.
+fn generate_safe_prime(rng: &mut ThreadRng, bits: u64) -> (BigUint, BigUint) {
+    loop {
+        let q = random_odd_with_bits(rng, bits - 1);
+        // r | 2q + 1 exactly when q = (r - 1) / 2 mod r
+        let has_small_factor = small_primes().iter().any(|&r| {
+            let rem = small_remainder(&q, r);
+            rem == 0 || rem == (r - 1) / 2
+        });
+        if has_small_factor {
+            continue;
+        }
+        let p = (&q << 1) + 1u32;
+        if miller_rabin(&q, 1)
+            && miller_rabin(&p, 1)
+            && is_probable_prime(&q)
+            && is_probable_prime(&p)
+        {
+            return (p, q);
+        }
+    }
+}
+
+// Pick a random prime q with `q_bits` bits and search for a prime p = kq + 1 with `bits` bits,
+// picking another q when none is found among a bounded number of candidates.
+fn generate_schnorr_prime(rng: &mut ThreadRng, bits: u64, q_bits: u64) -> (BigUint, BigUint) {
+    loop {
+        let q = loop {
+            let q = random_odd_with_bits(rng, q_bits);
+            if is_probable_prime(&q) {
+                break q;
+            }
+        };
+        let two_q = &q << 1;
+        for _ in 0..CANDIDATES_PER_SUBGROUP_BIT * bits {
+            // Round a random `bits` sized number down to the nearest value that is 1 mod 2q
+            let x = random_odd_with_bits(rng, bits);
+            let p: BigUint = &x - (&x % &two_q) + 1u32;
+            if p.bits() == bits && is_probable_prime(&p) {
+                return (p, q);
+            }
+        }
+    }
+}
+
+// A random odd number with exactly `bits` bits.
+fn random_odd_with_bits(rng: &mut ThreadRng, bits: u64) -> BigUint {
+    let mut n = rng.gen_biguint(bits);
+    n.set_bit(bits - 1, true);
+    n.set_bit(0, true);
+    n
+}
+
+// Raise a random base to the cofactor (p-1)/q, giving an element of order q unless it is 1.
+fn random_subgroup_element(rng: &mut ThreadRng, p: &BigUint, q: &BigUint) -> BigUint {
+    let cofactor = (p - 1u32) / q;
+    let upper = p - 1u32;
+    loop {
+        let base = rng.gen_biguint_range(&BigUint::from(2u32), &upper);
+        let element = base.modpow(&cofactor, p);
+        if !element.is_one() {
+            return element;
+        }
+    }
+}
+
+// Parse a hexadecimal parameter, accepting an optional `0x` prefix.
+fn parse_hex(name: &str, value: &str) -> Result<BigUint, ParameterError> {
+    let digits = value.trim().trim_start_matches("0x");
+    BigUint::from_str_radix(digits, 16)
+        .map_err(|e| ParameterError::Parse(format!("{} is not valid hexadecimal: {}", name, e)))
+}
+
+impl Exponentiation {
+    // Validate the parameters against the default minimum sizes.
+    pub fn validate(&self) -> Result<(), ParameterError> {
+        self.validate_with_minimums(MIN_MODULUS_BITS, MIN_ORDER_BITS)
+    }
+
+    // Check that p and q are large enough primes with q | p - 1, and that g and h are distinct
+    // non-trivial elements of the order q subgroup. Cheap checks run before the primality tests.
+    pub fn validate_with_minimums(
+        &self,
+        min_modulus_bits: u64,
+        min_order_bits: u64,
+    ) -> Result<(), ParameterError> {
+        check_sizes(
+            self.p.bits(),
+            self.q.bits(),
+            min_modulus_bits,
+            min_order_bits,
+        )?;
+
+        if self.q.is_zero() || !((&self.p - 1u32) % &self.q).is_zero() {
+            return Err(ParameterError::SubgroupOrderDoesNotDivide);
+        }
+
+        for (parameter, generator) in [("g", &self.g), ("h", &self.h)] {
+            let in_range = !generator.is_zero() && !generator.is_one() && generator < &self.p;
+            if !in_range || !generator.modpow(&self.q, &self.p).is_one() {
+                return Err(ParameterError::InvalidGenerator { parameter });
+            }
+        }
+        if self.g == self.h {
+            return Err(ParameterError::InvalidGenerator { parameter: "h" });
+        }
+        if let Some(seed) = &self.seed {
+            if Self::derive_h(&self.p, &self.q, &self.g, seed) != self.h {
+                return Err(ParameterError::GeneratorNotDerived { seed: seed.clone() });
+            }
+        }
+
+        for (parameter, value) in [("q", &self.q), ("p", &self.p)] {
+            if !is_probable_prime(value) {
+                return Err(ParameterError::NotPrime { parameter });
+            }
+        }
+        Ok(())
+    }
+
+    // Serialise the group parameters as TOML.
+    pub fn to_toml(&self) -> String {
+        let file = ParameterFile {
+            p: self.p.to_str_radix(16),
+            q: self.q.to_str_radix(16),
+            g: self.g.to_str_radix(16),
+            h: self.h.to_str_radix(16),
+            seed: self.seed.clone(),
+        };
+        toml::to_string(&file).expect("Parameter files always serialise")
+    }
+
+    // Parse group parameters from TOML produced by `to_toml`.
+    pub fn from_toml(contents: &str) -> Result<Self, ParameterError> {
+        let file: ParameterFile =
+            toml::from_str(contents).map_err(|e| ParameterError::Parse(e.to_string()))?;
+        Ok(Exponentiation {
+            p: parse_hex("p", &file.p)?,
+            q: parse_hex("q", &file.q)?,
+            g: parse_hex("g", &file.g)?,
+            h: parse_hex("h", &file.h)?,
+            seed: file.seed,
+        })
+    }
+
+    // Load group parameters from a TOML file.
+    pub fn from_file(path: &Path) -> Result<Self, ParameterError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    // Write the group parameters to a TOML file.
+    pub fn to_file(&self, path: &Path) -> Result<(), ParameterError> {
+        fs::write(path, self.to_toml())?;
+        Ok(())
+    }
+}
+
+// Convert the group into the parameters the server advertises to clients.
+impl From<&Exponentiation> for GroupParameters {
+    fn from(exponentiation: &Exponentiation) -> Self {
+        GroupParameters {
+            p: exponentiation.p.to_bytes_be(),
+            q: exponentiation.q.to_bytes_be(),
+            g: exponentiation.g.to_bytes_be(),
+            h: exponentiation.h.to_bytes_be(),
+            seed: exponentiation.seed.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// Convert advertised parameters back into a group, without validating them.
+impl From<GroupParameters> for Exponentiation {
+    fn from(parameters: GroupParameters) -> Self {
+        Exponentiation {
+            p: BigUint::from_bytes_be(&parameters.p),
+            q: BigUint::from_bytes_be(&parameters.q),
+            g: BigUint::from_bytes_be(&parameters.g),
+            h: BigUint::from_bytes_be(&parameters.h),
+            seed: Some(parameters.seed).filter(|seed| !seed.is_empty()),
+        }
+    }
+}
+
+// Unit tests for parameter generation and the parameter file format.
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::authentication::{
+            common::DEFAULT_GENERATOR_SEED, exponentiation::GroupId, Authenticate,
+        },
+        test_case::test_case,
+    };
+
+    // Generated groups must have prime p and q, q | p - 1 and generators of order q.
+    #[test_case(128, None; "when generating a safe prime group")]
+    #[test_case(512, Some(160); "when generating a schnorr group")]
+    #[test_case(128, Some(126); "when the subgroup leaves few multipliers")]
+    fn generated_parameters_should_form_a_prime_order_subgroup(
+        bits: u64,
+        subgroup_bits: Option<u64>,
+    ) {
+        let e = generate_parameters(bits, subgroup_bits, DEFAULT_GENERATOR_SEED).unwrap();
+        let one = BigUint::one();
+        assert_eq!(e.p.bits(), bits, "p has the wrong size");
+        assert_eq!(
+            e.q.bits(),
+            subgroup_bits.unwrap_or(bits - 1),
+            "q has the wrong size"
+        );
+        assert!(is_probable_prime(&e.p), "p is not prime");
+        assert!(is_probable_prime(&e.q), "q is not prime");
+        assert_eq!(
+            (&e.p - 1u32) % &e.q,
+            BigUint::default(),
+            "q does not divide p-1"
+        );
+        assert!(
+            e.g != one && e.g.modpow(&e.q, &e.p) == one,
+            "g is not of order q"
+        );
+        assert!(
+            e.h != one && e.h.modpow(&e.q, &e.p) == one,
+            "h is not of order q"
+        );
+        assert_ne!(e.g, e.h, "g and h must be distinct");
+    }
+
+    // A full authentication run must succeed on a freshly generated group.
+    #[test]
+    fn generated_parameters_should_authenticate() {
+        let e = generate_parameters(256, Some(64), DEFAULT_GENERATOR_SEED).unwrap();
+        let secret = e.secret(b"correct horse battery staple");
+        let (y1, y2) = e.registration(&secret).unwrap();
+        let k = e.get_random();
+        let (r1, r2) = e.authentication(&k).unwrap();
+        let c = e.challenge();
+        let s = e.response(&k, &secret, &c).unwrap();
+        assert!(e.verify(&y1, &y2, &r1, &r2, &s, &c).unwrap());
+    }
+
+    // Impossible sizes are rejected rather than looping forever.
+    #[test_case(2048, Some(2048); "when subgroup is as large as the modulus")]
+    #[test_case(2048, Some(2047); "when subgroup only allows a safe prime")]
+    #[test_case(2048, Some(8); "when subgroup is too small")]
+    #[test_case(8, None; "when modulus is too small")]
+    fn invalid_sizes_should_be_rejected(bits: u64, subgroup_bits: Option<u64>) {
+        assert!(matches!(
+            generate_parameters(bits, subgroup_bits, DEFAULT_GENERATOR_SEED),
+            Err(ParameterError::InvalidSize(_))
+        ));
+    }
+
+    // Every standardised group must pass validation with the default minimums.
+    #[test_case(GroupId::Modp2048; "when group is modp2048")]
+    #[test_case(GroupId::Modp3072; "when group is modp3072")]
+    #[test_case(GroupId::Modp4096; "when group is modp4096")]
+    #[test_case(GroupId::Rfc5114Modp2048Q224; "when group is rfc5114 2048 224")]
+    #[test_case(GroupId::Rfc5114Modp2048Q256; "when group is rfc5114 2048 256")]
+    fn standardised_groups_should_be_valid(group: GroupId) {
+        let validation = Exponentiation::with_group(group).validate();
+        assert!(validation.is_ok(), "{} failed with {:?}", group, validation);
+    }
+
+    // Generated groups must pass validation with minimums matching their size.
+    #[test]
+    fn generated_parameters_should_be_valid() {
+        let e = generate_parameters(512, Some(160), DEFAULT_GENERATOR_SEED).unwrap();
+        assert!(e.validate_with_minimums(512, 160).is_ok());
+    }
+
+    // Advertised parameters must convert back into the same group, with or without a seed.
+    #[test]
+    fn advertised_parameters_should_round_trip() {
+        for group in [
+            Exponentiation::with_group(GroupId::Rfc5114Modp2048Q224),
+            small_group(),
+        ] {
+            let advertised = Exponentiation::from(GroupParameters::from(&group));
+            assert_eq!(advertised.to_toml(), group.to_toml());
+            assert_eq!(advertised.fingerprint(), group.fingerprint());
+        }
+    }
+
+    // Build a small valid group and break one property at a time.
+    fn small_group() -> Exponentiation {
+        // p = 2q + 1 with q = 1019, and 4 = 2^2 and 9 = 3^2 are squares of order q
+        Exponentiation {
+            p: BigUint::from(2039u32),
+            q: BigUint::from(1019u32),
+            g: BigUint::from(4u32),
+            h: BigUint::from(9u32),
+            seed: None,
+        }
+    }
+
+    // Each misconfiguration is reported with the matching structured error.
+    #[test]
+    fn invalid_parameters_should_be_rejected() {
+        assert!(small_group().validate_with_minimums(11, 10).is_ok());
+
+        assert!(matches!(
+            small_group().validate(),
+            Err(ParameterError::TooSmall { parameter: "p", .. })
+        ));
+        assert!(matches!(
+            small_group().validate_with_minimums(11, 11),
+            Err(ParameterError::TooSmall { parameter: "q", .. })
+        ));
+
+        // The original hardcoded group, where q = 5004 is neither prime nor the order of g
+        let original = Exponentiation {
+            p: BigUint::from(10009u32),
+            q: BigUint::from(5004u32),
+            g: BigUint::from(3u32),
+            h: BigUint::from(2892u32),
+            seed: None,
+        };
+        assert!(matches!(
+            original.validate_with_minimums(1, 1),
+            Err(ParameterError::NotPrime { parameter: "q" })
+        ));
+
+        let mut not_dividing = small_group();
+        not_dividing.q = BigUint::from(1021u32);
+        assert!(matches!(
+            not_dividing.validate_with_minimums(1, 1),
+            Err(ParameterError::SubgroupOrderDoesNotDivide)
+        ));
+
+        // 341 = 11 * 31 is composite, yet 4 and 16 have order 5 and 5 divides 340
+        let composite_p = Exponentiation {
+            p: BigUint::from(341u32),
+            q: BigUint::from(5u32),
+            g: BigUint::from(4u32),
+            h: BigUint::from(16u32),
+            seed: None,
+        };
+        assert!(matches!(
+            composite_p.validate_with_minimums(1, 1),
+            Err(ParameterError::NotPrime { parameter: "p" })
+        ));
+
+        for (g, h, parameter) in [
+            (1u32, 9u32, "g"),
+            (4, 1, "h"),
+            (2038, 9, "g"),
+            (4, 2039, "h"),
+            (4, 4, "h"),
+        ] {
+            let mut bad = small_group();
+            bad.g = BigUint::from(g);
+            bad.h = BigUint::from(h);
+            assert!(
+                matches!(
+                    bad.validate_with_minimums(1, 1),
+                    Err(ParameterError::InvalidGenerator { parameter: p }) if p == parameter
+                ),
+                "g = {}, h = {} should be rejected",
+                g,
+                h
+            );
+        }
+    }
+
+    // A file whose `h` was not derived from its declared seed is rejected.
+    #[test]
+    fn generator_not_derived_from_seed_should_be_rejected() {
+        let mut e = generate_parameters(512, Some(160), DEFAULT_GENERATOR_SEED).unwrap();
+        e.seed = Some("a different seed".to_string());
+        assert!(matches!(
+            e.validate_with_minimums(512, 160),
+            Err(ParameterError::GeneratorNotDerived { .. })
+        ));
+        // Without a seed there is nothing to check the generator against
+        e.seed = None;
+        assert!(e.validate_with_minimums(512, 160).is_ok());
+    }
+
+    // Parameters must survive a round trip through the file format.
+    #[test]
+    fn parameters_should_round_trip_through_toml() {
+        let e = Exponentiation::new();
+        let parsed = Exponentiation::from_toml(&e.to_toml()).unwrap();
+        assert_eq!(
+            (parsed.p, parsed.q, parsed.g, parsed.h, parsed.seed),
+            (e.p, e.q, e.g, e.h, e.seed)
+        );
+    }
+
+    // Malformed files are reported as parse errors.
+    #[test_case("p = \"zz\"\nq = \"1\"\ng = \"1\"\nh = \"1\""; "when a value is not hexadecimal")]
+    #[test_case("p = \"17\"\nq = \"b\""; "when a value is missing")]
+    fn malformed_parameters_should_not_parse(contents: &str) {
+        assert!(matches!(
+            Exponentiation::from_toml(contents),
+            Err(ParameterError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,108 @@
+use num_bigint::{BigUint, RandBigInt};
+use num_traits::{One, Zero};
+use rand::thread_rng;
+use std::sync::OnceLock;
+
+// Number of Miller-Rabin rounds, giving an error probability below 2^-128 for random bases.
+const MILLER_RABIN_ROUNDS: usize = 64;
+
+// Upper bound for the small primes used in trial division before running Miller-Rabin.
+const SMALL_PRIME_BOUND: u32 = 2000;
+
+// Return the odd primes below `SMALL_PRIME_BOUND`, computed once with a sieve of Eratosthenes.
+pub(super) fn small_primes() -> &'static [u32] {
+    static PRIMES: OnceLock<Vec<u32>> = OnceLock::new();
+    PRIMES.get_or_init(|| {
+        let bound = SMALL_PRIME_BOUND as usize;
+        let mut composite = vec![false; bound];
+        let mut primes = Vec::new();
+        for n in 3..bound {
+            if !composite[n] && n % 2 == 1 {
+                primes.push(n as u32);
+                (n * n..bound).step_by(n).for_each(|m| composite[m] = true);
+            }
+        }
+        primes
+    })
+}
+
+// Remainder of `n` modulo a small prime.
+pub(super) fn small_remainder(n: &BigUint, prime: u32) -> u32 {
+    (n % prime).to_u32_digits().first().copied().unwrap_or(0)
+}
+
+// Check whether `n` is prime, using trial division by small primes followed by Miller-Rabin.
+pub fn is_probable_prime(n: &BigUint) -> bool {
+    let two = BigUint::from(2u32);
+    if n < &two {
+        return false;
+    }
+    if n == &two {
+        return true;
+    }
+    if (n % 2u32).is_zero() {
+        return false;
+    }
+    for &prime in small_primes() {
+        if n == &BigUint::from(prime) {
+            return true;
+        }
+        if small_remainder(n, prime) == 0 {
+            return false;
+        }
+    }
+    miller_rabin(n, MILLER_RABIN_ROUNDS)
+}
+
+// Run the Miller-Rabin test on an odd `n` larger than the small primes with random bases.
+pub(super) fn miller_rabin(n: &BigUint, rounds: usize) -> bool {
+    let one = BigUint::one();
+    let n_minus_one = n - 1u32;
+    // Write n - 1 as d * 2^r with d odd
+    let r = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> r;
+    let mut rng = thread_rng();
+    'witness: for _ in 0..rounds {
+        let a = rng.gen_biguint_range(&BigUint::from(2u32), &n_minus_one);
+        let mut x = a.modpow(&d, n);
+        if x == one || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..r {
+            x = x.modpow(&BigUint::from(2u32), n);
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// Unit tests for the primality checks.
+#[cfg(test)]
+mod tests {
+    use {super::*, test_case::test_case};
+
+    // Known primes, including the boundaries of the small primes and a Mersenne prime.
+    #[test_case("2"; "when prime is two")]
+    #[test_case("1999"; "when prime is a small prime")]
+    #[test_case("2003"; "when prime is just above the small primes")]
+    #[test_case("170141183460469231731687303715884105727"; "when prime is mersenne 127")]
+    fn known_primes_should_be_prime(n: &str) {
+        let n = BigUint::parse_bytes(n.as_bytes(), 10).unwrap();
+        assert!(is_probable_prime(&n), "{} should be prime", n);
+    }
+
+    // Known composites, including a Carmichael number and a product of two large primes.
+    #[test_case("0"; "when number is zero")]
+    #[test_case("1"; "when number is one")]
+    #[test_case("5004"; "when number is even")]
+    #[test_case("561"; "when number is a carmichael number")]
+    #[test_case("4028033"; "when number is a product of primes above the small primes")]
+    #[test_case("392318858461667547569595655490009919272404068553904357377"; "when number is a product of two large primes")]
+    fn known_composites_should_not_be_prime(n: &str) {
+        let n = BigUint::parse_bytes(n.as_bytes(), 10).unwrap();
+        assert!(!is_probable_prime(&n), "{} should not be prime", n);
+    }
+}
@@ -0,0 +1,204 @@
+use super::common::{expand_hash, length_prefixed};
+use crate::errors::AuthenticationError;
+use crate::zkp_auth::AuthenticationType;
+use std::fmt::Debug;
+
+// Number of bytes beyond the scalar size to derive or hash before reducing modulo the group order,
+// so that the reduction leaves a bias below 2^-128.
+pub const REDUCTION_EXTRA_BYTES: usize = 16;
+
+// Domain separation tag for hashing a transcript into a non-interactive challenge.
+const FIAT_SHAMIR_DOMAIN: &[u8] = b"adapted-chaum-pedersen/fiat-shamir/challenge";
+
+// Define a trait for a prime order group the Chaum-Pedersen protocol can run over. The protocol is
+// written multiplicatively, so for elliptic curves `exp` is scalar multiplication and `mul` is
+// point addition. Every element and scalar has a canonical byte encoding used on the wire.
+pub trait Group: Send + Sync {
+    // An element of the prime order group
+    type Element: Clone + PartialEq + Debug + Send + Sync;
+    // An integer modulo the group order
+    type Scalar: Clone + PartialEq + Debug + Send + Sync;
+
+    // Return the type of authentication this group provides
+    fn auth_type(&self) -> AuthenticationType;
+    // An unambiguous description of the group and its generators, bound into every proof
+    fn description(&self) -> Vec<u8>;
+    // The first generator of the group
+    fn g(&self) -> &Self::Element;
+    // The second generator of the group, whose discrete logarithm to the base g is unknown
+    fn h(&self) -> &Self::Element;
+
+    // Raise an element to the power of a scalar
+    fn exp(&self, base: &Self::Element, exponent: &Self::Scalar) -> Self::Element;
+    // Combine two elements with the group operation
+    fn mul(&self, a: &Self::Element, b: &Self::Element) -> Self::Element;
+
+    // Generate a uniformly random non-zero scalar
+    fn random_scalar(&self) -> Self::Scalar;
+    // Interpret arbitrary big endian bytes as an integer and map it into [1, q), by reducing it
+    // modulo q - 1 and adding one, so that a secret can never be zero
+    fn scalar_from_bytes(&self, bytes: &[u8]) -> Self::Scalar;
+    // The size in bytes of an encoded scalar
+    fn scalar_size(&self) -> usize;
+    // Multiply two scalars modulo the group order
+    fn scalar_mul(&self, a: &Self::Scalar, b: &Self::Scalar) -> Self::Scalar;
+    // Subtract two scalars modulo the group order
+    fn scalar_sub(&self, a: &Self::Scalar, b: &Self::Scalar) -> Self::Scalar;
+
+    // Encode an element in its canonical form
+    fn encode_element(&self, element: &Self::Element) -> Vec<u8>;
+    // Decode an element, rejecting anything that is not a canonical encoding
+    fn decode_element(&self, bytes: &[u8]) -> Result<Self::Element, AuthenticationError>;
+    // Check that a decoded element is a member of the prime order subgroup and not the identity
+    fn validate_element(&self, element: &Self::Element) -> Result<(), AuthenticationError>;
+    // Encode a scalar in its canonical form
+    fn encode_scalar(&self, scalar: &Self::Scalar) -> Vec<u8>;
+    // Decode a scalar, rejecting anything that is not a canonical encoding
+    fn decode_scalar(&self, bytes: &[u8]) -> Result<Self::Scalar, AuthenticationError>;
+}
+
+// Decode an element received from the other party, accepting only non-identity members of the
+// prime order subgroup.
+pub fn decode_valid_element<G: Group>(
+    group: &G,
+    bytes: &[u8],
+) -> Result<G::Element, AuthenticationError> {
+    let element = group.decode_element(bytes)?;
+    group.validate_element(&element)?;
+    Ok(element)
+}
+
+// Everything a non-interactive proof commits to: the user, their registered y1 and y2, the
+// commitments r1 and r2, and a nonce issued by the verifier so that proofs cannot be replayed. Every
+// element is in its canonical encoding.
+#[derive(Clone, Copy, Debug)]
+pub struct Transcript<'a> {
+    pub user: &'a str,
+    pub y1: &'a [u8],
+    pub y2: &'a [u8],
+    pub r1: &'a [u8],
+    pub r2: &'a [u8],
+    pub nonce: &'a [u8],
+}
+
+// The Fiat-Shamir challenge is c = H(group, user, y1, y2, r1, r2, nonce) reduced into [1, q).
+pub fn fiat_shamir_challenge<G: Group>(group: &G, transcript: &Transcript) -> G::Scalar {
+    let input = length_prefixed(&[
+        &group.description(),
+        transcript.user.as_bytes(),
+        transcript.y1,
+        transcript.y2,
+        transcript.r1,
+        transcript.r2,
+        transcript.nonce,
+    ]);
+    let length = group.scalar_size() + REDUCTION_EXTRA_BYTES;
+    group.scalar_from_bytes(&expand_hash(FIAT_SHAMIR_DOMAIN, &input, length))
+}
+
+// Registration computes y1 = g^x and y2 = h^x from the secret x.
+pub fn registration<G: Group>(group: &G, secret: &G::Scalar) -> (G::Element, G::Element) {
+    (group.exp(group.g(), secret), group.exp(group.h(), secret))
+}
+
+// The commitment to a nonce k is r1 = g^k and r2 = h^k.
+pub fn commitment<G: Group>(group: &G, nonce: &G::Scalar) -> (G::Element, G::Element) {
+    (group.exp(group.g(), nonce), group.exp(group.h(), nonce))
+}
+
+// The response to a challenge c is s = k - c * x mod q.
+pub fn response<G: Group>(
+    group: &G,
+    nonce: &G::Scalar,
+    secret: &G::Scalar,
+    challenge: &G::Scalar,
+) -> G::Scalar {
+    group.scalar_sub(nonce, &group.scalar_mul(challenge, secret))
+}
+
+// The verifier accepts when r1 = g^s * y1^c and r2 = h^s * y2^c.
+pub fn verify<G: Group>(
+    group: &G,
+    y1: &G::Element,
+    y2: &G::Element,
+    r1: &G::Element,
+    r2: &G::Element,
+    s: &G::Scalar,
+    c: &G::Scalar,
+) -> bool {
+    let ver1 = group.mul(&group.exp(group.g(), s), &group.exp(y1, c));
+    let ver2 = group.mul(&group.exp(group.h(), s), &group.exp(y2, c));
+    ver1 == *r1 && ver2 == *r2
+}
+
+// Unit tests for the generic protocol, run over every group.
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::authentication::{
+            ellipticcurve::EllipticCurve,
+            exponentiation::{Exponentiation, GroupId},
+        },
+    };
+
+    // Run the protocol with one secret at registration and another at authentication.
+    fn authenticate<G: Group>(group: &G, registered: &[u8], presented: &[u8]) -> bool {
+        let (y1, y2) = registration(group, &group.scalar_from_bytes(registered));
+        let k = group.random_scalar();
+        let (r1, r2) = commitment(group, &k);
+        let c = group.random_scalar();
+        let s = response(group, &k, &group.scalar_from_bytes(presented), &c);
+        verify(group, &y1, &y2, &r1, &r2, &s, &c)
+    }
+
+    // The challenge must be reproducible from the transcript and change with every part of it.
+    #[test]
+    fn fiat_shamir_challenge_should_bind_the_whole_transcript() {
+        let e = EllipticCurve::new();
+        let transcript = Transcript {
+            user: "alice",
+            y1: b"y1",
+            y2: b"y2",
+            r1: b"r1",
+            r2: b"r2",
+            nonce: b"nonce",
+        };
+        let c = fiat_shamir_challenge(&e, &transcript);
+        assert_eq!(c, fiat_shamir_challenge(&e, &transcript));
+        for changed in [
+            Transcript {
+                user: "bob",
+                ..transcript
+            },
+            Transcript {
+                y1: b"y2",
+                y2: b"y1",
+                ..transcript
+            },
+            Transcript {
+                r1: b"r1r2",
+                r2: b"",
+                ..transcript
+            },
+            Transcript {
+                nonce: b"other",
+                ..transcript
+            },
+        ] {
+            assert_ne!(c, fiat_shamir_challenge(&e, &changed));
+        }
+        let other_group = EllipticCurve::with_seed("another seed");
+        assert_ne!(c, fiat_shamir_challenge(&other_group, &transcript));
+    }
+
+    #[test]
+    fn protocol_should_verify_only_the_registered_secret() {
+        let e = EllipticCurve::new();
+        assert!(authenticate(&e, b"secret", b"secret"));
+        assert!(!authenticate(&e, b"secret", b"guess"));
+        let x = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256);
+        assert!(authenticate(&x, b"secret", b"secret"));
+        assert!(!authenticate(&x, b"secret", b"guess"));
+    }
+}
@@ -0,0 +1,357 @@
+use super::common::generate_random_bytes;
+use crate::errors::AuthenticationError;
+use crate::zkp_auth::{
+    kdf_parameters::Algorithm, Argon2idParameters, KdfParameters, Pbkdf2Parameters,
+    ScryptParameters,
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+// Size in bytes of the random per-user salt generated at registration.
+pub const SALT_SIZE: usize = 16;
+
+// Largest salt accepted, anything longer is not a salt this crate generated.
+const MAX_SALT_SIZE: usize = 64;
+
+// Bounds on the cost parameters. The minimums follow the OWASP password storage recommendations, so
+// the server refuses registrations that are cheap to attack offline, and the maximums stop a server
+// from making the client spend unbounded memory or time.
+const ARGON2_MEMORY_KIB: (u32, u32) = (19_456, 1_048_576);
+const ARGON2_ITERATIONS: (u32, u32) = (2, 64);
+const ARGON2_PARALLELISM: (u32, u32) = (1, 16);
+const SCRYPT_LOG_N: (u8, u8) = (17, 22);
+const SCRYPT_R: (u32, u32) = (8, 32);
+const SCRYPT_P: (u32, u32) = (1, 16);
+const PBKDF2_ROUNDS: (u32, u32) = (600_000, 10_000_000);
+
+// The key derivation functions a password can be stretched with, selected on the command line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum KdfAlgorithm {
+    #[default]
+    Argon2id,
+    Scrypt,
+    // PBKDF2 with HMAC-SHA256
+    Pbkdf2,
+}
+
+// A key derivation function together with its cost parameters.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum Kdf {
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    Scrypt {
+        log_n: u8,
+        r: u32,
+        p: u32,
+    },
+    Pbkdf2 {
+        rounds: u32,
+    },
+}
+
+impl Kdf {
+    // The recommended costs for the algorithm, which are also the minimum accepted costs.
+    pub fn recommended(algorithm: KdfAlgorithm) -> Self {
+        match algorithm {
+            KdfAlgorithm::Argon2id => Kdf::Argon2id {
+                memory_kib: ARGON2_MEMORY_KIB.0,
+                iterations: ARGON2_ITERATIONS.0,
+                parallelism: ARGON2_PARALLELISM.0,
+            },
+            KdfAlgorithm::Scrypt => Kdf::Scrypt {
+                log_n: SCRYPT_LOG_N.0,
+                r: SCRYPT_R.0,
+                p: SCRYPT_P.0,
+            },
+            KdfAlgorithm::Pbkdf2 => Kdf::Pbkdf2 {
+                rounds: PBKDF2_ROUNDS.0,
+            },
+        }
+    }
+
+    // Check that every cost parameter is within its bounds.
+    fn check_costs(&self) -> Result<(), AuthenticationError> {
+        match *self {
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                check_bounds("argon2id memory", memory_kib, ARGON2_MEMORY_KIB)?;
+                check_bounds("argon2id iterations", iterations, ARGON2_ITERATIONS)?;
+                check_bounds("argon2id parallelism", parallelism, ARGON2_PARALLELISM)
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                check_bounds("scrypt log_n", log_n, SCRYPT_LOG_N)?;
+                check_bounds("scrypt r", r, SCRYPT_R)?;
+                check_bounds("scrypt p", p, SCRYPT_P)
+            }
+            Kdf::Pbkdf2 { rounds } => check_bounds("pbkdf2 rounds", rounds, PBKDF2_ROUNDS),
+        }
+    }
+
+    // Stretch the password with the salt, filling the whole of the output.
+    fn derive_into(
+        &self,
+        password: &[u8],
+        salt: &[u8],
+        output: &mut [u8],
+    ) -> Result<(), AuthenticationError> {
+        match *self {
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params =
+                    argon2::Params::new(memory_kib, iterations, parallelism, Some(output.len()))
+                        .map_err(|e| AuthenticationError::InvalidKdfParameters(e.to_string()))?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(password, salt, output)
+                    .map_err(|e| AuthenticationError::KeyDerivationFailed(e.to_string()))
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(log_n, r, p, output.len())
+                    .map_err(|e| AuthenticationError::InvalidKdfParameters(e.to_string()))?;
+                scrypt::scrypt(password, salt, &params, output)
+                    .map_err(|e| AuthenticationError::KeyDerivationFailed(e.to_string()))
+            }
+            Kdf::Pbkdf2 { rounds } => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, rounds, output);
+                Ok(())
+            }
+        }
+    }
+}
+
+// Check that a cost parameter lies within its inclusive bounds.
+fn check_bounds<T: PartialOrd + std::fmt::Display>(
+    parameter: &str,
+    value: T,
+    (minimum, maximum): (T, T),
+) -> Result<(), AuthenticationError> {
+    if value < minimum || value > maximum {
+        return Err(AuthenticationError::InvalidKdfParameters(format!(
+            "{} is {} but must be between {} and {}",
+            parameter, value, minimum, maximum
+        )));
+    }
+    Ok(())
+}
+
+// The key derivation a user registered with: the function, its costs and the per-user salt. It is
+// stored alongside the registration and handed back to the client when it authenticates.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyDerivation {
+    pub kdf: Kdf,
+    #[serde(with = "hex::serde")]
+    pub salt: Vec<u8>,
+}
+
+impl KeyDerivation {
+    // Create a key derivation with the recommended costs and a fresh random salt.
+    pub fn new(algorithm: KdfAlgorithm) -> Self {
+        KeyDerivation {
+            kdf: Kdf::recommended(algorithm),
+            salt: generate_random_bytes(SALT_SIZE),
+        }
+    }
+
+    // The key derivation reported for a user who has not registered: the recommended costs of the
+    // default algorithm and a salt derived from the user and a server key, so that it looks like
+    // any registration and stays the same between requests.
+    pub fn decoy(user: &str, key: &[u8]) -> Self {
+        let digest = Sha256::new()
+            .chain_update(key)
+            .chain_update(user.as_bytes())
+            .finalize();
+        KeyDerivation {
+            kdf: Kdf::recommended(KdfAlgorithm::default()),
+            salt: digest[..SALT_SIZE].to_vec(),
+        }
+    }
+
+    // Check that the salt and costs are acceptable, rejecting derivations that are too weak to
+    // protect the registration or too expensive to run.
+    pub fn validate(&self) -> Result<(), AuthenticationError> {
+        if self.salt.len() < SALT_SIZE || self.salt.len() > MAX_SALT_SIZE {
+            return Err(AuthenticationError::InvalidKdfParameters(format!(
+                "salt has {} bytes but must have between {} and {}",
+                self.salt.len(),
+                SALT_SIZE,
+                MAX_SALT_SIZE
+            )));
+        }
+        self.kdf.check_costs()
+    }
+
+    // Derive `length` bytes of secret material from the password, wiped when dropped.
+    pub fn derive(
+        &self,
+        password: &[u8],
+        length: usize,
+    ) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        let mut output = Zeroizing::new(vec![0u8; length]);
+        self.kdf.derive_into(password, &self.salt, &mut output)?;
+        Ok(output)
+    }
+}
+
+// Convert the key derivation into its protobuf representation.
+impl From<&KeyDerivation> for KdfParameters {
+    fn from(derivation: &KeyDerivation) -> Self {
+        let algorithm = match derivation.kdf {
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => Algorithm::Argon2id(Argon2idParameters {
+                memory_kib,
+                iterations,
+                parallelism,
+            }),
+            Kdf::Scrypt { log_n, r, p } => Algorithm::Scrypt(ScryptParameters {
+                log_n: log_n.into(),
+                r,
+                p,
+            }),
+            Kdf::Pbkdf2 { rounds } => Algorithm::Pbkdf2(Pbkdf2Parameters { rounds }),
+        };
+        KdfParameters {
+            salt: derivation.salt.clone(),
+            algorithm: Some(algorithm),
+        }
+    }
+}
+
+// Convert the protobuf representation into a key derivation, without checking the costs.
+impl TryFrom<KdfParameters> for KeyDerivation {
+    type Error = AuthenticationError;
+
+    fn try_from(parameters: KdfParameters) -> Result<Self, Self::Error> {
+        let kdf = match parameters.algorithm {
+            Some(Algorithm::Argon2id(argon2id)) => Kdf::Argon2id {
+                memory_kib: argon2id.memory_kib,
+                iterations: argon2id.iterations,
+                parallelism: argon2id.parallelism,
+            },
+            Some(Algorithm::Scrypt(scrypt)) => Kdf::Scrypt {
+                log_n: u8::try_from(scrypt.log_n).map_err(|_| {
+                    AuthenticationError::InvalidKdfParameters(format!(
+                        "scrypt log_n {} is out of range",
+                        scrypt.log_n
+                    ))
+                })?,
+                r: scrypt.r,
+                p: scrypt.p,
+            },
+            Some(Algorithm::Pbkdf2(pbkdf2)) => Kdf::Pbkdf2 {
+                rounds: pbkdf2.rounds,
+            },
+            None => {
+                return Err(AuthenticationError::InvalidKdfParameters(
+                    "no key derivation function given".to_string(),
+                ))
+            }
+        };
+        Ok(KeyDerivation {
+            kdf,
+            salt: parameters.salt,
+        })
+    }
+}
+
+// Unit tests for the password key derivation.
+#[cfg(test)]
+mod tests {
+    use {super::*, test_case::test_case};
+
+    // Costs far below the minimums, so that the derivation itself can be tested quickly.
+    fn cheap(algorithm: KdfAlgorithm) -> KeyDerivation {
+        let kdf = match algorithm {
+            KdfAlgorithm::Argon2id => Kdf::Argon2id {
+                memory_kib: 64,
+                iterations: 1,
+                parallelism: 1,
+            },
+            KdfAlgorithm::Scrypt => Kdf::Scrypt {
+                log_n: 4,
+                r: 8,
+                p: 1,
+            },
+            KdfAlgorithm::Pbkdf2 => Kdf::Pbkdf2 { rounds: 10 },
+        };
+        KeyDerivation {
+            kdf,
+            ..KeyDerivation::new(algorithm)
+        }
+    }
+
+    // The same password and salt must always give the same material, and a different salt or
+    // password must not.
+    #[test_case(KdfAlgorithm::Argon2id; "when using argon2id")]
+    #[test_case(KdfAlgorithm::Scrypt; "when using scrypt")]
+    #[test_case(KdfAlgorithm::Pbkdf2; "when using pbkdf2")]
+    fn derivation_should_depend_on_password_and_salt(algorithm: KdfAlgorithm) {
+        let derivation = cheap(algorithm);
+        let material = derivation.derive(b"password", 48).unwrap();
+        assert_eq!(material.len(), 48);
+        assert_eq!(material, derivation.derive(b"password", 48).unwrap());
+        assert_ne!(material, derivation.derive(b"passw0rd", 48).unwrap());
+        let resalted = KeyDerivation {
+            salt: vec![0u8; SALT_SIZE],
+            ..derivation
+        };
+        assert_ne!(material, resalted.derive(b"password", 48).unwrap());
+    }
+
+    // Fresh derivations use the recommended costs and a random salt of the full size.
+    #[test_case(KdfAlgorithm::Argon2id; "when using argon2id")]
+    #[test_case(KdfAlgorithm::Scrypt; "when using scrypt")]
+    #[test_case(KdfAlgorithm::Pbkdf2; "when using pbkdf2")]
+    fn new_derivations_should_be_valid_and_salted(algorithm: KdfAlgorithm) {
+        let first = KeyDerivation::new(algorithm);
+        let second = KeyDerivation::new(algorithm);
+        assert!(first.validate().is_ok());
+        assert_eq!(first.salt.len(), SALT_SIZE);
+        assert_ne!(first.salt, second.salt, "Salts must be unique per user");
+    }
+
+    // Weak costs, absurd costs and bad salts must all be refused.
+    #[test_case(cheap(KdfAlgorithm::Argon2id); "when argon2id is too cheap")]
+    #[test_case(cheap(KdfAlgorithm::Scrypt); "when scrypt is too cheap")]
+    #[test_case(cheap(KdfAlgorithm::Pbkdf2); "when pbkdf2 is too cheap")]
+    #[test_case(KeyDerivation { kdf: Kdf::Argon2id { memory_kib: u32::MAX, iterations: 2, parallelism: 1 }, ..KeyDerivation::new(KdfAlgorithm::Argon2id) }; "when argon2id memory is too large")]
+    #[test_case(KeyDerivation { kdf: Kdf::Scrypt { log_n: 40, r: 8, p: 1 }, ..KeyDerivation::new(KdfAlgorithm::Scrypt) }; "when scrypt log_n is too large")]
+    #[test_case(KeyDerivation { salt: vec![1; 8], ..KeyDerivation::new(KdfAlgorithm::Argon2id) }; "when salt is too short")]
+    #[test_case(KeyDerivation { salt: vec![1; 65], ..KeyDerivation::new(KdfAlgorithm::Argon2id) }; "when salt is too long")]
+    fn invalid_derivations_should_be_rejected(derivation: KeyDerivation) {
+        assert!(derivation.validate().is_err());
+    }
+
+    // Every derivation must survive the round trip through its protobuf representation.
+    #[test_case(KdfAlgorithm::Argon2id; "when using argon2id")]
+    #[test_case(KdfAlgorithm::Scrypt; "when using scrypt")]
+    #[test_case(KdfAlgorithm::Pbkdf2; "when using pbkdf2")]
+    fn derivations_should_round_trip_through_protobuf(algorithm: KdfAlgorithm) {
+        let derivation = KeyDerivation::new(algorithm);
+        let parameters = KdfParameters::from(&derivation);
+        assert_eq!(KeyDerivation::try_from(parameters).unwrap(), derivation);
+    }
+
+    // Parameters without an algorithm cannot be used.
+    #[test]
+    fn parameters_without_an_algorithm_should_be_rejected() {
+        let parameters = KdfParameters {
+            salt: vec![0u8; SALT_SIZE],
+            algorithm: None,
+        };
+        assert!(KeyDerivation::try_from(parameters).is_err());
+    }
+}
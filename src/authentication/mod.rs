@@ -1,12 +1,19 @@
 pub mod common;
 pub mod ellipticcurve;
 pub mod exponentiation;
+pub mod group;
+pub mod kdf;
+use crate::errors::AuthenticationError;
 use crate::zkp_auth::AuthenticationType;
+use common::generate_random_string_of_length;
 use ellipticcurve::EllipticCurve;
 use exponentiation::Exponentiation;
-use num_bigint::BigUint;
+pub use group::{Group, Transcript};
+use zeroize::Zeroizing; // To wipe the secret scalar from memory once it has been used
 
-// Define a trait to encapsulate authentication behavior, ensuring it's compatible with asynchronous runtime
+// Define a trait to encapsulate authentication behavior, ensuring it's compatible with asynchronous runtime.
+// Every value crosses this trait in its canonical byte encoding, so that callers such as the client and
+// server never need to know which group is in use.
 pub trait Authenticate: Sync + Send {
     // Return the type of authentication being used
     fn auth_type(&self) -> AuthenticationType;
@@ -14,35 +21,157 @@ pub trait Authenticate: Sync + Send {
     fn auth_id(&self) -> String;
     // Generate a unique session identifier to link challenge and verification steps
     fn session_id(&self) -> String;
-    // Generate a random number
-    fn get_random(&self) -> BigUint;
+    // The number of bytes of secret material to derive, so that mapping it onto a scalar is uniform
+    fn secret_length(&self) -> usize;
+    // Map secret material, such as the output of a key derivation function, onto an encoded secret scalar,
+    // which is wiped from memory once it is dropped
+    fn secret(&self, material: &[u8]) -> Zeroizing<Vec<u8>>;
+    // Generate an encoded random scalar
+    fn get_random(&self) -> Vec<u8>;
     // Process for registration, taking the secret and returning two values for the registration request
-    fn registration(&self, secret: &BigUint) -> (BigUint, BigUint);
+    fn registration(&self, secret: &[u8]) -> Result<(Vec<u8>, Vec<u8>), AuthenticationError>;
     // Process for authentication, taking a nonce and  returning two values for the authentication
     // request
-    fn authentication(&self, nonce: &BigUint) -> (BigUint, BigUint);
+    fn authentication(&self, nonce: &[u8]) -> Result<(Vec<u8>, Vec<u8>), AuthenticationError>;
     // Generate a challenge for the client, part of the authentication process
-    fn challenge(&self) -> BigUint;
+    fn challenge(&self) -> Vec<u8>;
     // Generate a response to a challenge, using the nonce, secret, and challenge
-    fn response(&self, nonce: &BigUint, secret: &BigUint, challenge: &BigUint) -> BigUint;
+    fn response(
+        &self,
+        nonce: &[u8],
+        secret: &[u8],
+        challenge: &[u8],
+    ) -> Result<Vec<u8>, AuthenticationError>;
+    // Check that an encoded element is a valid, non-identity member of the prime order subgroup
+    fn check_element(&self, element: &[u8]) -> Result<(), AuthenticationError>;
     // Verify the response to a challenge, confirming authenticity
     fn verify(
         &self,
-        y1: &BigUint,
-        y2: &BigUint,
-        r1: &BigUint,
-        r2: &BigUint,
-        s: &BigUint,
-        c: &BigUint,
-    ) -> bool;
+        y1: &[u8],
+        y2: &[u8],
+        r1: &[u8],
+        r2: &[u8],
+        s: &[u8],
+        c: &[u8],
+    ) -> Result<bool, AuthenticationError>;
+    // Derive the challenge for a non-interactive proof by hashing the transcript, so that the
+    // prover can answer it without waiting for the verifier
+    fn proof_challenge(&self, transcript: &Transcript) -> Result<Vec<u8>, AuthenticationError>;
+    // Verify a non-interactive proof, recomputing its challenge from the transcript
+    fn verify_proof(&self, transcript: &Transcript, s: &[u8]) -> Result<bool, AuthenticationError>;
+}
+
+// Every group is an authenticator, decoding the wire values into typed elements and scalars, running
+// the protocol in the group and encoding the results again.
+impl<G: Group> Authenticate for G {
+    fn auth_type(&self) -> AuthenticationType {
+        Group::auth_type(self)
+    }
+
+    fn auth_id(&self) -> String {
+        generate_random_string_of_length(50)
+    }
+
+    fn session_id(&self) -> String {
+        generate_random_string_of_length(100)
+    }
+
+    // Derive extra bytes beyond the scalar size, making the bias of the reduction negligible
+    fn secret_length(&self) -> usize {
+        self.scalar_size() + group::REDUCTION_EXTRA_BYTES
+    }
+
+    fn secret(&self, material: &[u8]) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(self.encode_scalar(&self.scalar_from_bytes(material)))
+    }
+
+    fn get_random(&self) -> Vec<u8> {
+        self.encode_scalar(&self.random_scalar())
+    }
+
+    fn registration(&self, secret: &[u8]) -> Result<(Vec<u8>, Vec<u8>), AuthenticationError> {
+        let x = self.decode_scalar(secret)?;
+        let (y1, y2) = group::registration(self, &x);
+        Ok((self.encode_element(&y1), self.encode_element(&y2)))
+    }
+
+    fn authentication(&self, nonce: &[u8]) -> Result<(Vec<u8>, Vec<u8>), AuthenticationError> {
+        let k = self.decode_scalar(nonce)?;
+        let (r1, r2) = group::commitment(self, &k);
+        Ok((self.encode_element(&r1), self.encode_element(&r2)))
+    }
+
+    fn challenge(&self) -> Vec<u8> {
+        self.encode_scalar(&self.random_scalar())
+    }
+
+    fn response(
+        &self,
+        nonce: &[u8],
+        secret: &[u8],
+        challenge: &[u8],
+    ) -> Result<Vec<u8>, AuthenticationError> {
+        let k = self.decode_scalar(nonce)?;
+        let x = self.decode_scalar(secret)?;
+        let c = self.decode_scalar(challenge)?;
+        Ok(self.encode_scalar(&group::response(self, &k, &x, &c)))
+    }
+
+    fn check_element(&self, element: &[u8]) -> Result<(), AuthenticationError> {
+        group::decode_valid_element(self, element).map(|_| ())
+    }
+
+    fn verify(
+        &self,
+        y1: &[u8],
+        y2: &[u8],
+        r1: &[u8],
+        r2: &[u8],
+        s: &[u8],
+        c: &[u8],
+    ) -> Result<bool, AuthenticationError> {
+        Ok(group::verify(
+            self,
+            &group::decode_valid_element(self, y1)?,
+            &group::decode_valid_element(self, y2)?,
+            &group::decode_valid_element(self, r1)?,
+            &group::decode_valid_element(self, r2)?,
+            &self.decode_scalar(s)?,
+            &self.decode_scalar(c)?,
+        ))
+    }
+
+    fn proof_challenge(&self, transcript: &Transcript) -> Result<Vec<u8>, AuthenticationError> {
+        // Only valid elements are hashed, so a proof can never commit to something verify rejects
+        for element in [transcript.y1, transcript.y2, transcript.r1, transcript.r2] {
+            group::decode_valid_element(self, element)?;
+        }
+        Ok(self.encode_scalar(&group::fiat_shamir_challenge(self, transcript)))
+    }
+
+    fn verify_proof(&self, transcript: &Transcript, s: &[u8]) -> Result<bool, AuthenticationError> {
+        let c = self.proof_challenge(transcript)?;
+        self.verify(
+            transcript.y1,
+            transcript.y2,
+            transcript.r1,
+            transcript.r2,
+            s,
+            &c,
+        )
+    }
 }
 
-// Function to instantiate the appropriate authentication mechanism based on the given AuthenticationType
-pub fn get_authentication(authtype: AuthenticationType) -> Box<dyn Authenticate> {
+// Function to instantiate the appropriate authentication mechanism based on the given AuthenticationType,
+// the exponentiation group is only used by the exponentiation mechanism
+pub fn get_authentication(
+    authtype: AuthenticationType,
+    exponentiation: Exponentiation,
+) -> Box<dyn Authenticate> {
     match authtype {
         // Instantiate an EllipticCurve authenticator
         AuthenticationType::EllipticCurve => Box::new(EllipticCurve::new()),
         // Instantiate an Exponentiation authenticator
-        AuthenticationType::Exponentiation => Box::new(Exponentiation::new()),
+        AuthenticationType::Exponentiation => Box::new(exponentiation),
     }
 }
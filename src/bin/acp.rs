@@ -1,63 +1,393 @@
-use acp::cli::{Cli, Command};
-use acp::client::{ClientAuthenticator, ClientRegistrar};
+use acp::authentication::exponentiation::params::check_minimum_sizes;
+use acp::authentication::exponentiation::{generate_parameters, Exponentiation};
+use acp::cli::{Cli, ClientTlsArgs, Command, ParamsCommand, SessionCommand, TokenCommand};
+use acp::client::{
+    get_token_key, refresh_session, reuse_session, revoke_session, validate_session,
+    ClientAuthenticator, ClientRegistrar,
+};
+use acp::config::{LogConfig, LogFormat, LogLevel};
+use acp::credentials::Session;
+use acp::errors::CredentialsError;
 use acp::server::ServerState;
+use acp::storage::unix_now;
+use acp::token::{TokenSigner, TokenVerifier};
 use acp::zkp_auth::auth_client::AuthClient;
 use acp::zkp_auth::auth_server::AuthServer;
+use acp::zkp_auth::SessionResponse;
 use clap::Parser; // For command-line argument parsing
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinSet;
 use tonic::transport::Channel; // For gRPC channel management
 use tonic::transport::Server; // For gRPC server functionality
-use tracing::{debug, error, info}; // For logging
+use tracing::level_filters::LevelFilter;
+use tracing::{error, info, warn}; // For logging
 
-// Asynchronously connect to the authentication server and return a gRPC client
-async fn connect_to_server(server_address: &str) -> anyhow::Result<AuthClient<Channel>> {
+// Asynchronously connect to the authentication server, over TLS when a CA is given, and return a
+// gRPC client
+async fn connect_to_server(
+    server_address: &SocketAddr,
+    tls: &ClientTlsArgs,
+) -> anyhow::Result<AuthClient<Channel>> {
     info!("Auth server address is {}", server_address);
-    let target_with_scheme = format!("http://{}", server_address);
-    let channel = Channel::from_shared(target_with_scheme)?.connect().await?;
+    let endpoint = match tls.config(server_address)? {
+        Some(config) => {
+            Channel::from_shared(format!("https://{}", server_address))?.tls_config(config)?
+        }
+        None => Channel::from_shared(format!("http://{}", server_address))?,
+    };
+    let channel = endpoint.connect().await?;
     Ok(AuthClient::new(channel))
 }
 
+// Initialize logging for the server at the configured level and layout
+fn init_logging(log: &LogConfig) {
+    let level = match log.level {
+        LogLevel::Off => LevelFilter::OFF,
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    };
+    let subscriber = tracing_subscriber::fmt().with_max_level(level);
+    match log.format {
+        LogFormat::Full => subscriber.init(),
+        LogFormat::Compact => subscriber.compact().init(),
+        LogFormat::Pretty => subscriber.pretty().init(),
+    }
+}
+
+// Print the details of a session returned by the server
+fn print_session(session: &SessionResponse) {
+    println!("Session for user '{}'", session.user);
+    println!("Authenticated with {}", session.auth());
+    println!(
+        "Expires in {} seconds",
+        session.expires.saturating_sub(unix_now())
+    );
+}
+
+// Print a session the client started, with what is needed to use it
+fn print_client_session(session: &Session) {
+    println!("Session id {}", session.id);
+    println!("Session for user '{}'", session.user);
+    println!("Authenticated with {}", session.auth_type);
+    println!(
+        "Expires in {} seconds",
+        session.expires.saturating_sub(unix_now())
+    );
+    if let Some(token) = &session.token {
+        println!("Token {token}");
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init(); // Initialize logging
     let cli = Cli::parse();
+    // The server configures its own logging, every other command logs at the default level
+    if !matches!(cli.command, Command::Server(_)) {
+        tracing_subscriber::fmt::init(); // Initialize logging
+    }
 
     // Match against the command specified by the user
     match cli.command {
-        Command::Register(client_args) => {
+        Command::Register(register_args) => {
+            let client_args = register_args.client;
             let mut client: AuthClient<Channel> =
-                connect_to_server(&client_args.server_address.to_string()).await?; // Connect to the server
+                connect_to_server(&client_args.server_address, &client_args.tls).await?; // Connect to the server
 
-            let c = ClientRegistrar::new(&mut client).await?; // Create a new client registrar
+            let c = ClientRegistrar::new(
+                &mut client,
+                client_args.group.pinned_fingerprint()?.as_deref(),
+            )
+            .await?; // Create a new client registrar
 
             // Attempt to register the user
-            match c.register(&client_args.user, &mut client).await? {
+            match c
+                .register(
+                    &client_args.user,
+                    register_args.kdf,
+                    client_args.password.provider().as_ref(),
+                    &mut client,
+                )
+                .await?
+            {
                 true => info!("Successfully registered"),
                 false => error!("Registration failed"),
             }
         }
-        Command::Authenticate(client_args) => {
+        Command::Authenticate(authenticate_args) => {
+            let client_args = authenticate_args.client;
+            let mut client: AuthClient<Channel> =
+                connect_to_server(&client_args.server_address, &client_args.tls).await?; // Connect to the server
+
+            let server = client_args.server_address.to_string();
+            // Authenticate without the cache when there is nowhere to keep it, such as when HOME is
+            // not set for a service, rather than failing
+            let mut cache = match authenticate_args.no_session_cache {
+                true => None,
+                false => match authenticate_args.cache.open() {
+                    Ok(cache) => Some(cache),
+                    Err(e @ CredentialsError::NoConfigDirectory) => {
+                        warn!("{}, authenticating without the session cache", e);
+                        None
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+            };
+
+            // Reuse the cached session while the server accepts it, unless a new one is asked for
+            let cached = match cache.as_mut() {
+                Some(cache) if !authenticate_args.new_session => {
+                    reuse_session(&mut client, cache, &server, &client_args.user).await?
+                }
+                _ => None,
+            };
+            let authenticated = match cached {
+                Some(session) => session,
+                None => {
+                    let c = ClientAuthenticator::new(
+                        &mut client,
+                        &client_args.user,
+                        client_args.group.pinned_fingerprint()?.as_deref(),
+                    )
+                    .await?; // Create a new client authenticator
+
+                    // Attempt to authenticate the user, with a challenge from the server or without
+                    let password = client_args.password.provider();
+                    let session = if authenticate_args.non_interactive {
+                        c.authenticate_non_interactive(
+                            &client_args.user,
+                            password.as_ref(),
+                            &mut client,
+                        )
+                        .await?
+                    } else {
+                        c.authenticate(&client_args.user, password.as_ref(), &mut client)
+                            .await?
+                    };
+                    info!("Authentication successful");
+                    session
+                }
+            };
+            if let Some(cache) = &mut cache {
+                cache.insert(&server, authenticated.clone());
+                cache.save()?;
+                info!("Session cached in {}", cache.path().display());
+            }
+            if authenticate_args.print_session {
+                print_client_session(&authenticated);
+            }
+            if let Some(path) = &authenticate_args.credentials {
+                authenticated.write(path)?;
+                info!("Session written to {}", path.display());
+            }
+        }
+        Command::ChangePassword(change_password_args) => {
+            let client_args = change_password_args.client;
             let mut client: AuthClient<Channel> =
-                connect_to_server(&client_args.server_address.to_string()).await?; // Connect to the server
+                connect_to_server(&client_args.server_address, &client_args.tls).await?; // Connect to the server
 
-            let c = ClientAuthenticator::new(&mut client).await?; // Create a new client authenticator
+            let c = ClientAuthenticator::new(
+                &mut client,
+                &client_args.user,
+                client_args.group.pinned_fingerprint()?.as_deref(),
+            )
+            .await?; // Create a new client authenticator
 
-            // Attempt to authenticate the user
-            match c.authenticate(&client_args.user, &mut client).await? {
-                true => info!("Authentication successful"),
-                false => error!("Authentication failed"),
+            // Attempt to change the password, proving knowledge of the current one
+            match c
+                .change_password(
+                    &client_args.user,
+                    change_password_args.kdf,
+                    client_args.password.provider().as_ref(),
+                    change_password_args.new_password.provider().as_ref(),
+                    &mut client,
+                )
+                .await?
+            {
+                true => info!("Password changed"),
+                false => error!("Password change failed"),
             }
         }
         Command::Server(server_args) => {
-            let binding_addr = format!("0.0.0.0:{}", server_args.port); // Determine the binding address
-            let state = ServerState::new(server_args.use_elliptic_curve); // Initialize server state
-
-            info!("Starting auth server on {}", binding_addr); // Log the server start
-                                                               // Start the gRPC server and add the authentication service
-            Server::builder()
-                .add_service(AuthServer::new(state))
-                .serve(binding_addr.parse()?) // Parse the address string into a SocketAddr
-                .await?;
+            let config = server_args.config()?; // Merge the configuration file and the flags
+            if server_args.print_config {
+                print!("{}", config.to_toml());
+                return Ok(());
+            }
+            init_logging(&config.log);
+
+            let exponentiation = config.exponentiation()?; // Load the exponentiation group
+            let registrations = config.open_registrations()?; // Open the registration store
+            let sessions = config.open_sessions()?; // Open the session store
+            let state = ServerState::new(
+                &config.auth_types,
+                exponentiation,
+                registrations,
+                sessions,
+                config.challenge_policy(),
+                config.session_ttl(),
+            )
+            .with_rate_limit_policy(config.rate_limit_policy()); // Initialize server state
+            let state = match &config.sessions.token_key {
+                Some(path) => state.with_token_signer(TokenSigner::load_or_generate(path)?),
+                None => state,
+            };
+            let state = Arc::new(state); // Shared by the servers on every address
+
+            // Serve over TLS when a certificate is given
+            let tls = config.tls_config()?;
+            if tls.is_some() {
+                info!("Serving over TLS");
+            }
+
+            // Start a gRPC server with the authentication service on every address
+            let mut servers = JoinSet::new();
+            for address in config.bind {
+                let mut builder = Server::builder();
+                if let Some(tls) = &tls {
+                    builder = builder.tls_config(tls.clone())?;
+                }
+                info!("Starting auth server on {}", address); // Log the server start
+                servers.spawn(
+                    builder
+                        .add_service(AuthServer::from_arc(state.clone()))
+                        .serve(address),
+                );
+            }
+            // Stop as soon as any of the servers fails
+            while let Some(served) = servers.join_next().await {
+                served??;
+            }
         }
+        Command::Session(session_args) => match session_args.command {
+            SessionCommand::Validate(target) => {
+                let session_id = target.session_id()?;
+                let mut client = connect_to_server(&target.server_address, &target.tls).await?;
+                print_session(&validate_session(&mut client, &session_id).await?);
+            }
+            SessionCommand::Refresh(target) => {
+                let session_id = target.session_id()?;
+                let mut client = connect_to_server(&target.server_address, &target.tls).await?;
+                let refreshed = refresh_session(&mut client, &session_id).await?;
+                print_session(&refreshed);
+                // Keep the credentials file in step with the new expiry and token
+                if let Some(path) = &target.credentials {
+                    let mut session = Session::read(path)?;
+                    session.expires = refreshed.expires;
+                    if !refreshed.token.is_empty() {
+                        session.token = Some(refreshed.token);
+                    }
+                    session.write(path)?;
+                }
+            }
+            SessionCommand::Revoke(target) => {
+                let session_id = target.session_id()?;
+                let mut client = connect_to_server(&target.server_address, &target.tls).await?;
+                revoke_session(&mut client, &session_id).await?;
+                println!("Session revoked");
+            }
+            SessionCommand::List(cache_args) => {
+                let cache = cache_args.open()?;
+                let now = unix_now();
+                for (server, session) in cache.sessions() {
+                    let expiry = match session.is_expired(now) {
+                        true => "expired".to_string(),
+                        false => format!("expires in {} seconds", session.expires - now),
+                    };
+                    println!("{server} {} {} {expiry}", session.user, session.auth_type);
+                }
+            }
+            SessionCommand::Show(show_args) => {
+                let cache = show_args.cache.open()?;
+                let server = show_args.server_address.to_string();
+                let Some(session) = cache.get(&server, &show_args.user) else {
+                    anyhow::bail!(
+                        "No session of user '{}' on {server} is cached",
+                        show_args.user
+                    );
+                };
+                print_client_session(session);
+            }
+            SessionCommand::Clear(clear_args) => {
+                let mut cache = clear_args.cache.open()?;
+                let server = clear_args.server_address.map(|address| address.to_string());
+                let cleared = cache.clear(
+                    server.as_deref(),
+                    clear_args.user.as_deref(),
+                    clear_args.expired.then(unix_now),
+                );
+                cache.save()?;
+                println!("Cleared {cleared} cached sessions");
+            }
+        },
+        Command::Token(token_args) => match token_args.command {
+            TokenCommand::Key(key_args) => {
+                let mut client = connect_to_server(&key_args.server_address, &key_args.tls).await?;
+                let key = get_token_key(&mut client).await?;
+                println!("Algorithm {}", key.algorithm);
+                println!("Key id {}", key.key_id);
+                println!("Public key {}", hex::encode(&key.public_key));
+            }
+            TokenCommand::Verify(verify_args) => {
+                let verifier = TokenVerifier::new(&hex::decode(verify_args.public_key.trim())?)?;
+                let claims = verifier.verify(verify_args.token.trim(), unix_now())?; // Fails the command with the reason the token is refused
+                println!("Token for user '{}'", claims.sub);
+                println!("Authenticated with {}", claims.auth);
+                println!(
+                    "Expires in {} seconds",
+                    claims.exp.saturating_sub(unix_now())
+                );
+            }
+        },
+        Command::Params(params_args) => match params_args.command {
+            ParamsCommand::Generate(generate_args) => {
+                // Refuse to spend time generating a group that would then fail validation
+                check_minimum_sizes(
+                    generate_args.bits,
+                    generate_args
+                        .subgroup_bits
+                        .unwrap_or(generate_args.bits.saturating_sub(1)),
+                )?;
+                info!(
+                    "Generating a {} bit group, this can take a while",
+                    generate_args.bits
+                );
+                let params = generate_parameters(
+                    generate_args.bits,
+                    generate_args.subgroup_bits,
+                    &generate_args.seed,
+                )?;
+
+                // Write the parameters to the requested file, or stdout
+                match generate_args.output {
+                    Some(path) => {
+                        params.to_file(&path)?;
+                        info!("Group parameters written to {}", path.display());
+                    }
+                    None => print!("{}", params.to_toml()),
+                }
+            }
+            ParamsCommand::Verify(verify_args) => {
+                let params = Exponentiation::from_file(&verify_args.file)?;
+                params.validate()?; // Fails the command with the reason the parameters are invalid
+                println!(
+                    "{} is a valid {} bit group with a {} bit subgroup",
+                    verify_args.file.display(),
+                    params.p.bits(),
+                    params.q.bits()
+                );
+                println!("Its fingerprint is {}", params.fingerprint());
+                match &params.seed {
+                    Some(seed) => println!("h is derived from the public seed '{}'", seed),
+                    None => {
+                        println!("h has no seed, so it cannot be shown that log_g(h) is unknown")
+                    }
+                }
+            }
+        },
     }
     Ok(())
 }
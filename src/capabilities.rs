@@ -0,0 +1,231 @@
+use crate::authentication::kdf::KdfAlgorithm;
+use crate::errors::AuthenticationError;
+use crate::zkp_auth::{AuthenticationType, Encoding, HelloResponse, KdfType, ProofMode};
+
+// The protocol version spoken by this build, raised whenever an encoding or the flow of messages
+// changes in a way that older peers cannot follow
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// The oldest protocol version this build still speaks
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+// Key derivation functions in the order the client prefers them
+const KDF_PREFERENCE: [KdfAlgorithm; 3] = [
+    KdfAlgorithm::Argon2id,
+    KdfAlgorithm::Scrypt,
+    KdfAlgorithm::Pbkdf2,
+];
+
+// Convert a key derivation function into its protobuf representation.
+impl From<KdfAlgorithm> for KdfType {
+    fn from(algorithm: KdfAlgorithm) -> Self {
+        match algorithm {
+            KdfAlgorithm::Argon2id => KdfType::Argon2id,
+            KdfAlgorithm::Scrypt => KdfType::Scrypt,
+            KdfAlgorithm::Pbkdf2 => KdfType::Pbkdf2,
+        }
+    }
+}
+
+// The options offered by a server that a client also supports, each in order of preference
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Negotiated {
+    pub protocol_version: u32,
+    pub auth_types: Vec<AuthenticationType>, // In the server's order of preference
+    pub kdfs: Vec<KdfAlgorithm>,             // In the client's order of preference
+    pub proof_modes: Vec<ProofMode>,
+}
+
+impl Negotiated {
+    // The authentication type new registrations should use, the one the server prefers most
+    pub fn auth_type(&self) -> AuthenticationType {
+        self.auth_types[0]
+    }
+
+    // Refuse an authentication type that is not supported on both sides
+    pub fn require_auth_type(&self, auth: AuthenticationType) -> Result<(), AuthenticationError> {
+        require(&self.auth_types, &auth, "authentication type")
+    }
+
+    // The key derivation function to stretch a new password with, the requested one if both sides
+    // support it, or the best supported one when none is requested
+    pub fn kdf(
+        &self,
+        requested: Option<KdfAlgorithm>,
+    ) -> Result<KdfAlgorithm, AuthenticationError> {
+        match requested {
+            Some(algorithm) => {
+                require(&self.kdfs, &algorithm, "key derivation")?;
+                Ok(algorithm)
+            }
+            None => Ok(self.kdfs[0]),
+        }
+    }
+
+    // Refuse a proof mode that is not supported on both sides
+    pub fn require_proof_mode(&self, mode: ProofMode) -> Result<(), AuthenticationError> {
+        require(&self.proof_modes, &mode, "proof mode")
+    }
+}
+
+// Refuse an option the negotiation left out, listing the options that are left
+fn require<T: PartialEq + std::fmt::Debug>(
+    options: &[T],
+    option: &T,
+    capability: &'static str,
+) -> Result<(), AuthenticationError> {
+    if options.contains(option) {
+        return Ok(());
+    }
+    Err(AuthenticationError::NoCommonCapability {
+        capability,
+        offered: format!("{:?}", options),
+    })
+}
+
+// The capabilities offered by a server serving the authentication types, in its order of preference
+pub fn offer(auth_types: &[AuthenticationType]) -> HelloResponse {
+    HelloResponse {
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        max_protocol_version: PROTOCOL_VERSION,
+        auth_types: auth_types.iter().map(|auth| *auth as i32).collect(),
+        kdfs: KDF_PREFERENCE
+            .iter()
+            .map(|algorithm| KdfType::from(*algorithm) as i32)
+            .collect(),
+        proof_modes: vec![
+            ProofMode::Interactive as i32,
+            ProofMode::NonInteractive as i32,
+        ],
+        encodings: vec![Encoding::Canonical as i32],
+    }
+}
+
+// Describe the options a server offered, naming the ones this build knows and numbering the rest
+fn describe<T: TryFrom<i32> + std::fmt::Debug>(offered: &[i32]) -> String {
+    if offered.is_empty() {
+        return "none".to_string();
+    }
+    offered
+        .iter()
+        .map(|value| match T::try_from(*value) {
+            Ok(option) => format!("{:?}", option),
+            Err(_) => format!("unknown option {}", value),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Keep the offered options this build knows, refusing the offer when there are none
+fn common<T: TryFrom<i32> + std::fmt::Debug>(
+    offered: &[i32],
+    capability: &'static str,
+) -> Result<Vec<T>, AuthenticationError> {
+    let known: Vec<T> = offered
+        .iter()
+        .filter_map(|value| T::try_from(*value).ok())
+        .collect();
+    if known.is_empty() {
+        return Err(AuthenticationError::NoCommonCapability {
+            capability,
+            offered: describe::<T>(offered),
+        });
+    }
+    Ok(known)
+}
+
+// Pick the highest protocol version both sides speak and the options both sides support from the
+// capabilities a server offered, failing with the first capability that has nothing in common
+pub fn negotiate(offer: &HelloResponse) -> Result<Negotiated, AuthenticationError> {
+    let protocol_version = PROTOCOL_VERSION.min(offer.max_protocol_version);
+    if protocol_version < MIN_PROTOCOL_VERSION.max(offer.min_protocol_version) {
+        return Err(AuthenticationError::NoCommonCapability {
+            capability: "protocol version",
+            offered: format!(
+                "versions {} to {}",
+                offer.min_protocol_version, offer.max_protocol_version
+            ),
+        });
+    }
+    let _: Vec<Encoding> = common(&offer.encodings, "encoding")?;
+    let auth_types = common(&offer.auth_types, "authentication type")?;
+    let offered_kdfs: Vec<KdfType> = common(&offer.kdfs, "key derivation")?;
+    let kdfs = KDF_PREFERENCE
+        .into_iter()
+        .filter(|algorithm| offered_kdfs.contains(&KdfType::from(*algorithm)))
+        .collect();
+    let proof_modes = common(&offer.proof_modes, "proof mode")?;
+    Ok(Negotiated {
+        protocol_version,
+        auth_types,
+        kdfs,
+        proof_modes,
+    })
+}
+
+// Unit tests for negotiating capabilities between a client and a server.
+#[cfg(test)]
+mod tests {
+    use {super::*, test_case::test_case};
+
+    // A server of the same build must agree on its own version and everything it offers, keeping
+    // its order of authentication types and the client's order of key derivations.
+    #[test]
+    fn offers_should_negotiate_with_the_same_build() {
+        let offer = offer(&[
+            AuthenticationType::EllipticCurve,
+            AuthenticationType::Exponentiation,
+        ]);
+        let negotiated = negotiate(&offer).unwrap();
+        assert_eq!(negotiated.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(negotiated.auth_type(), AuthenticationType::EllipticCurve);
+        assert_eq!(negotiated.kdfs, KDF_PREFERENCE.to_vec());
+        assert_eq!(negotiated.kdf(None).unwrap(), KdfAlgorithm::Argon2id);
+        assert!(negotiated
+            .require_proof_mode(ProofMode::NonInteractive)
+            .is_ok());
+    }
+
+    // The best option both sides support must be picked, ignoring options this build does not know.
+    #[test]
+    fn the_best_common_options_should_be_picked() {
+        let offer = HelloResponse {
+            max_protocol_version: PROTOCOL_VERSION + 1,
+            kdfs: vec![7, KdfType::Pbkdf2 as i32, KdfType::Scrypt as i32],
+            proof_modes: vec![ProofMode::Interactive as i32],
+            ..offer(&[AuthenticationType::Exponentiation])
+        };
+        let negotiated = negotiate(&offer).unwrap();
+        assert_eq!(negotiated.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(negotiated.kdf(None).unwrap(), KdfAlgorithm::Scrypt);
+        assert_eq!(
+            negotiated.kdf(Some(KdfAlgorithm::Pbkdf2)).unwrap(),
+            KdfAlgorithm::Pbkdf2
+        );
+        assert!(matches!(
+            negotiated.kdf(Some(KdfAlgorithm::Argon2id)),
+            Err(AuthenticationError::NoCommonCapability { .. })
+        ));
+        assert!(negotiated
+            .require_proof_mode(ProofMode::NonInteractive)
+            .is_err());
+        assert!(negotiated
+            .require_auth_type(AuthenticationType::EllipticCurve)
+            .is_err());
+    }
+
+    // An offer with nothing in common for a capability must be refused, naming the capability.
+    #[test_case(HelloResponse { min_protocol_version: PROTOCOL_VERSION + 1, max_protocol_version: PROTOCOL_VERSION + 2, ..Default::default() }, "protocol version"; "when versions do not overlap")]
+    #[test_case(HelloResponse { encodings: vec![5], ..offer(&[AuthenticationType::Exponentiation]) }, "encoding"; "when encoding is unknown")]
+    #[test_case(HelloResponse { auth_types: vec![], ..offer(&[AuthenticationType::Exponentiation]) }, "authentication type"; "when no type is offered")]
+    #[test_case(HelloResponse { kdfs: vec![9], ..offer(&[AuthenticationType::Exponentiation]) }, "key derivation"; "when key derivation is unknown")]
+    #[test_case(HelloResponse { proof_modes: vec![], ..offer(&[AuthenticationType::Exponentiation]) }, "proof mode"; "when no proof mode is offered")]
+    fn incompatible_offers_should_be_refused(offer: HelloResponse, expected: &str) {
+        match negotiate(&offer) {
+            Err(AuthenticationError::NoCommonCapability { capability, .. }) => {
+                assert_eq!(capability, expected)
+            }
+            other => panic!("Expected no common {}, got {:?}", expected, other),
+        }
+    }
+}
@@ -1,8 +1,20 @@
+use crate::authentication::common::DEFAULT_GENERATOR_SEED;
+use crate::authentication::exponentiation::{Exponentiation, GroupId};
+use crate::authentication::kdf::KdfAlgorithm;
+use crate::config::{LogFormat, LogLevel, ServerConfig};
+use crate::credentials::{Session, SessionCache};
+use crate::errors::{ConfigError, CredentialsError, ParameterError, TlsError};
+use crate::secret::{EnvSecret, FileSecret, SecretProvider, StdinSecret, TerminalSecret};
+use crate::storage::StorageKind;
+use crate::tls;
+use crate::zkp_auth::AuthenticationType;
 use clap::{Args, Parser, Subcommand};
 use std::{
     io::{Error, ErrorKind, Result},
     net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
 };
+use tonic::transport::ClientTlsConfig;
 
 // Function to resolve a network target (e.g., "localhost:8080") into a SocketAddr
 fn resolve_target(target: &str) -> Result<SocketAddr> {
@@ -41,43 +53,746 @@ pub struct ClientArgs {
     // User ID for authentication, required for client commands
     #[arg(short, long = "user", help = "The user id for authentication")]
     pub user: String,
+
+    // The exponentiation group the server must advertise, any valid group when none is pinned
+    #[command(flatten)]
+    pub group: GroupArgs,
+
+    // How to connect to the server over TLS
+    #[command(flatten)]
+    pub tls: ClientTlsArgs,
+
+    // Where the password is read from, prompted for on the terminal by default
+    #[command(flatten)]
+    pub password: PasswordArgs,
+}
+
+// Define arguments for the register command
+#[derive(Args)]
+pub struct RegisterArgs {
+    #[command(flatten)]
+    pub client: ClientArgs,
+
+    // The key derivation function the password is stretched with before registering
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "The key derivation function used to stretch the password, the best one the server supports when omitted"
+    )]
+    pub kdf: Option<KdfAlgorithm>,
+}
+
+// Define arguments for the change password command
+#[derive(Args)]
+pub struct ChangePasswordArgs {
+    #[command(flatten)]
+    pub client: ClientArgs,
+
+    // The key derivation function the new password is stretched with
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "The key derivation function used to stretch the new password, the best one the server supports when omitted"
+    )]
+    pub kdf: Option<KdfAlgorithm>,
+
+    // Where the new password is read from, prompted for twice on the terminal by default
+    #[command(flatten)]
+    pub new_password: NewPasswordArgs,
+}
+
+// Define arguments for the authenticate command
+#[derive(Args)]
+pub struct AuthenticateArgs {
+    #[command(flatten)]
+    pub client: ClientArgs,
+
+    // Flag to send a single non-interactive proof rather than answering a server challenge
+    #[arg(
+        short,
+        long,
+        help = "Authenticate with a single non-interactive proof rather than a challenge from the server"
+    )]
+    pub non_interactive: bool,
+
+    // Flag to print the session the server started
+    #[arg(
+        long,
+        help = "Print the session the server started, including its token"
+    )]
+    pub print_session: bool,
+
+    // The file the session is written to for later session commands
+    #[arg(
+        long,
+        help = "Write the session to this credentials file, readable only by the user, for later session commands"
+    )]
+    pub credentials: Option<PathBuf>,
+
+    // Where sessions are cached between commands
+    #[command(flatten)]
+    pub cache: SessionCacheArgs,
+
+    // Flag to neither reuse nor cache a session
+    #[arg(
+        long,
+        conflicts_with_all = ["session_cache", "new_session"],
+        help = "Neither reuse a cached session nor cache the new one"
+    )]
+    pub no_session_cache: bool,
+
+    // Flag to authenticate even when a cached session is still valid
+    #[arg(
+        long,
+        help = "Authenticate again even when the cached session is still valid, caching the new session"
+    )]
+    pub new_session: bool,
 }
 
-// Define arguments for the server command
+// Define arguments for the server command. Every setting but the configuration file itself is
+// optional, flags that are given override the configuration file, which overrides the defaults
 #[derive(Args)]
 pub struct ServerArgs {
-    // The port number on which the server should listen, parsed as an unsigned 32-bit integer
+    // The configuration file
     #[arg(
         short,
         long,
-        help = "The port on which to bind the authentication server"
+        help = "Read the server configuration from this TOML file, flags override its settings"
+    )]
+    pub config: Option<PathBuf>,
+    // Flag to print the effective configuration rather than starting the server
+    #[arg(
+        long,
+        help = "Print the effective configuration as TOML and exit without starting the server"
     )]
-    pub port: u32,
+    pub print_config: bool,
+    // The port number on which the server should listen on every interface
+    #[arg(
+        short,
+        long,
+        conflicts_with = "bind",
+        help = "The port on which to bind the authentication server on every interface"
+    )]
+    pub port: Option<u16>,
+    // The addresses on which the server should listen
+    #[arg(
+        short,
+        long,
+        help = "An address on which to bind the authentication server, can be repeated"
+    )]
+    pub bind: Vec<SocketAddr>,
     // Flag to indicate whether elliptic curve cryptography should be used instead of exponentiation
     #[arg(
         short = 'e',
         long = "use-elliptic-curve",
+        conflicts_with = "auth_types",
         help = "Indicates if the client/server pair should use elliptic curves rather than exponents."
     )]
     pub use_elliptic_curve: bool,
+    // The authentication types served, the first being the one new registrations use
+    #[arg(
+        long = "auth-type",
+        value_enum,
+        help = "An authentication type to serve, can be repeated, new registrations use the first"
+    )]
+    pub auth_types: Vec<AuthenticationType>,
+    // One of the standardised groups
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "The standardised group used for exponentiation, advertised to clients"
+    )]
+    pub group: Option<GroupId>,
+    // A parameter file written by `acp params generate`
+    #[arg(
+        long,
+        conflicts_with = "group",
+        help = "A group parameter file to use for exponentiation instead of a standardised group"
+    )]
+    pub params: Option<PathBuf>,
+    // The kind of registration store
+    #[arg(
+        long = "storage",
+        value_enum,
+        help = "Where to keep registrations, only the file and sqlite stores survive a restart"
+    )]
+    pub storage: Option<StorageKind>,
+    // The file used by the file and sqlite stores
+    #[arg(
+        long,
+        help = "The file registrations are kept in, required by the file and sqlite stores"
+    )]
+    pub storage_path: Option<PathBuf>,
+    // How long a challenge can be answered for
+    #[arg(long, help = "The number of seconds a challenge can be answered for")]
+    pub challenge_ttl: Option<u64>,
+    // How many challenges can be outstanding at once
+    #[arg(
+        long,
+        help = "The maximum number of outstanding challenges, the oldest are dropped beyond this"
+    )]
+    pub challenge_capacity: Option<u64>,
+    // How long a session lasts
+    #[arg(
+        long,
+        help = "The number of seconds a session lasts after it is started or refreshed"
+    )]
+    pub session_ttl: Option<u64>,
+    // The key session tokens are signed with, tokens are only issued when it is given
+    #[arg(
+        long,
+        help = "Issue signed session tokens with the Ed25519 key in this file, which is created if it does not exist"
+    )]
+    pub token_key: Option<PathBuf>,
+    // How to serve over TLS
+    #[command(flatten)]
+    pub tls: ServerTlsArgs,
+    // How many failed attempts are allowed before backing off
+    #[arg(
+        long,
+        help = "The number of failed attempts by a user or address allowed before backing off"
+    )]
+    pub free_failures: Option<u32>,
+    // How long the first backoff lasts
+    #[arg(
+        long,
+        help = "The number of seconds to wait after the first failure that is not free, doubling with every further failure"
+    )]
+    pub backoff_base: Option<u64>,
+    // How long a backoff can last at most
+    #[arg(long, help = "The longest number of seconds to wait between attempts")]
+    pub backoff_max: Option<u64>,
+    // How many failed attempts lock a user or address out
+    #[arg(
+        long,
+        help = "The number of failed attempts after which a user or address is locked out"
+    )]
+    pub lockout_failures: Option<u32>,
+    // How long a lockout lasts
+    #[arg(
+        long,
+        help = "The number of seconds a lockout lasts, and after which failures are forgotten"
+    )]
+    pub lockout_secs: Option<u64>,
+    // The most detailed level of log messages written
+    #[arg(
+        long,
+        value_enum,
+        help = "The most detailed level of log messages written"
+    )]
+    pub log_level: Option<LogLevel>,
+    // How log messages are laid out
+    #[arg(long, value_enum, help = "How log messages are laid out")]
+    pub log_format: Option<LogFormat>,
+}
+
+// Replace a setting with the value of a flag, if the flag was given
+fn set<T>(setting: &mut T, flag: Option<T>) {
+    if let Some(value) = flag {
+        *setting = value;
+    }
+}
+
+impl ServerArgs {
+    // The effective configuration: the configuration file, or the defaults when there is none,
+    // with the flags that were given applied over it
+    pub fn config(&self) -> std::result::Result<ServerConfig, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::from_file(path)?,
+            None => ServerConfig::default(),
+        };
+        if let Some(port) = self.port {
+            config.bind = vec![SocketAddr::from(([0, 0, 0, 0], port))];
+        }
+        if !self.bind.is_empty() {
+            config.bind = self.bind.clone();
+        }
+        if self.use_elliptic_curve {
+            config.auth_types = vec![AuthenticationType::EllipticCurve];
+        }
+        if !self.auth_types.is_empty() {
+            config.auth_types = self.auth_types.clone();
+        }
+        // A group given on the command line replaces a parameter file from the configuration,
+        // and the other way around
+        if let Some(group) = self.group {
+            config.group.id = group;
+            config.group.params = None;
+        }
+        if self.params.is_some() {
+            config.group.params = self.params.clone();
+        }
+        set(&mut config.storage.kind, self.storage);
+        config.storage.path = self.storage_path.clone().or(config.storage.path);
+        set(&mut config.challenges.ttl_secs, self.challenge_ttl);
+        set(&mut config.challenges.capacity, self.challenge_capacity);
+        set(&mut config.sessions.ttl_secs, self.session_ttl);
+        config.sessions.token_key = self.token_key.clone().or(config.sessions.token_key);
+        config.tls.cert = self.tls.tls_cert.clone().or(config.tls.cert);
+        config.tls.key = self.tls.tls_key.clone().or(config.tls.key);
+        config.tls.ca = self.tls.ca.clone().or(config.tls.ca);
+        config.tls.client_cert_optional |= self.tls.client_cert_optional;
+        set(&mut config.rate_limit.free_failures, self.free_failures);
+        set(&mut config.rate_limit.backoff_base_secs, self.backoff_base);
+        set(&mut config.rate_limit.backoff_max_secs, self.backoff_max);
+        set(
+            &mut config.rate_limit.lockout_failures,
+            self.lockout_failures,
+        );
+        set(&mut config.rate_limit.lockout_secs, self.lockout_secs);
+        set(&mut config.log.level, self.log_level);
+        set(&mut config.log.format, self.log_format);
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+// Define arguments for serving over TLS, optionally verifying client certificates
+#[derive(Args)]
+pub struct ServerTlsArgs {
+    // The certificate chain of the server, TLS is used when it is given
+    #[arg(
+        long,
+        help = "Serve over TLS with the PEM certificate chain in this file"
+    )]
+    pub tls_cert: Option<PathBuf>,
+    // The private key of the server certificate
+    #[arg(long, help = "The PEM private key of the server certificate")]
+    pub tls_key: Option<PathBuf>,
+    // The CA client certificates are checked against
+    #[arg(
+        long,
+        help = "Require clients to present a certificate signed by the PEM CA in this file"
+    )]
+    pub ca: Option<PathBuf>,
+    // Flag to accept clients without a certificate
+    #[arg(
+        long,
+        help = "Also accept clients that present no certificate, checking only those that do"
+    )]
+    pub client_cert_optional: bool,
+}
+
+// Define arguments for connecting to the server over TLS, shared by the client commands
+#[derive(Args)]
+pub struct ClientTlsArgs {
+    // The CA the server certificate is checked against, TLS is used when it is given
+    #[arg(
+        long,
+        help = "Connect over TLS, trusting server certificates signed by the PEM CA in this file"
+    )]
+    pub ca: Option<PathBuf>,
+    // The certificate presented to the server for mutual TLS
+    #[arg(
+        long,
+        requires_all = ["ca", "tls_key"],
+        help = "Present the PEM certificate in this file to the server for mutual TLS"
+    )]
+    pub tls_cert: Option<PathBuf>,
+    // The private key of the client certificate
+    #[arg(
+        long,
+        requires_all = ["ca", "tls_cert"],
+        help = "The PEM private key of the client certificate"
+    )]
+    pub tls_key: Option<PathBuf>,
+    // The name the server certificate must be issued for
+    #[arg(
+        long,
+        requires = "ca",
+        help = "The name the server certificate must be issued for, the IP address of the server by default"
+    )]
+    pub tls_domain: Option<String>,
+}
+
+impl ClientTlsArgs {
+    // The TLS configuration for connecting to the server, or none when connecting in plaintext
+    pub fn config(
+        &self,
+        server_address: &SocketAddr,
+    ) -> std::result::Result<Option<ClientTlsConfig>, TlsError> {
+        let Some(ca) = &self.ca else {
+            return Ok(None);
+        };
+        let identity = self.tls_cert.as_deref().zip(self.tls_key.as_deref());
+        let domain = match &self.tls_domain {
+            Some(domain) => domain.clone(),
+            None => server_address.ip().to_string(),
+        };
+        Ok(Some(tls::client_config(ca, identity, &domain)?))
+    }
+}
+
+// Define arguments pinning the exponentiation group the server must advertise, shared by the
+// client commands
+#[derive(Args)]
+pub struct GroupArgs {
+    // One of the standardised groups
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "Only accept a server using this standardised group for exponentiation"
+    )]
+    pub group: Option<GroupId>,
+    // A parameter file written by `acp params generate`
+    #[arg(
+        long,
+        conflicts_with = "group",
+        help = "Only accept a server using the group in this parameter file for exponentiation"
+    )]
+    pub params: Option<PathBuf>,
+    // The fingerprint printed by `acp params verify` and logged by the server
+    #[arg(
+        long,
+        conflicts_with_all = ["group", "params"],
+        help = "Only accept a server whose exponentiation group has this fingerprint"
+    )]
+    pub group_fingerprint: Option<String>,
+}
+
+impl GroupArgs {
+    // The fingerprint the group advertised by the server must have, when one was pinned
+    pub fn pinned_fingerprint(&self) -> std::result::Result<Option<String>, ParameterError> {
+        if let Some(path) = &self.params {
+            return Ok(Some(Exponentiation::from_file(path)?.fingerprint()));
+        }
+        Ok(self
+            .group
+            .map(|group| Exponentiation::with_group(group).fingerprint())
+            .or_else(|| self.group_fingerprint.clone()))
+    }
+}
+
+// Define arguments choosing where the password is read from, so that the client can run without a
+// terminal
+#[derive(Args)]
+pub struct PasswordArgs {
+    // The file whose first line is the password
+    #[arg(
+        long,
+        conflicts_with_all = ["password_stdin", "password_env"],
+        help = "Read the password from the first line of this file rather than prompting for it"
+    )]
+    pub password_file: Option<PathBuf>,
+    // Flag to read the password from a line of standard input
+    #[arg(
+        long,
+        conflicts_with = "password_env",
+        help = "Read the password from a line of standard input rather than prompting for it"
+    )]
+    pub password_stdin: bool,
+    // The environment variable holding the password
+    #[arg(
+        long,
+        help = "Read the password from this environment variable rather than prompting for it"
+    )]
+    pub password_env: Option<String>,
+}
+
+impl PasswordArgs {
+    // The source of the password, the terminal when no other source is given
+    pub fn provider(&self) -> Box<dyn SecretProvider> {
+        secret_provider(
+            self.password_file.as_ref(),
+            self.password_stdin,
+            self.password_env.as_ref(),
+        )
+    }
+}
+
+// Define arguments choosing where the new password is read from when changing it
+#[derive(Args)]
+pub struct NewPasswordArgs {
+    // The file whose first line is the new password
+    #[arg(
+        long,
+        conflicts_with_all = ["new_password_stdin", "new_password_env"],
+        help = "Read the new password from the first line of this file rather than prompting for it"
+    )]
+    pub new_password_file: Option<PathBuf>,
+    // Flag to read the new password from a line of standard input, after the current password
+    #[arg(
+        long,
+        conflicts_with = "new_password_env",
+        help = "Read the new password from a line of standard input, after the current password when it is read from there too"
+    )]
+    pub new_password_stdin: bool,
+    // The environment variable holding the new password
+    #[arg(
+        long,
+        help = "Read the new password from this environment variable rather than prompting for it"
+    )]
+    pub new_password_env: Option<String>,
+}
+
+impl NewPasswordArgs {
+    // The source of the new password, the terminal when no other source is given
+    pub fn provider(&self) -> Box<dyn SecretProvider> {
+        secret_provider(
+            self.new_password_file.as_ref(),
+            self.new_password_stdin,
+            self.new_password_env.as_ref(),
+        )
+    }
+}
+
+// The password source chosen by the flags, which clap keeps mutually exclusive
+fn secret_provider(
+    file: Option<&PathBuf>,
+    stdin: bool,
+    env: Option<&String>,
+) -> Box<dyn SecretProvider> {
+    match (file, stdin, env) {
+        (Some(path), _, _) => Box::new(FileSecret(path.clone())),
+        (None, true, _) => Box::new(StdinSecret),
+        (None, false, Some(name)) => Box::new(EnvSecret(name.clone())),
+        (None, false, None) => Box::new(TerminalSecret),
+    }
+}
+
+// Define arguments for the session commands
+#[derive(Args)]
+pub struct SessionArgs {
+    #[command(subcommand)]
+    pub command: SessionCommand,
+}
+
+// Enum to represent the session commands
+#[derive(Subcommand)]
+pub enum SessionCommand {
+    #[command(aliases = ["v"])]
+    Validate(SessionTargetArgs),
+    #[command(aliases = ["r"])]
+    Refresh(SessionTargetArgs),
+    #[command(aliases = ["x"])]
+    Revoke(SessionTargetArgs),
+    #[command(aliases = ["l"])]
+    List(SessionCacheArgs),
+    #[command(aliases = ["s"])]
+    Show(SessionShowArgs),
+    #[command(aliases = ["c"])]
+    Clear(SessionClearArgs),
+}
+
+// Define arguments choosing the file sessions are cached in between commands
+#[derive(Args)]
+pub struct SessionCacheArgs {
+    // The cache file, the default one in the user's configuration directory when omitted
+    #[arg(
+        long,
+        help = "The file sessions are cached in, acp/sessions.toml in the user's configuration directory by default"
+    )]
+    pub session_cache: Option<PathBuf>,
+}
+
+impl SessionCacheArgs {
+    // Open the session cache
+    pub fn open(&self) -> std::result::Result<SessionCache, CredentialsError> {
+        match &self.session_cache {
+            Some(path) => SessionCache::open(path),
+            None => SessionCache::open(&SessionCache::default_path()?),
+        }
+    }
+}
+
+// Define arguments identifying a cached session
+#[derive(Args)]
+pub struct SessionShowArgs {
+    // The server address, parsed by the resolve_target function to match the cached address
+    #[arg(short, long, value_parser = resolve_target, help = "The address of the authentication server")]
+    pub server_address: SocketAddr,
+
+    // The user whose session is shown
+    #[arg(short, long = "user", help = "The user id the session was started for")]
+    pub user: String,
+
+    #[command(flatten)]
+    pub cache: SessionCacheArgs,
+}
+
+// Define arguments choosing the cached sessions to forget, every one unless narrowed down
+#[derive(Args)]
+pub struct SessionClearArgs {
+    // The server whose sessions are forgotten
+    #[arg(short, long, value_parser = resolve_target, help = "Only forget sessions on the authentication server at this address")]
+    pub server_address: Option<SocketAddr>,
+
+    // The user whose sessions are forgotten
+    #[arg(short, long = "user", help = "Only forget sessions of this user id")]
+    pub user: Option<String>,
+
+    // Flag to keep the sessions that are still valid
+    #[arg(long, help = "Only forget sessions that have expired")]
+    pub expired: bool,
+
+    #[command(flatten)]
+    pub cache: SessionCacheArgs,
+}
+
+// Define arguments identifying a session on a server
+#[derive(Args)]
+pub struct SessionTargetArgs {
+    // The server address, parsed by the resolve_target function to ensure validity
+    #[arg(short, long, value_parser = resolve_target, help = "The address of the authentication server")]
+    pub server_address: SocketAddr,
+
+    // How to connect to the server over TLS
+    #[command(flatten)]
+    pub tls: ClientTlsArgs,
+
+    // The session id returned when authenticating
+    #[arg(
+        short = 'i',
+        long,
+        required_unless_present = "credentials",
+        help = "The session id returned when authenticating"
+    )]
+    pub session_id: Option<String>,
+
+    // The credentials file the session was written to when authenticating
+    #[arg(
+        long,
+        conflicts_with = "session_id",
+        help = "Take the session from this credentials file written when authenticating"
+    )]
+    pub credentials: Option<PathBuf>,
+}
+
+impl SessionTargetArgs {
+    // The id of the session, given directly or read from the credentials file
+    pub fn session_id(&self) -> std::result::Result<String, CredentialsError> {
+        match (&self.session_id, &self.credentials) {
+            (Some(session_id), _) => Ok(session_id.clone()),
+            (None, Some(path)) => Ok(Session::read(path)?.id),
+            (None, None) => unreachable!("clap requires a session id or a credentials file"),
+        }
+    }
+}
+
+// Define arguments for the session token commands
+#[derive(Args)]
+pub struct TokenArgs {
+    #[command(subcommand)]
+    pub command: TokenCommand,
+}
+
+// Enum to represent the session token commands
+#[derive(Subcommand)]
+pub enum TokenCommand {
+    #[command(aliases = ["k"])]
+    Key(TokenKeyArgs),
+    #[command(aliases = ["v"])]
+    Verify(VerifyTokenArgs),
+}
+
+// Define arguments for fetching the public key tokens are signed with
+#[derive(Args)]
+pub struct TokenKeyArgs {
+    // The server address, parsed by the resolve_target function to ensure validity
+    #[arg(short, long, value_parser = resolve_target, help = "The address of the authentication server")]
+    pub server_address: SocketAddr,
+
+    // How to connect to the server over TLS
+    #[command(flatten)]
+    pub tls: ClientTlsArgs,
+}
+
+// Define arguments for verifying a token locally
+#[derive(Args)]
+pub struct VerifyTokenArgs {
+    // The hex encoded public key published by the server
+    #[arg(
+        short,
+        long,
+        help = "The hex encoded public key tokens are signed with, as printed by `acp token key`"
+    )]
+    pub public_key: String,
+    // The token to verify
+    #[arg(help = "The session token to verify")]
+    pub token: String,
+}
+
+// Define arguments for the group parameter commands
+#[derive(Args)]
+pub struct ParamsArgs {
+    #[command(subcommand)]
+    pub command: ParamsCommand,
+}
+
+// Enum to represent the group parameter commands
+#[derive(Subcommand)]
+pub enum ParamsCommand {
+    #[command(aliases = ["g"])]
+    Generate(GenerateArgs),
+    #[command(aliases = ["v"])]
+    Verify(VerifyArgs),
+}
+
+// Define arguments for verifying a group parameter file
+#[derive(Args)]
+pub struct VerifyArgs {
+    // The parameter file to verify
+    #[arg(help = "The group parameter file to verify")]
+    pub file: PathBuf,
+}
+
+// Define arguments for generating new group parameters
+#[derive(Args)]
+pub struct GenerateArgs {
+    // The size of the modulus p in bits
+    #[arg(short, long, help = "The size of the modulus p in bits")]
+    pub bits: u64,
+    // The size of the subgroup order q in bits, a safe prime p = 2q + 1 is generated when omitted
+    #[arg(
+        short,
+        long,
+        help = "The size of the subgroup order q in bits, generating p = kq + 1 rather than a safe prime p = 2q + 1"
+    )]
+    pub subgroup_bits: Option<u64>,
+    // The public seed the second generator h is derived from
+    #[arg(
+        long,
+        default_value = DEFAULT_GENERATOR_SEED,
+        help = "The public seed the second generator h is derived from"
+    )]
+    pub seed: String,
+    // Where to write the parameters, printed to stdout when omitted
+    #[arg(
+        short,
+        long,
+        help = "The file to write the parameters to, printed to stdout when omitted"
+    )]
+    pub output: Option<PathBuf>,
 }
 
 // Enum to represent the possible CLI commands, each associated with its specific arguments
 #[derive(Subcommand)]
 pub enum Command {
     #[command(aliases = ["r"])]
-    Register(ClientArgs),
+    Register(RegisterArgs),
     #[command(aliases = ["a"])]
-    Authenticate(ClientArgs),
+    Authenticate(AuthenticateArgs),
+    #[command(aliases = ["c"])]
+    ChangePassword(ChangePasswordArgs),
     #[command(aliases = ["s"])]
     Server(ServerArgs),
+    #[command(aliases = ["p"])]
+    Params(ParamsArgs),
+    Session(SessionArgs),
+    Token(TokenArgs),
 }
 
+#[cfg(test)]
 mod tests {
     use {
         super::*,
+        clap::Parser,
         proptest::{
-            prelude::{Just, Strategy}, // For creating custom test strategies
+            prelude::{Just, ProptestConfig, Strategy}, // For creating custom test strategies
             prop_oneof,
             proptest,
         },
@@ -140,4 +855,123 @@ mod tests {
             assert!(target.is_err(), "Expected an error when resolving artificially constructed domain: {}", url);
         }
     }
+
+    // The effective configuration of `acp server` with the arguments
+    fn server_config(args: &[&str]) -> std::result::Result<ServerConfig, ConfigError> {
+        let cli = Cli::try_parse_from(["acp", "server"].iter().chain(args)).unwrap();
+        let Command::Server(server_args) = cli.command else {
+            unreachable!("The server command was parsed");
+        };
+        server_args.config()
+    }
+
+    // Flags that are given must override the configuration file, which must override the
+    // defaults.
+    #[test]
+    fn flags_should_override_the_configuration_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("acp.toml");
+        std::fs::write(
+            &path,
+            "bind = [\"127.0.0.1:9000\"]\n[group]\nparams = \"group.toml\"\n[rate_limit]\nfree_failures = 5\nlockout_secs = 60\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let config = server_config(&["--config", path]).unwrap();
+        assert_eq!(config.bind, vec!["127.0.0.1:9000".parse().unwrap()]);
+        assert_eq!(config.rate_limit.free_failures, 5);
+
+        let config = server_config(&[
+            "--config",
+            path,
+            "--port",
+            "9001",
+            "--group",
+            "modp3072",
+            "--lockout-secs",
+            "30",
+            "--log-level",
+            "debug",
+        ])
+        .unwrap();
+        assert_eq!(config.bind, vec!["0.0.0.0:9001".parse().unwrap()]);
+        assert_eq!(config.group.id, GroupId::Modp3072);
+        assert_eq!(config.group.params, None);
+        assert_eq!(config.rate_limit.free_failures, 5);
+        assert_eq!(config.rate_limit.lockout_secs, 30);
+        assert_eq!(config.log.level, LogLevel::Debug);
+
+        let config = server_config(&["-b", "127.0.0.1:1", "-b", "[::1]:1"]).unwrap();
+        assert_eq!(config.bind.len(), 2);
+        assert_eq!(config.rate_limit, Default::default());
+
+        let config = server_config(&["-e"]).unwrap();
+        assert_eq!(config.auth_types, vec![AuthenticationType::EllipticCurve]);
+        let config = server_config(&[
+            "--auth-type",
+            "exponentiation",
+            "--auth-type",
+            "elliptic-curve",
+        ])
+        .unwrap();
+        assert_eq!(
+            config.auth_types,
+            vec![
+                AuthenticationType::Exponentiation,
+                AuthenticationType::EllipticCurve
+            ]
+        );
+    }
+
+    // Settings that only work together must be checked after the flags are applied.
+    #[test_case(&["--tls-cert", "server.pem"]; "when TLS key is missing")]
+    #[test_case(&["--storage", "sqlite"]; "when storage path is missing")]
+    #[test_case(&["--auth-type", "exponentiation", "--auth-type", "exponentiation"]; "when authentication type is repeated")]
+    fn incomplete_flags_should_be_refused(args: &[&str]) {
+        assert!(matches!(server_config(args), Err(ConfigError::Invalid(_))));
+    }
+
+    // Only one source may be given for each password.
+    #[test_case(&["--password-file", "pw", "--password-stdin"], false; "when file and stdin are given")]
+    #[test_case(&["--password-stdin", "--password-env", "PW"], false; "when stdin and env are given")]
+    #[test_case(&["--new-password-file", "pw", "--new-password-env", "PW"], false; "when two new password sources are given")]
+    #[test_case(&["--password-stdin", "--new-password-stdin"], true; "when both passwords are read from stdin")]
+    #[test_case(&["--password-file", "pw", "--new-password-env", "PW"], true; "when each password has its own source")]
+    fn password_sources_should_be_exclusive(args: &[&str], accepted: bool) {
+        let cli = Cli::try_parse_from(
+            [
+                "acp",
+                "change-password",
+                "-s",
+                "127.0.0.1:50051",
+                "-u",
+                "alice",
+            ]
+            .iter()
+            .chain(args),
+        );
+        assert_eq!(cli.is_ok(), accepted);
+    }
+
+    // Opting out of the session cache cannot be combined with using it.
+    #[test_case(&["--no-session-cache"], true; "when the cache is not used")]
+    #[test_case(&["--new-session", "--session-cache", "sessions.toml"], true; "when a new session is cached")]
+    #[test_case(&["--no-session-cache", "--session-cache", "sessions.toml"], false; "when a cache file is given")]
+    #[test_case(&["--no-session-cache", "--new-session"], false; "when a new session is asked for")]
+    fn session_cache_flags_should_be_consistent(args: &[&str], accepted: bool) {
+        let cli = Cli::try_parse_from(
+            [
+                "acp",
+                "authenticate",
+                "-s",
+                "127.0.0.1:50051",
+                "-u",
+                "alice",
+            ]
+            .iter()
+            .chain(args),
+        );
+        assert_eq!(cli.is_ok(), accepted);
+    }
 }
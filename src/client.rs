@@ -1,67 +1,292 @@
-use crate::authentication::{get_authentication, Authenticate};
+use crate::authentication::{
+    ellipticcurve::EllipticCurve,
+    exponentiation::Exponentiation,
+    kdf::{KdfAlgorithm, KeyDerivation},
+    Authenticate, Transcript,
+};
+use crate::capabilities::{negotiate, Negotiated, PROTOCOL_VERSION};
+use crate::credentials::{Session, SessionCache};
 use crate::errors::{AuthenticationError, StatusAsError};
+use crate::secret::{SecretProvider, SecretPurpose};
+use crate::storage::unix_now;
 use crate::zkp_auth::auth_client::AuthClient;
 use crate::zkp_auth::{
-    AuthTypeRequest, AuthenticationAnswerRequest, AuthenticationChallengeRequest,
-    AuthenticationType, RegisterRequest,
+    AuthTypeRequest, AuthenticationAnswerRequest, AuthenticationAnswerResponse,
+    AuthenticationChallengeRequest, AuthenticationType, ChangeSecretChallengeRequest,
+    ChangeSecretRequest, GroupParameters, HelloRequest, KdfParametersRequest, NonInteractiveProof,
+    ProofMode, ProofNonceRequest, RegisterRequest, SessionRequest, SessionResponse,
+    TokenKeyRequest, TokenKeyResponse,
 };
-use num_bigint::BigUint; // For handling large integers in cryptographic operations
-use rpassword::prompt_password; // To securely prompt for password input
-use tonic::{transport::Channel, Request}; // Tonic for gRPC communication
+use tonic::{transport::Channel, Code, Request}; // Tonic for gRPC communication
 use tracing::{debug, info}; // For logging
+use zeroize::Zeroizing; // To wipe the secret and the nonce from memory once they have been used
 
-// Function to get the user's password securely, returns a BigUint representation
-fn get_password() -> Result<BigUint, AuthenticationError> {
-    match prompt_password("Enter password: ") {
-        Ok(password) => Ok(BigUint::from_bytes_be(password.trim().as_bytes())),
-        Err(_) => Err(AuthenticationError::CouldNotGetPassword),
-    }
+// Stretch a new password with the given key derivation function and a fresh salt, returning the
+// registration values y1 and y2 for the secret and the key derivation used
+fn registration_values(
+    auth: &dyn Authenticate,
+    password: &[u8],
+    algorithm: KdfAlgorithm,
+) -> Result<(Vec<u8>, Vec<u8>, KeyDerivation), AuthenticationError> {
+    let kdf = KeyDerivation::new(algorithm); // Choose a salt unique to this registration
+    let material = kdf.derive(password, auth.secret_length())?; // Stretch the password
+    let secret = auth.secret(&material); // Map the stretched password onto a secret scalar
+    let (y1, y2) = auth.registration(&secret)?; // Get the registration parameters for the secret
+    Ok((y1, y2, kdf))
 }
 
-// Get the authentication type from the server so that the client can match the type of auth
+// Say hello to the server and negotiate the protocol version and the options to use with it.
+// Servers that predate negotiation do not know the call, and are refused
+pub async fn hello(client: &mut AuthClient<Channel>) -> Result<Negotiated, AuthenticationError> {
+    let response = client
+        .hello(Request::new(HelloRequest {
+            protocol_version: PROTOCOL_VERSION,
+        }))
+        .await
+        .map_err(|s| match s.code() {
+            tonic::Code::Unimplemented => AuthenticationError::NoCommonCapability {
+                capability: "protocol version",
+                offered: "none".to_string(),
+            },
+            _ => s.map_status_to_err(),
+        })?
+        .into_inner();
+    let negotiated = negotiate(&response)?;
+    debug!(
+        "Negotiated protocol version {}",
+        negotiated.protocol_version
+    );
+    Ok(negotiated)
+}
+
+// Get the authentication type the user registered with from the server so that the client can
+// match the type of auth, along with the type the server prefers for new registrations and the
+// exponentiation group the server advertises
 async fn get_auth_type(
     client: &mut AuthClient<Channel>,
-) -> Result<AuthenticationType, AuthenticationError> {
+    user: &str,
+) -> Result<
+    (
+        AuthenticationType,
+        AuthenticationType,
+        Option<GroupParameters>,
+    ),
+    AuthenticationError,
+> {
     let response = client
-        .get_auth_type(Request::new(AuthTypeRequest {}))
+        .get_auth_type(Request::new(AuthTypeRequest {
+            user: user.to_string(),
+        }))
         .await
         .map_err(|_| AuthenticationError::UnableToGetAuthTypeFromServer)?
         .into_inner();
-    let e = AuthenticationType::from_i32(response.auth)
-        .ok_or_else(|| AuthenticationError::UnableToGetAuthTypeFromServer)?;
-    Ok(e)
+    let e = AuthenticationType::try_from(response.auth)
+        .map_err(|_| AuthenticationError::UnableToGetAuthTypeFromServer)?;
+    let preferred = AuthenticationType::try_from(response.preferred)
+        .map_err(|_| AuthenticationError::UnableToGetAuthTypeFromServer)?;
+    Ok((e, preferred, response.group))
+}
+
+// Build the authenticator of a type from what the server advertised. The exponentiation group
+// must have been advertised, match the fingerprint the client pinned if there is one, and pass
+// validation, so that a server cannot talk the client into a weak group
+fn advertised_authenticator(
+    auth: AuthenticationType,
+    group: Option<&GroupParameters>,
+    pinned_group: Option<&str>,
+) -> Result<Box<dyn Authenticate>, AuthenticationError> {
+    if auth == AuthenticationType::EllipticCurve {
+        return Ok(Box::new(EllipticCurve::new()));
+    }
+    let group = group.ok_or(AuthenticationError::MissingGroupParameters)?;
+    let exponentiation = Exponentiation::from(group.clone());
+    let actual = exponentiation.fingerprint();
+    if let Some(expected) = pinned_group {
+        if !expected.trim().eq_ignore_ascii_case(&actual) {
+            return Err(AuthenticationError::UntrustedGroup {
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+    }
+    exponentiation
+        .validate()
+        .map_err(AuthenticationError::InvalidGroupParameters)?;
+    debug!("Using the server group with fingerprint {}", actual);
+    Ok(Box::new(exponentiation))
+}
+
+// Get the key derivation the user registered with from the server, refusing parameters that are
+// too weak or that would make the client spend unbounded memory or time
+async fn get_kdf_parameters(
+    client: &mut AuthClient<Channel>,
+    user: &str,
+) -> Result<KeyDerivation, AuthenticationError> {
+    let response = client
+        .get_kdf_parameters(Request::new(KdfParametersRequest {
+            user: user.to_string(),
+        }))
+        .await
+        .map_err(|s| s.map_status_to_err())?
+        .into_inner();
+    let kdf = KeyDerivation::try_from(response.kdf.unwrap_or_default())?;
+    kdf.validate()?;
+    Ok(kdf)
+}
+
+// Check that a session is valid, returning its details
+pub async fn validate_session(
+    client: &mut AuthClient<Channel>,
+    session_id: &str,
+) -> Result<SessionResponse, AuthenticationError> {
+    let response = client
+        .validate_session(Request::new(SessionRequest {
+            session_id: session_id.to_string(),
+        }))
+        .await
+        .map_err(|s| s.map_status_to_err())?;
+    Ok(response.into_inner())
+}
+
+// Extend a valid session, returning its details with the new expiry
+pub async fn refresh_session(
+    client: &mut AuthClient<Channel>,
+    session_id: &str,
+) -> Result<SessionResponse, AuthenticationError> {
+    let response = client
+        .refresh_session(Request::new(SessionRequest {
+            session_id: session_id.to_string(),
+        }))
+        .await
+        .map_err(|s| s.map_status_to_err())?;
+    Ok(response.into_inner())
+}
+
+// End a session before it expires
+pub async fn revoke_session(
+    client: &mut AuthClient<Channel>,
+    session_id: &str,
+) -> Result<(), AuthenticationError> {
+    client
+        .revoke_session(Request::new(SessionRequest {
+            session_id: session_id.to_string(),
+        }))
+        .await
+        .map_err(|s| s.map_status_to_err())?;
+    Ok(())
+}
+
+// The cached session of the user on the server, if it has not expired and the server still accepts
+// it. A session the server no longer knows is forgotten, while one it accepts takes the expiry and
+// token the server gives it
+pub async fn reuse_session(
+    client: &mut AuthClient<Channel>,
+    cache: &mut SessionCache,
+    server: &str,
+    user: &str,
+) -> Result<Option<Session>, AuthenticationError> {
+    let Some(session) = cache.valid(server, user, unix_now()).cloned() else {
+        return Ok(None);
+    };
+    let response = match client
+        .validate_session(Request::new(SessionRequest {
+            session_id: session.id.clone(),
+        }))
+        .await
+    {
+        Ok(response) => response.into_inner(),
+        Err(status) if status.code() == Code::NotFound => {
+            info!("Cached session of user '{}' is no longer valid", user);
+            cache.remove(server, user);
+            return Ok(None);
+        }
+        Err(status) => return Err(status.map_status_to_err()),
+    };
+    let session = Session {
+        expires: response.expires,
+        token: (!response.token.is_empty())
+            .then_some(response.token)
+            .or(session.token),
+        ..session
+    };
+    info!("Reusing the cached session of user '{}'", user);
+    cache.insert(server, session.clone());
+    Ok(Some(session))
+}
+
+// The session started by a successful authentication, which is logged. The session id and the
+// token are both bearer credentials that stay valid until the session expires, so neither is logged,
+// only the expiry
+fn started_session(
+    user: &str,
+    auth_type: AuthenticationType,
+    response: AuthenticationAnswerResponse,
+) -> Session {
+    let session = Session::new(user, auth_type, response);
+    info!("Session received, expiring at {}", session.expires);
+    if session.token.is_some() {
+        info!("Session token received");
+    }
+    session
+}
+
+// Get the public key the server signs session tokens with
+pub async fn get_token_key(
+    client: &mut AuthClient<Channel>,
+) -> Result<TokenKeyResponse, AuthenticationError> {
+    let response = client
+        .get_token_key(Request::new(TokenKeyRequest {}))
+        .await
+        .map_err(|s| s.map_status_to_err())?;
+    Ok(response.into_inner())
 }
 
 // ClientRegistrar structure for handling user registration encapsulating the internal
-// authenticator
+// authenticator and the options negotiated with the server
 pub struct ClientRegistrar {
     authenticator: Box<dyn Authenticate>,
+    negotiated: Negotiated,
 }
 
 impl ClientRegistrar {
-    // Construct the ClientRegistrar, including requesting the auth type from the server
-    pub async fn new(client: &mut AuthClient<Channel>) -> Result<Self, AuthenticationError> {
-        let auth_type = get_auth_type(client).await?;
-        let authenticator = get_authentication(auth_type);
-        Ok(Self { authenticator })
+    // Construct the ClientRegistrar, including negotiating with the server, which picks the auth
+    // type it prefers most for new registrations, and requesting its group, which must have the
+    // pinned fingerprint if one is given
+    pub async fn new(
+        client: &mut AuthClient<Channel>,
+        pinned_group: Option<&str>,
+    ) -> Result<Self, AuthenticationError> {
+        let negotiated = hello(client).await?;
+        let (_, _, group) = get_auth_type(client, "").await?;
+        let authenticator =
+            advertised_authenticator(negotiated.auth_type(), group.as_ref(), pinned_group)?;
+        Ok(Self {
+            authenticator,
+            negotiated,
+        })
     }
 
-    // Register a user with the authentication server
+    // Register a user with the authentication server, stretching the password from the provider
+    // with the given key derivation function, or the best one both sides support, and a fresh salt
     pub async fn register(
         &self,
         user: &str,
+        algorithm: Option<KdfAlgorithm>,
+        password: &dyn SecretProvider,
         client: &mut AuthClient<Channel>,
     ) -> Result<bool, AuthenticationError> {
         info!("Registering user '{}' with authentication server", user);
 
-        let auth = &self.authenticator;
-        let password = get_password()?; // Securely get the user's password from the terminal
-        let (y1, y2) = auth.registration(&password); // Get the initial registration parameters
-                                                     // based on the password
+        let algorithm = self.negotiated.kdf(algorithm)?;
+
+        let password = password.password(SecretPurpose::Password)?; // Get the user's password
+        let (y1, y2, kdf) = registration_values(self.authenticator.as_ref(), &password, algorithm)?;
         let reg_request = RegisterRequest {
             user: user.to_string(),
-            y1: y1.to_bytes_be(),
-            y2: y2.to_bytes_be(),
+            y1: y1.clone(),
+            y2: y2.clone(),
+            kdf: Some((&kdf).into()),
+            auth: self.authenticator.auth_type() as i32,
         };
 
         debug!("Registering y1:{:?} and y2:{:?}", &y1, &y2);
@@ -74,40 +299,121 @@ impl ClientRegistrar {
     }
 }
 
+// A single authentication attempt's nonce k, wiped when dropped, and the commitments r1 and r2 to it
+struct Commitment {
+    k: Zeroizing<Vec<u8>>,
+    r1: Vec<u8>,
+    r2: Vec<u8>,
+}
+
 // ClientAuthenticator structure for handling user authentication, encapsulating the internal
-// authenticator and the nonce value k
+// authenticator of the type the user registered with, the authenticator a new password is
+// registered with when the server prefers another type, and the options negotiated with the
+// server. The nonce k is drawn fresh for every attempt, reusing it across two transcripts would
+// leak the secret
 pub struct ClientAuthenticator {
-    pub authenticator: Box<dyn Authenticate>,
-    pub k: BigUint,
+    authenticator: Box<dyn Authenticate>,
+    replacement: Option<Box<dyn Authenticate>>,
+    negotiated: Negotiated,
 }
 
 impl ClientAuthenticator {
-    // Construct the ClientAuthenticator, including requesting the auth type from the server
-    pub async fn new(client: &mut AuthClient<Channel>) -> Result<Self, AuthenticationError> {
-        let auth_type = get_auth_type(client).await?;
-        let authenticator = get_authentication(auth_type);
-        let k = authenticator.get_random(); // Generate the one time parameter k
-        Ok(Self { authenticator, k })
+    // Construct the ClientAuthenticator for a user, including negotiating with the server and
+    // requesting the auth type the user registered with and the group from the server, which must
+    // have the pinned fingerprint if one is given
+    pub async fn new(
+        client: &mut AuthClient<Channel>,
+        user: &str,
+        pinned_group: Option<&str>,
+    ) -> Result<Self, AuthenticationError> {
+        let negotiated = hello(client).await?;
+        let (auth_type, _, group) = get_auth_type(client, user).await?;
+        negotiated.require_auth_type(auth_type)?;
+        let preferred = negotiated.auth_type();
+        let authenticator = advertised_authenticator(auth_type, group.as_ref(), pinned_group)?;
+        let replacement = (preferred != auth_type)
+            .then(|| advertised_authenticator(preferred, group.as_ref(), pinned_group))
+            .transpose()?;
+        Ok(Self {
+            authenticator,
+            replacement,
+            negotiated,
+        })
+    }
+
+    // Generate the one time parameter k and the commitments r1 and r2 to it
+    fn commit(&self) -> Result<Commitment, AuthenticationError> {
+        let k = Zeroizing::new(self.authenticator.get_random());
+        let (r1, r2) = self.authenticator.authentication(&k)?;
+        Ok(Commitment { k, r1, r2 })
+    }
+
+    // Derive the user's secret from their password, with the salt and costs used at registration,
+    // which is wiped from memory once it is dropped
+    async fn get_secret(
+        &self,
+        user: &str,
+        password: &dyn SecretProvider,
+        client: &mut AuthClient<Channel>,
+    ) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        let auth = &self.authenticator;
+        let kdf = get_kdf_parameters(client, user).await?; // The salt and costs used at registration
+        let password = password.password(SecretPurpose::Password)?; // Get the user's password
+        let material = kdf.derive(&password, auth.secret_length())?; // Stretch the password
+        Ok(auth.secret(&material)) // Map the stretched password onto a secret scalar
     }
 
-    // Authenticating a user with the server
+    // Build a non-interactive proof of the secret, answering the challenge derived by hashing the
+    // transcript rather than one chosen by the server
+    fn prove(
+        &self,
+        user: &str,
+        secret: &[u8],
+        nonce: Vec<u8>,
+    ) -> Result<NonInteractiveProof, AuthenticationError> {
+        let auth = &self.authenticator;
+        let (y1, y2) = auth.registration(secret)?; // The values the server holds for the user
+        let Commitment { k, r1, r2 } = self.commit()?; // Fresh nonce and commitments
+        let c = auth.proof_challenge(&Transcript {
+            user,
+            y1: &y1,
+            y2: &y2,
+            r1: &r1,
+            r2: &r2,
+            nonce: &nonce,
+        })?;
+        let s = auth.response(&k, secret, &c)?; // Generate response to the derived challenge
+        Ok(NonInteractiveProof {
+            user: user.to_string(),
+            nonce,
+            r1,
+            r2,
+            s,
+        })
+    }
+
+    // Authenticating a user with the server, with the password from the provider, returning the
+    // session the server started
     pub async fn authenticate(
         &self,
         user: &str,
+        password: &dyn SecretProvider,
         client: &mut AuthClient<Channel>,
-    ) -> Result<bool, AuthenticationError> {
+    ) -> Result<Session, AuthenticationError> {
         info!("Authenticating user '{}' with authentication server", user);
 
+        self.negotiated.require_proof_mode(ProofMode::Interactive)?;
+
         let auth = &self.authenticator;
 
-        let password = get_password()?; // Securely get the user's password
+        let secret = self.get_secret(user, password, client).await?;
 
-        let (r1, r2) = auth.authentication(&self.k); // Generate authentication parameters
+        let Commitment { k, r1, r2 } = self.commit()?; // Fresh nonce and commitments
 
         let challenge_req = AuthenticationChallengeRequest {
             user: user.to_string(),
-            r1: r1.to_bytes_be(),
-            r2: r2.to_bytes_be(),
+            r1: r1.clone(),
+            r2: r2.clone(),
         };
 
         debug!("Authenticating r1:{:?} and r2:{:?}", &r1, &r2);
@@ -118,16 +424,17 @@ impl ClientAuthenticator {
             .map_err(|s| s.map_status_to_err())?
             .into_inner();
 
-        let c = BigUint::from_bytes_be(&challenge_response.c);
+        let c = challenge_response.c;
 
         info!("Authentication challenge received.");
         debug!("Received c {:?}", &c);
 
-        let s = auth.response(&self.k, &password, &c); // Generate response to the challenge
+        let s = auth.response(&k, &secret, &c)?; // Generate response to the challenge
+        drop(k); // The nonce must never be used again
 
         let answer_req = AuthenticationAnswerRequest {
             auth_id: challenge_response.auth_id,
-            s: s.to_bytes_be(),
+            s: s.clone(),
         };
 
         info!("Sending authentication challenge response.");
@@ -139,11 +446,223 @@ impl ClientAuthenticator {
             .await
             .map_err(|s| s.map_status_to_err())?; // Verify the challenge response with the server
 
+        Ok(started_session(
+            user,
+            self.authenticator.auth_type(),
+            verify_response.into_inner(),
+        ))
+    }
+
+    // Change the password of a user, proving knowledge of the current password from `current` by
+    // answering a challenge that the server binds to the registration of the new password from
+    // `new`. The new password is registered with the type the server prefers, which moves the user
+    // over to it, and stretched with the given key derivation function or the best one both sides
+    // support
+    pub async fn change_password(
+        &self,
+        user: &str,
+        algorithm: Option<KdfAlgorithm>,
+        current: &dyn SecretProvider,
+        new: &dyn SecretProvider,
+        client: &mut AuthClient<Channel>,
+    ) -> Result<bool, AuthenticationError> {
+        info!("Changing the password of user '{}'", user);
+
+        self.negotiated.require_proof_mode(ProofMode::Interactive)?;
+        let algorithm = self.negotiated.kdf(algorithm)?;
+
+        let auth = &self.authenticator;
+
+        let secret = self.get_secret(user, current, client).await?; // The current secret
+        let password = new.password(SecretPurpose::NewPassword)?;
+        let replacement = self.replacement.as_ref().unwrap_or(auth);
+        let (y1, y2, kdf) = registration_values(replacement.as_ref(), &password, algorithm)?;
+
+        let Commitment { k, r1, r2 } = self.commit()?; // Fresh nonce and commitments
+
+        let challenge_req = ChangeSecretChallengeRequest {
+            user: user.to_string(),
+            r1,
+            r2,
+            y1,
+            y2,
+            kdf: Some((&kdf).into()),
+            auth: replacement.auth_type() as i32,
+        };
+
+        let challenge_response = client
+            .create_change_secret_challenge(Request::new(challenge_req))
+            .await
+            .map_err(|s| s.map_status_to_err())?
+            .into_inner();
+
+        info!("Change password challenge received.");
+
+        let s = auth.response(&k, &secret, &challenge_response.c)?; // Answer with the current secret
+        drop(k); // The nonce must never be used again
+
+        client
+            .change_secret(Request::new(ChangeSecretRequest {
+                auth_id: challenge_response.auth_id,
+                s,
+            }))
+            .await
+            .map_err(|s| s.map_status_to_err())?;
+
+        Ok(true)
+    }
+
+    // Authenticating a user with the server using a single non-interactive proof, once the server
+    // has issued a nonce, with the password from the provider, returning the session the server
+    // started
+    pub async fn authenticate_non_interactive(
+        &self,
+        user: &str,
+        password: &dyn SecretProvider,
+        client: &mut AuthClient<Channel>,
+    ) -> Result<Session, AuthenticationError> {
         info!(
-            "Session id received {:?}",
-            &verify_response.into_inner().session_id
+            "Authenticating user '{}' with authentication server using a non-interactive proof",
+            user
         );
 
-        Ok(true)
+        self.negotiated
+            .require_proof_mode(ProofMode::NonInteractive)?;
+
+        let secret = self.get_secret(user, password, client).await?;
+
+        let nonce = client
+            .create_proof_nonce(Request::new(ProofNonceRequest {
+                user: user.to_string(),
+            }))
+            .await
+            .map_err(|s| s.map_status_to_err())?
+            .into_inner()
+            .nonce;
+
+        let proof = self.prove(user, &secret, nonce)?;
+
+        debug!("Sending proof {:?}", &proof);
+
+        let verify_response = client
+            .verify_proof(Request::new(proof))
+            .await
+            .map_err(|s| s.map_status_to_err())?; // Verify the proof with the server
+
+        Ok(started_session(
+            user,
+            self.authenticator.auth_type(),
+            verify_response.into_inner(),
+        ))
+    }
+}
+
+// Unit tests for the client side of the protocol.
+#[cfg(test)]
+mod tests {
+    use {
+        super::*, crate::authentication::exponentiation::GroupId, crate::capabilities::offer,
+        test_case::test_case,
+    };
+
+    // A client authenticator that negotiated everything a server of its own type offers.
+    fn client_authenticator(authenticator: Box<dyn Authenticate>) -> ClientAuthenticator {
+        let negotiated = negotiate(&offer(&[authenticator.auth_type()])).unwrap();
+        ClientAuthenticator {
+            authenticator,
+            replacement: None,
+            negotiated,
+        }
+    }
+
+    // Two attempts by the same authenticator must never share a nonce, which would be visible as
+    // repeated commitments.
+    #[test_case(Box::new(EllipticCurve::new()); "when using elliptic curve")]
+    #[test_case(Box::new(Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256)); "when using exponentiation")]
+    fn each_attempt_should_use_a_fresh_nonce(authenticator: Box<dyn Authenticate>) {
+        let client = client_authenticator(authenticator);
+        let first = client.commit().unwrap();
+        let second = client.commit().unwrap();
+        assert_ne!(*first.k, *second.k, "Nonce was reused");
+        assert_ne!(first.r1, second.r1, "Commitment r1 was reused");
+        assert_ne!(first.r2, second.r2, "Commitment r2 was reused");
+    }
+
+    // A proof must verify offline against the registration, and only for the user and nonce it
+    // was made for.
+    #[test_case(Box::new(EllipticCurve::new()); "when using elliptic curve")]
+    #[test_case(Box::new(Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256)); "when using exponentiation")]
+    fn proofs_should_verify_only_for_their_transcript(authenticator: Box<dyn Authenticate>) {
+        let client = client_authenticator(authenticator);
+        let auth = &client.authenticator;
+        let secret = auth.secret(b"correct horse battery staple");
+        let (y1, y2) = auth.registration(&secret).unwrap();
+        let proof = client.prove("alice", &secret, vec![7u8; 32]).unwrap();
+        let transcript = Transcript {
+            user: "alice",
+            y1: &y1,
+            y2: &y2,
+            r1: &proof.r1,
+            r2: &proof.r2,
+            nonce: &proof.nonce,
+        };
+        assert!(auth.verify_proof(&transcript, &proof.s).unwrap());
+        let other_user = Transcript {
+            user: "bob",
+            ..transcript
+        };
+        assert!(!auth.verify_proof(&other_user, &proof.s).unwrap());
+        let other_nonce = Transcript {
+            nonce: &[8u8; 32],
+            ..transcript
+        };
+        assert!(!auth.verify_proof(&other_nonce, &proof.s).unwrap());
+        let wrong_secret = auth.secret(b"hunter2");
+        let forged = client.prove("alice", &wrong_secret, vec![7u8; 32]).unwrap();
+        let forged_transcript = Transcript {
+            r1: &forged.r1,
+            r2: &forged.r2,
+            ..transcript
+        };
+        assert!(!auth.verify_proof(&forged_transcript, &forged.s).unwrap());
+    }
+
+    // Only a valid advertised group matching the pinned fingerprint, if any, must be used.
+    #[test]
+    fn advertised_groups_should_be_checked() {
+        let exponentiation = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256);
+        let group = GroupParameters::from(&exponentiation);
+        let fingerprint = exponentiation.fingerprint();
+        let build = |group: Option<&GroupParameters>, pinned: Option<&str>| {
+            advertised_authenticator(AuthenticationType::Exponentiation, group, pinned)
+        };
+
+        assert!(build(Some(&group), None).is_ok());
+        assert!(build(Some(&group), Some(&fingerprint.to_uppercase())).is_ok());
+        assert!(matches!(
+            build(None, None),
+            Err(AuthenticationError::MissingGroupParameters)
+        ));
+        let other = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q224).fingerprint();
+        assert!(matches!(
+            build(Some(&group), Some(&other)),
+            Err(AuthenticationError::UntrustedGroup { .. })
+        ));
+        let weak = GroupParameters {
+            q: vec![0x03, 0xfb],
+            ..group.clone()
+        };
+        assert!(matches!(
+            build(Some(&weak), None),
+            Err(AuthenticationError::InvalidGroupParameters(_))
+        ));
+
+        // Elliptic curve authentication has no group to advertise
+        let elliptic_curve =
+            advertised_authenticator(AuthenticationType::EllipticCurve, None, Some(&fingerprint));
+        assert_eq!(
+            elliptic_curve.unwrap().auth_type(),
+            AuthenticationType::EllipticCurve
+        );
     }
 }
@@ -0,0 +1,453 @@
+use crate::authentication::exponentiation::{Exponentiation, GroupId};
+use crate::errors::{ConfigError, ParameterError, StorageError, TlsError};
+use crate::ratelimit::{
+    RateLimitPolicy, DEFAULT_BACKOFF_BASE_SECS, DEFAULT_BACKOFF_MAX_SECS, DEFAULT_FREE_FAILURES,
+    DEFAULT_LOCKOUT_FAILURES, DEFAULT_LOCKOUT_SECS,
+};
+use crate::server::{
+    ChallengePolicy, DEFAULT_CHALLENGE_CAPACITY, DEFAULT_CHALLENGE_TTL_SECS,
+    DEFAULT_SESSION_TTL_SECS,
+};
+use crate::storage::{self, RegistrationStore, SessionStore, StorageKind};
+use crate::tls;
+use crate::zkp_auth::AuthenticationType;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tonic::transport::ServerTlsConfig;
+
+// Default address the server binds when none is configured
+pub const DEFAULT_BIND: &str = "0.0.0.0:8080";
+
+// Serialise the enums selected on the command line by the names they are given there, so that the
+// configuration file and the flags take the same values
+pub(crate) mod value_name {
+    use clap::ValueEnum;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: ValueEnum, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let value = value
+            .to_possible_value()
+            .expect("Configurable values are never skipped");
+        serializer.serialize_str(value.get_name())
+    }
+
+    pub fn deserialize<'de, T: ValueEnum, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        T::from_str(&name, false).map_err(D::Error::custom)
+    }
+}
+
+// Serialise lists of those enums by their names in the same way
+mod value_names {
+    use clap::ValueEnum;
+    use serde::{de::Error, ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: ValueEnum, S: Serializer>(
+        values: &[T],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for value in values {
+            let value = value
+                .to_possible_value()
+                .expect("Configurable values are never skipped");
+            seq.serialize_element(value.get_name())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, T: ValueEnum, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<T>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|name| T::from_str(name, false).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+// The configuration of the server, read from a TOML file and overridden by command line flags.
+// Every setting has a default, so a file only needs the settings that differ from it. The first of
+// the authentication types served is the one new registrations use by default.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: Vec<SocketAddr>,
+    #[serde(with = "value_names")]
+    pub auth_types: Vec<AuthenticationType>,
+    pub group: GroupConfig,
+    pub storage: StorageConfig,
+    pub challenges: ChallengeConfig,
+    pub sessions: SessionConfig,
+    pub rate_limit: RateLimitConfig,
+    pub tls: TlsConfig,
+    pub log: LogConfig,
+}
+
+// The exponentiation group, a parameter file taking precedence over a standardised group
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroupConfig {
+    #[serde(with = "value_name")]
+    pub id: GroupId,
+    pub params: Option<PathBuf>,
+}
+
+// Where registrations and sessions are kept
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    #[serde(with = "value_name")]
+    pub kind: StorageKind,
+    pub path: Option<PathBuf>,
+}
+
+// How long challenges can be answered for and how many are kept at once
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChallengeConfig {
+    pub ttl_secs: u64,
+    pub capacity: u64,
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        ChallengeConfig {
+            ttl_secs: DEFAULT_CHALLENGE_TTL_SECS,
+            capacity: DEFAULT_CHALLENGE_CAPACITY,
+        }
+    }
+}
+
+// How long sessions last and the key their tokens are signed with, if tokens are issued
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub ttl_secs: u64,
+    pub token_key: Option<PathBuf>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            ttl_secs: DEFAULT_SESSION_TTL_SECS,
+            token_key: None,
+        }
+    }
+}
+
+// The limits on failed authentication attempts
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub free_failures: u32,
+    pub backoff_base_secs: u64,
+    pub backoff_max_secs: u64,
+    pub lockout_failures: u32,
+    pub lockout_secs: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            free_failures: DEFAULT_FREE_FAILURES,
+            backoff_base_secs: DEFAULT_BACKOFF_BASE_SECS,
+            backoff_max_secs: DEFAULT_BACKOFF_MAX_SECS,
+            lockout_failures: DEFAULT_LOCKOUT_FAILURES,
+            lockout_secs: DEFAULT_LOCKOUT_SECS,
+        }
+    }
+}
+
+// The certificate and key to serve TLS with, and the CA client certificates are checked against
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub ca: Option<PathBuf>,
+    pub client_cert_optional: bool,
+}
+
+// The most detailed level of log messages written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+// How log messages are laid out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    // One line per message with its timestamp, level and target
+    #[default]
+    Full,
+    // One shorter line per message
+    Compact,
+    // Several lines per message, for reading by eye
+    Pretty,
+}
+
+// How the server logs
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    #[serde(with = "value_name")]
+    pub level: LogLevel,
+    #[serde(with = "value_name")]
+    pub format: LogFormat,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: vec![DEFAULT_BIND.parse().expect("The default address is valid")],
+            auth_types: vec![AuthenticationType::Exponentiation],
+            group: GroupConfig::default(),
+            storage: StorageConfig::default(),
+            challenges: ChallengeConfig::default(),
+            sessions: SessionConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            tls: TlsConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    // Parse a configuration from TOML, settings that are left out keep their defaults.
+    pub fn from_toml(contents: &str) -> Result<Self, ConfigError> {
+        toml::from_str(contents).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    // Read a configuration from a TOML file.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_toml(&contents)
+    }
+
+    // Serialise the configuration as TOML that `from_toml` reads back.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Configurations always serialise")
+    }
+
+    // Refuse settings that only make sense together when some of them are missing
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Invalid(message.to_string()));
+        if self.bind.is_empty() {
+            return invalid("at least one bind address is required");
+        }
+        if self.auth_types.is_empty() {
+            return invalid("at least one authentication type is required");
+        }
+        if (1..self.auth_types.len()).any(|i| self.auth_types[..i].contains(&self.auth_types[i])) {
+            return invalid("each authentication type can only be served once");
+        }
+        if self.tls.cert.is_some() != self.tls.key.is_some() {
+            return invalid("the TLS certificate and key must be given together");
+        }
+        if self.tls.ca.is_some() && self.tls.cert.is_none() {
+            return invalid("client certificates can only be checked when serving over TLS");
+        }
+        if self.tls.client_cert_optional && self.tls.ca.is_none() {
+            return invalid("optional client certificates need a CA to check them against");
+        }
+        if self.storage.kind != StorageKind::Memory && self.storage.path.is_none() {
+            return invalid("the file and sqlite stores need a storage path");
+        }
+        Ok(())
+    }
+
+    // Build the exponentiation authenticator from the parameter file, refusing parameters that
+    // fail validation, or from the standardised group
+    pub fn exponentiation(&self) -> Result<Exponentiation, ParameterError> {
+        match &self.group.params {
+            Some(path) => {
+                let exponentiation = Exponentiation::from_file(path)?;
+                exponentiation.validate()?;
+                Ok(exponentiation)
+            }
+            None => Ok(Exponentiation::with_group(self.group.id)),
+        }
+    }
+
+    // Open the configured registration store
+    pub fn open_registrations(&self) -> Result<Box<dyn RegistrationStore>, StorageError> {
+        storage::open(self.storage.kind, self.storage.path.as_deref())
+    }
+
+    // Open the session store that goes with the configured registration store
+    pub fn open_sessions(&self) -> Result<Box<dyn SessionStore>, StorageError> {
+        storage::open_sessions(self.storage.kind, self.storage.path.as_deref())
+    }
+
+    // The limits on outstanding challenges
+    pub fn challenge_policy(&self) -> ChallengePolicy {
+        ChallengePolicy {
+            ttl: Duration::from_secs(self.challenges.ttl_secs),
+            capacity: self.challenges.capacity,
+        }
+    }
+
+    // How long a session lasts
+    pub fn session_ttl(&self) -> Duration {
+        Duration::from_secs(self.sessions.ttl_secs)
+    }
+
+    // The limits on failed authentication attempts
+    pub fn rate_limit_policy(&self) -> RateLimitPolicy {
+        RateLimitPolicy {
+            free_failures: self.rate_limit.free_failures,
+            backoff_base: Duration::from_secs(self.rate_limit.backoff_base_secs),
+            backoff_max: Duration::from_secs(self.rate_limit.backoff_max_secs),
+            lockout_failures: self.rate_limit.lockout_failures,
+            lockout: Duration::from_secs(self.rate_limit.lockout_secs),
+        }
+    }
+
+    // The TLS configuration of the server, or none when serving in plaintext
+    pub fn tls_config(&self) -> Result<Option<ServerTlsConfig>, TlsError> {
+        match (&self.tls.cert, &self.tls.key) {
+            (Some(cert), Some(key)) => Ok(Some(tls::server_config(
+                cert,
+                key,
+                self.tls.ca.as_deref(),
+                self.tls.client_cert_optional,
+            )?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+// Unit tests for reading and writing server configurations.
+#[cfg(test)]
+mod tests {
+    use {super::*, test_case::test_case};
+
+    // The printed configuration must read back as the same configuration.
+    #[test]
+    fn configurations_should_round_trip() {
+        let config = ServerConfig {
+            bind: vec![
+                "127.0.0.1:9000".parse().unwrap(),
+                "[::1]:9000".parse().unwrap(),
+            ],
+            auth_types: vec![
+                AuthenticationType::EllipticCurve,
+                AuthenticationType::Exponentiation,
+            ],
+            group: GroupConfig {
+                id: GroupId::Rfc5114Modp2048Q256,
+                params: None,
+            },
+            storage: StorageConfig {
+                kind: StorageKind::Sqlite,
+                path: Some("registrations.db".into()),
+            },
+            tls: TlsConfig {
+                cert: Some("server.pem".into()),
+                key: Some("server.key".into()),
+                ..TlsConfig::default()
+            },
+            log: LogConfig {
+                level: LogLevel::Debug,
+                format: LogFormat::Compact,
+            },
+            ..ServerConfig::default()
+        };
+        assert_eq!(ServerConfig::from_toml(&config.to_toml()).unwrap(), config);
+        assert_eq!(
+            ServerConfig::from_toml(&ServerConfig::default().to_toml()).unwrap(),
+            ServerConfig::default()
+        );
+    }
+
+    // Settings left out of a file must keep their defaults, and values must use the names the
+    // command line flags take.
+    #[test]
+    fn files_should_only_need_the_settings_that_differ() {
+        let config = ServerConfig::from_toml(
+            r#"
+            bind = ["127.0.0.1:9000"]
+            auth_types = ["elliptic-curve", "exponentiation"]
+
+            [group]
+            id = "rfc5114-2048-224"
+
+            [rate_limit]
+            lockout_secs = 60
+
+            [log]
+            level = "warn"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.bind, vec!["127.0.0.1:9000".parse().unwrap()]);
+        assert_eq!(
+            config.auth_types,
+            vec![
+                AuthenticationType::EllipticCurve,
+                AuthenticationType::Exponentiation
+            ]
+        );
+        assert_eq!(config.group.id, GroupId::Rfc5114Modp2048Q224);
+        assert_eq!(config.rate_limit_policy().lockout, Duration::from_secs(60));
+        assert_eq!(
+            config.rate_limit.free_failures,
+            RateLimitConfig::default().free_failures
+        );
+        assert_eq!(config.log.level, LogLevel::Warn);
+        assert_eq!(config.storage, StorageConfig::default());
+    }
+
+    // Misspelt settings and unknown values must be refused rather than silently ignored.
+    #[test_case("port = 8080"; "when setting is unknown")]
+    #[test_case("[sessions]\nttl = 60"; "when nested setting is unknown")]
+    #[test_case("[storage]\nkind = \"postgres\""; "when value is unknown")]
+    #[test_case("auth_types = [\"rsa\"]"; "when authentication type is unknown")]
+    #[test_case("bind = [\"localhost\"]"; "when address is invalid")]
+    fn invalid_files_should_be_refused(contents: &str) {
+        assert!(matches!(
+            ServerConfig::from_toml(contents),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    // Settings that only work together must be refused when some are missing.
+    #[test_case("bind = []"; "when there is no bind address")]
+    #[test_case("auth_types = []"; "when there is no authentication type")]
+    #[test_case("auth_types = [\"exponentiation\", \"exponentiation\"]"; "when authentication type is repeated")]
+    #[test_case("[tls]\ncert = \"server.pem\""; "when TLS key is missing")]
+    #[test_case("[tls]\nca = \"ca.pem\""; "when CA is given without TLS")]
+    #[test_case("[tls]\ncert = \"s.pem\"\nkey = \"s.key\"\nclient_cert_optional = true"; "when optional client certificates have no CA")]
+    #[test_case("[storage]\nkind = \"sqlite\""; "when storage path is missing")]
+    fn incomplete_settings_should_be_refused(contents: &str) {
+        let config = ServerConfig::from_toml(contents).unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    // A missing file must be reported with its path.
+    #[test]
+    fn missing_files_should_be_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = ServerConfig::from_file(&dir.path().join("acp.toml")).unwrap_err();
+        assert!(error.to_string().contains("acp.toml"));
+    }
+}
@@ -0,0 +1,373 @@
+use crate::config::value_name;
+use crate::errors::CredentialsError;
+use crate::zkp_auth::{AuthenticationAnswerResponse, AuthenticationType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+// A session the client started by authenticating, with its expiry in seconds since the Unix epoch
+// and the signed token when the server issues them
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Session {
+    pub id: String,
+    pub user: String,
+    #[serde(with = "value_name")]
+    pub auth_type: AuthenticationType,
+    pub expires: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl Session {
+    // The session the server started for the user when it accepted their proof
+    pub fn new(
+        user: &str,
+        auth_type: AuthenticationType,
+        response: AuthenticationAnswerResponse,
+    ) -> Self {
+        Session {
+            id: response.session_id,
+            user: user.to_string(),
+            auth_type,
+            expires: response.expires,
+            token: (!response.token.is_empty()).then_some(response.token),
+        }
+    }
+
+    // Whether the session has expired at the time `now`
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires <= now
+    }
+
+    // Parse a session from a TOML credentials file's contents
+    pub fn from_toml(contents: &str) -> Result<Self, CredentialsError> {
+        toml::from_str(contents).map_err(|e| CredentialsError::Parse(e.to_string()))
+    }
+
+    // Serialise the session as TOML that `from_toml` reads back
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Sessions always serialise")
+    }
+
+    // Read a session from a credentials file
+    pub fn read(path: &Path) -> Result<Self, CredentialsError> {
+        Self::from_toml(&read_credentials(path)?)
+    }
+
+    // Write the session to a credentials file that only the user can read
+    pub fn write(&self, path: &Path) -> Result<(), CredentialsError> {
+        write_credentials(path, &self.to_toml())
+    }
+}
+
+// The sessions the client started, kept in a TOML file readable only by the user as a table for each
+// server address holding the session of each user on it, so that later commands and other tooling
+// can use them without authenticating again
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SessionCache {
+    path: PathBuf,
+    servers: BTreeMap<String, BTreeMap<String, Session>>,
+}
+
+impl SessionCache {
+    // Where the cache is kept by default, `acp/sessions.toml` in the user's configuration directory
+    pub fn default_path() -> Result<PathBuf, CredentialsError> {
+        let config = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME")
+                    .filter(|home| !home.is_empty())
+                    .map(|home| Path::new(&home).join(".config"))
+            })
+            .ok_or(CredentialsError::NoConfigDirectory)?;
+        Ok(config.join("acp").join("sessions.toml"))
+    }
+
+    // Open the cache kept in the file, which is empty until the file is first saved
+    pub fn open(path: &Path) -> Result<Self, CredentialsError> {
+        let servers = match fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|e| CredentialsError::Parse(e.to_string()))?
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(source) => {
+                return Err(CredentialsError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+        Ok(SessionCache {
+            path: path.to_path_buf(),
+            servers,
+        })
+    }
+
+    // The file the cache is kept in
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The session of the user on the server, whether or not it has expired
+    pub fn get(&self, server: &str, user: &str) -> Option<&Session> {
+        self.servers.get(server)?.get(user)
+    }
+
+    // The session of the user on the server if it has not expired at the time `now`
+    pub fn valid(&self, server: &str, user: &str, now: u64) -> Option<&Session> {
+        self.get(server, user)
+            .filter(|session| !session.is_expired(now))
+    }
+
+    // Keep the session as the one of its user on the server, replacing any earlier one
+    pub fn insert(&mut self, server: &str, session: Session) {
+        self.servers
+            .entry(server.to_string())
+            .or_default()
+            .insert(session.user.clone(), session);
+    }
+
+    // Forget the session of the user on the server, returning it if there was one
+    pub fn remove(&mut self, server: &str, user: &str) -> Option<Session> {
+        let sessions = self.servers.get_mut(server)?;
+        let session = sessions.remove(user);
+        if sessions.is_empty() {
+            self.servers.remove(server);
+        }
+        session
+    }
+
+    // Forget the sessions that match, on the server and of the user when they are given and only
+    // the expired ones at the time `now` when it is given, returning how many were forgotten
+    pub fn clear(&mut self, server: Option<&str>, user: Option<&str>, now: Option<u64>) -> usize {
+        let mut cleared = 0;
+        self.servers.retain(|address, sessions| {
+            if server.is_some_and(|server| server != address) {
+                return true;
+            }
+            sessions.retain(|name, session| {
+                let matches = user.is_none_or(|user| user == name)
+                    && now.is_none_or(|now| session.is_expired(now));
+                cleared += usize::from(matches);
+                !matches
+            });
+            !sessions.is_empty()
+        });
+        cleared
+    }
+
+    // Every session in the cache with the server it was started on, ordered by server and user
+    pub fn sessions(&self) -> impl Iterator<Item = (&str, &Session)> {
+        self.servers.iter().flat_map(|(server, sessions)| {
+            sessions
+                .values()
+                .map(move |session| (server.as_str(), session))
+        })
+    }
+
+    // Write the cache to its file, creating the directory it is in if needed
+    pub fn save(&self) -> Result<(), CredentialsError> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let mut builder = DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            builder.create(dir).map_err(|source| CredentialsError::Io {
+                path: dir.to_path_buf(),
+                source,
+            })?;
+        }
+        let contents = toml::to_string(&self.servers).expect("Sessions always serialise");
+        write_credentials(&self.path, &contents)
+    }
+}
+
+// Read a credentials file
+fn read_credentials(path: &Path) -> Result<String, CredentialsError> {
+    fs::read_to_string(path).map_err(|source| CredentialsError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// Replace a credentials file with the contents, writing them to a file only the user can read
+// first so that the old contents stay in place if writing fails
+fn write_credentials(path: &Path, contents: &str) -> Result<(), CredentialsError> {
+    let io = |source| CredentialsError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let partial = path.with_extension("partial");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&partial).map_err(io)?;
+    file.write_all(contents.as_bytes()).map_err(io)?;
+    file.sync_data().map_err(io)?;
+    fs::rename(&partial, path).map_err(io)
+}
+
+// Unit tests for reading and writing credentials files
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::storage::tests::temporary_path, test_case::test_case};
+
+    // A session with a token or without one
+    fn session(token: Option<&str>) -> Session {
+        Session {
+            id: "0123456789abcdef".to_string(),
+            user: "alice".to_string(),
+            auth_type: AuthenticationType::EllipticCurve,
+            expires: 1_700_000_000,
+            token: token.map(str::to_string),
+        }
+    }
+
+    // A session must be read back from the credentials file it was written to, which only the user
+    // can read.
+    #[test_case(Some("header.claims.signature"); "when the server issues tokens")]
+    #[test_case(None; "when the server does not issue tokens")]
+    fn sessions_should_be_written_and_read_back(token: Option<&str>) {
+        let (_dir, path) = temporary_path("credentials.toml");
+        session(token).write(&path).unwrap();
+        assert_eq!(Session::read(&path).unwrap(), session(token));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    // The response of the server must be turned into a session, an empty token meaning none.
+    #[test]
+    fn responses_should_become_sessions() {
+        let response = AuthenticationAnswerResponse {
+            session_id: "0123456789abcdef".to_string(),
+            expires: 1_700_000_000,
+            token: String::new(),
+        };
+        let session = Session::new("alice", AuthenticationType::EllipticCurve, response);
+        assert_eq!(session, self::session(None));
+        assert!(!session.is_expired(1_699_999_999));
+        assert!(session.is_expired(1_700_000_000));
+    }
+
+    // Files that are missing or are not sessions must be refused.
+    #[test]
+    fn unreadable_credentials_should_be_refused() {
+        let (_dir, path) = temporary_path("credentials.toml");
+        assert!(matches!(
+            Session::read(&path),
+            Err(CredentialsError::Io { .. })
+        ));
+        fs::write(&path, "id = \"0123\"\nuser = \"alice\"\n").unwrap();
+        assert!(matches!(
+            Session::read(&path),
+            Err(CredentialsError::Parse(_))
+        ));
+    }
+
+    // A session of the user expiring at the time given
+    fn user_session(user: &str, expires: u64) -> Session {
+        Session {
+            user: user.to_string(),
+            expires,
+            ..session(None)
+        }
+    }
+
+    // Sessions must be kept for each server and user, in a file that only the user can read and
+    // that is created with its directory when the cache is first saved.
+    #[test]
+    fn cached_sessions_should_be_saved_and_reopened() {
+        let (dir, _) = temporary_path("unused");
+        let path = dir.path().join("acp").join("sessions.toml");
+        let mut cache = SessionCache::open(&path).unwrap();
+        assert_eq!(cache.sessions().count(), 0);
+
+        cache.insert("127.0.0.1:8080", user_session("alice", 100));
+        cache.insert("127.0.0.1:8080", user_session("bob", 100));
+        cache.insert("127.0.0.1:9090", user_session("alice", 200));
+        cache.insert("127.0.0.1:8080", user_session("alice", 300));
+        cache.save().unwrap();
+
+        let reopened = SessionCache::open(&path).unwrap();
+        assert_eq!(reopened, cache);
+        assert_eq!(
+            reopened.get("127.0.0.1:8080", "alice"),
+            Some(&user_session("alice", 300))
+        );
+        assert_eq!(reopened.get("127.0.0.1:9090", "bob"), None);
+        let servers: Vec<_> = reopened
+            .sessions()
+            .map(|(server, session)| (server, session.user.as_str()))
+            .collect();
+        assert_eq!(
+            servers,
+            vec![
+                ("127.0.0.1:8080", "alice"),
+                ("127.0.0.1:8080", "bob"),
+                ("127.0.0.1:9090", "alice")
+            ]
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    // Only sessions that have not expired must be offered for reuse.
+    #[test]
+    fn expired_sessions_should_not_be_valid() {
+        let mut cache = SessionCache::default();
+        cache.insert("127.0.0.1:8080", user_session("alice", 100));
+        assert!(cache.valid("127.0.0.1:8080", "alice", 99).is_some());
+        assert!(cache.valid("127.0.0.1:8080", "alice", 100).is_none());
+        assert!(cache.valid("127.0.0.1:8080", "bob", 99).is_none());
+        assert_eq!(
+            cache.remove("127.0.0.1:8080", "alice"),
+            Some(user_session("alice", 100))
+        );
+        assert_eq!(cache.sessions().count(), 0);
+    }
+
+    // Clearing must forget the sessions matching every filter that is given.
+    #[test_case(None, None, None, 0; "when nothing is given")]
+    #[test_case(Some("127.0.0.1:8080"), None, None, 1; "when the server is given")]
+    #[test_case(None, Some("alice"), None, 1; "when the user is given")]
+    #[test_case(Some("127.0.0.1:9090"), Some("bob"), None, 3; "when nothing matches")]
+    #[test_case(None, None, Some(150), 2; "when only expired sessions are cleared")]
+    fn clearing_should_forget_matching_sessions(
+        server: Option<&str>,
+        user: Option<&str>,
+        now: Option<u64>,
+        kept: usize,
+    ) {
+        let mut cache = SessionCache::default();
+        cache.insert("127.0.0.1:8080", user_session("alice", 100));
+        cache.insert("127.0.0.1:8080", user_session("bob", 200));
+        cache.insert("127.0.0.1:9090", user_session("alice", 200));
+        assert_eq!(cache.clear(server, user, now), 3 - kept);
+        assert_eq!(cache.sessions().count(), kept);
+    }
+
+    // Corrupt caches must be refused rather than overwritten.
+    #[test]
+    fn corrupt_caches_should_be_refused() {
+        let (_dir, path) = temporary_path("sessions.toml");
+        fs::write(&path, "not a cache").unwrap();
+        assert!(matches!(
+            SessionCache::open(&path),
+            Err(CredentialsError::Parse(_))
+        ));
+    }
+}
@@ -10,7 +10,7 @@ impl StatusAsError for Status {
     fn map_status_to_err(&self) -> AuthenticationError {
         // Specifically maps to UnableToAuthenticateWithServer variant, including the status
         AuthenticationError::UnableToAuthenticateWithServer {
-            status: self.clone(),
+            status: Box::new(self.clone()),
         }
     }
 }
@@ -24,6 +24,29 @@ pub enum AcpError {
     // Storage error variant, encapsulating a StorageError
     #[error("Storage error: {0}")]
     Storage(#[from] StorageError),
+    // Group parameter error variant, encapsulating a ParameterError
+    #[error("Parameter error: {0}")]
+    Parameter(#[from] ParameterError),
+    // Session token error variant, encapsulating a TokenError
+    #[error("Token error: {0}")]
+    Token(#[from] TokenError),
+    // Rate limit error variant, encapsulating a RateLimitError
+    #[error("Rate limited: {0}")]
+    RateLimit(#[from] RateLimitError),
+}
+
+// Convert an error into the gRPC status returned to clients, values the client sent that cannot be
+// used are the client's concern, anything else is a failure of the server
+impl From<AcpError> for Status {
+    fn from(error: AcpError) -> Self {
+        match error {
+            AcpError::Authentication(error) => Status::invalid_argument(error.to_string()),
+            AcpError::Storage(error) => error.into(),
+            AcpError::Parameter(error) => Status::internal(error.to_string()),
+            AcpError::Token(error) => Status::internal(error.to_string()),
+            AcpError::RateLimit(error) => error.into(),
+        }
+    }
 }
 
 // Define authentication error variants
@@ -31,22 +54,226 @@ pub enum AcpError {
 pub enum AuthenticationError {
     // Error variant for authentication issues, includes tonic::Status for more context
     #[error("Unable to authenticate with server: {status}")]
-    UnableToAuthenticateWithServer { status: tonic::Status },
+    UnableToAuthenticateWithServer { status: Box<tonic::Status> },
     // Error variant for issues retrieving passwords from user entries
     #[error("Could not get password from user entry")]
     CouldNotGetPassword,
+    // Error variant for a password source, such as a file, that could not be read
+    #[error("Could not read the password from {from}: {reason}")]
+    CouldNotReadPassword { from: String, reason: String },
+    // Error variant for a new password that was not entered the same way twice
+    #[error("Passwords do not match")]
+    PasswordsDoNotMatch,
     // Error variant for failures in getting the authentication type from the server
     #[error("Unable to get the authentication type from the server")]
     UnableToGetAuthTypeFromServer,
+    // Error variant for an authentication type the server does not serve
+    #[error("Authentication type {0} is not served")]
+    UnsupportedAuthType(String),
+    // Error variant for a server that serves exponentiation without advertising its group
+    #[error("The server did not advertise its group parameters")]
+    MissingGroupParameters,
+    // Error variant for group parameters advertised by the server that fail validation
+    #[error("The server group parameters are invalid: {0}")]
+    InvalidGroupParameters(#[source] ParameterError),
+    // Error variant for a capability, such as the protocol version, for which the client cannot use
+    // any of the options the server offers
+    #[error("No {capability} is supported by both client and server, the server offers {offered}")]
+    NoCommonCapability {
+        capability: &'static str,
+        offered: String,
+    },
+    // Error variant for a server group that does not match the fingerprint the client pinned
+    #[error("The server group has fingerprint {actual} but {expected} was expected")]
+    UntrustedGroup { expected: String, actual: String },
+    // Error variant for bytes that are not the canonical encoding of a group element
+    #[error("Invalid group element encoding")]
+    InvalidElementEncoding,
+    // Error variant for an element that is the identity or outside the prime order subgroup
+    #[error("Group element is the identity or not in the prime order subgroup")]
+    InvalidElement,
+    // Error variant for bytes that are not the canonical encoding of a scalar
+    #[error("Invalid scalar encoding")]
+    InvalidScalarEncoding,
+    // Error variant for key derivation parameters that are missing, too weak or too expensive
+    #[error("Invalid key derivation parameters: {0}")]
+    InvalidKdfParameters(String),
+    // Error variant for a key derivation function that failed to run
+    #[error("Key derivation failed: {0}")]
+    KeyDerivationFailed(String),
 }
 
 // Define storage error variants
 #[derive(Error, Debug)]
 pub enum StorageError {
     // Error variant for failing to find a challenge
-    #[error("Unable to find challange")]
+    #[error("Unable to find challenge")]
     UnableToFindChallenge,
+    // Error variant for failing to find an unexpired session
+    #[error("Unable to find session")]
+    UnableToFindSession,
     // Error variant for failing to find a registration
     #[error("Unable to find registration")]
     UnableToFindRegistration,
+    // Error variant for registering a user that has already registered
+    #[error("User is already registered")]
+    AlreadyRegistered,
+    // Error variant for replacing a registration that changed since it was read
+    #[error("Registration has changed")]
+    RegistrationChanged,
+    // Error variant for a file based store that was selected without a path
+    #[error("A path is required for this kind of storage")]
+    MissingPath,
+    // Error variant for failing to read or write a storage file
+    #[error("Unable to access storage: {0}")]
+    Io(#[from] std::io::Error),
+    // Error variant for failures in the embedded database
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    // Error variant for stored data that cannot be read back
+    #[error("Stored data is corrupt: {0}")]
+    Corrupt(String),
+    // Error variant for a lock poisoned by a panic while it was held
+    #[error("Storage lock was poisoned")]
+    LockPoisoned,
+}
+
+// Convert a storage error into the gRPC status returned to clients, only a missing, existing or
+// concurrently changed record is the client's concern, anything else is a failure of the server
+impl From<StorageError> for Status {
+    fn from(error: StorageError) -> Self {
+        match error {
+            StorageError::UnableToFindChallenge
+            | StorageError::UnableToFindSession
+            | StorageError::UnableToFindRegistration => Status::not_found(error.to_string()),
+            StorageError::AlreadyRegistered => Status::already_exists(error.to_string()),
+            StorageError::RegistrationChanged => Status::aborted(error.to_string()),
+            _ => Status::internal(error.to_string()),
+        }
+    }
+}
+
+// Define group parameter error variants
+#[derive(Error, Debug)]
+pub enum ParameterError {
+    // Error variant for failing to read or write a parameter file
+    #[error("Unable to access parameter file: {0}")]
+    Io(#[from] std::io::Error),
+    // Error variant for a parameter file that cannot be parsed
+    #[error("Unable to parse parameters: {0}")]
+    Parse(String),
+    // Error variant for requesting parameters of an unusable size
+    #[error("Invalid parameter size: {0}")]
+    InvalidSize(String),
+    // Error variant for a parameter smaller than the minimum accepted size
+    #[error("Parameter {parameter} has {bits} bits but at least {minimum} are required")]
+    TooSmall {
+        parameter: &'static str,
+        bits: u64,
+        minimum: u64,
+    },
+    // Error variant for a modulus or subgroup order that is not prime
+    #[error("Parameter {parameter} is not prime")]
+    NotPrime { parameter: &'static str },
+    // Error variant for a subgroup order that does not divide p - 1
+    #[error("Subgroup order q does not divide p - 1")]
+    SubgroupOrderDoesNotDivide,
+    // Error variant for a generator that is out of range, trivial or not of order q
+    #[error("Generator {parameter} is not an element of order q")]
+    InvalidGenerator { parameter: &'static str },
+    // Error variant for a generator h that does not match the derivation from its public seed
+    #[error("Generator h was not derived from the seed '{seed}'")]
+    GeneratorNotDerived { seed: String },
+}
+
+// Define session token error variants
+#[derive(Error, Debug)]
+pub enum TokenError {
+    // Error variant for a token that is not three base64url encoded parts
+    #[error("Token is malformed")]
+    Malformed,
+    // Error variant for a token signed with an algorithm other than Ed25519
+    #[error("Token algorithm {0} is not supported")]
+    UnsupportedAlgorithm(String),
+    // Error variant for a token whose signature does not verify
+    #[error("Token signature is invalid")]
+    InvalidSignature,
+    // Error variant for a token past its expiry
+    #[error("Token has expired")]
+    Expired,
+    // Error variant for a signing or public key that cannot be used
+    #[error("Invalid token key: {0}")]
+    InvalidKey(String),
+    // Error variant for failing to encode the parts of a token
+    #[error("Unable to encode token: {0}")]
+    Encoding(String),
+    // Error variant for failing to read or write a key file
+    #[error("Unable to access token key: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+// Define rate limit error variants
+#[derive(Error, Debug)]
+pub enum RateLimitError {
+    // Error variant for an attempt made before the backoff after earlier failures has passed
+    #[error("Too many failed attempts, retry in {retry_after} seconds")]
+    TooManyAttempts { retry_after: u64 },
+    // Error variant for an attempt by a user or from an address that is locked out
+    #[error("Locked out after too many failed attempts, retry in {retry_after} seconds")]
+    LockedOut { retry_after: u64 },
+}
+
+// Convert a rate limit error into the gRPC status returned to clients
+impl From<RateLimitError> for Status {
+    fn from(error: RateLimitError) -> Self {
+        match error {
+            RateLimitError::TooManyAttempts { .. } => Status::resource_exhausted(error.to_string()),
+            RateLimitError::LockedOut { .. } => Status::permission_denied(error.to_string()),
+        }
+    }
+}
+
+// Define TLS error variants
+#[derive(Error, Debug)]
+pub enum TlsError {
+    // Error variant for failing to read a certificate, key or CA file
+    #[error("Unable to read {}: {source}", path.display())]
+    Io {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+}
+
+// Define server configuration error variants
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    // Error variant for failing to read a configuration file
+    #[error("Unable to read {}: {source}", path.display())]
+    Io {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+    // Error variant for a configuration file that cannot be parsed
+    #[error("Unable to parse configuration: {0}")]
+    Parse(String),
+    // Error variant for settings that cannot be used together
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+// Define client credentials file error variants
+#[derive(Error, Debug)]
+pub enum CredentialsError {
+    // Error variant for failing to read or write a credentials file
+    #[error("Unable to access {}: {source}", path.display())]
+    Io {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+    // Error variant for a credentials file that cannot be parsed
+    #[error("Unable to parse credentials: {0}")]
+    Parse(String),
+    // Error variant for a user without a configuration directory to keep the session cache in
+    #[error("Unable to find a configuration directory for the session cache, neither XDG_CONFIG_HOME nor HOME is set")]
+    NoConfigDirectory,
 }
@@ -1,13 +1,22 @@
 pub mod authentication;
+pub mod capabilities;
 pub mod cli;
 pub mod client;
+pub mod config;
+pub mod credentials;
 pub mod errors;
+pub mod ratelimit;
+pub mod secret;
 pub mod server;
+pub mod storage;
+pub mod tls;
+pub mod token;
 pub mod zkp_auth {
     // Dynamically include the Rust version of the protobuf schema generated at compile time.
     include!(concat!(env!("OUT_DIR"), "/zkp_auth.rs"));
 }
 
+use clap::{builder::PossibleValue, ValueEnum};
 use std::fmt;
 
 impl fmt::Display for zkp_auth::AuthenticationType {
@@ -21,3 +30,20 @@ impl fmt::Display for zkp_auth::AuthenticationType {
         write!(f, "{}", writable)
     }
 }
+
+// Select authentication types on the command line and in the server configuration by name
+impl ValueEnum for zkp_auth::AuthenticationType {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            zkp_auth::AuthenticationType::Exponentiation,
+            zkp_auth::AuthenticationType::EllipticCurve,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            zkp_auth::AuthenticationType::Exponentiation => PossibleValue::new("exponentiation"),
+            zkp_auth::AuthenticationType::EllipticCurve => PossibleValue::new("elliptic-curve"),
+        })
+    }
+}
@@ -0,0 +1,227 @@
+use crate::errors::RateLimitError;
+use crate::storage::unix_now;
+use moka::ops::compute::{CompResult, Op};
+use moka::sync::Cache;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// Default number of failed attempts allowed before any backoff
+pub const DEFAULT_FREE_FAILURES: u32 = 3;
+
+// Default number of seconds to wait after the first failure that is not free, doubling with every
+// further failure
+pub const DEFAULT_BACKOFF_BASE_SECS: u64 = 1;
+
+// Default longest number of seconds to wait between attempts
+pub const DEFAULT_BACKOFF_MAX_SECS: u64 = 300;
+
+// Default number of failed attempts after which a user or address is locked out
+pub const DEFAULT_LOCKOUT_FAILURES: u32 = 10;
+
+// Default number of seconds a lockout lasts, and after which failures are forgotten
+pub const DEFAULT_LOCKOUT_SECS: u64 = 900;
+
+// Limits on failed authentication attempts, applied separately to each user and each peer address,
+// so that passwords cannot be guessed online at speed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+    pub free_failures: u32,
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+    pub lockout_failures: u32,
+    pub lockout: Duration,
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> Self {
+        RateLimitPolicy {
+            free_failures: DEFAULT_FREE_FAILURES,
+            backoff_base: Duration::from_secs(DEFAULT_BACKOFF_BASE_SECS),
+            backoff_max: Duration::from_secs(DEFAULT_BACKOFF_MAX_SECS),
+            lockout_failures: DEFAULT_LOCKOUT_FAILURES,
+            lockout: Duration::from_secs(DEFAULT_LOCKOUT_SECS),
+        }
+    }
+}
+
+impl RateLimitPolicy {
+    // How long to wait after `failures` consecutive failures before the next attempt
+    fn backoff(&self, failures: u32) -> Duration {
+        match failures.checked_sub(self.free_failures) {
+            None | Some(0) => Duration::ZERO,
+            Some(excess) => self
+                .backoff_base
+                .saturating_mul(2u32.saturating_pow(excess - 1))
+                .min(self.backoff_max),
+        }
+    }
+}
+
+// The consecutive failures of a user or address and when the last one happened
+#[derive(Clone, Copy, Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+// Define a limiter of failed attempts, counting failures per user and per peer address in memory.
+// Counts are forgotten once a full lockout has passed without a failure.
+pub struct RateLimiter {
+    policy: RateLimitPolicy,
+    users: Cache<String, Failures>,
+    peers: Cache<IpAddr, Failures>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        RateLimiter {
+            policy,
+            users: failure_cache(&policy),
+            peers: failure_cache(&policy),
+        }
+    }
+
+    // Refuse an attempt by the user from the peer address while either is locked out or waiting
+    // out a backoff
+    pub fn check(&self, user: &str, peer: Option<IpAddr>) -> Result<(), RateLimitError> {
+        self.check_failures(self.users.get(user))?;
+        if let Some(peer) = peer {
+            self.check_failures(self.peers.get(&peer))?;
+        }
+        Ok(())
+    }
+
+    // Count a failed attempt by the user from the peer address, returning when the lockout of the
+    // user ends if this failure locked them out
+    pub fn record_failure(&self, user: &str, peer: Option<IpAddr>) -> Option<u64> {
+        let count = increment(&self.users, user.to_string());
+        if let Some(peer) = peer {
+            increment(&self.peers, peer);
+        }
+        (count == self.policy.lockout_failures)
+            .then(|| unix_now().saturating_add(self.policy.lockout.as_secs()))
+    }
+
+    // Forget the failures of a user who has authenticated. Failures of the peer address are kept,
+    // so that succeeding for one account does not allow guessing at others.
+    pub fn record_success(&self, user: &str) {
+        self.users.invalidate(user);
+    }
+
+    fn check_failures(&self, failures: Option<Failures>) -> Result<(), RateLimitError> {
+        let Some(failures) = failures else {
+            return Ok(());
+        };
+        let elapsed = failures.last.elapsed();
+        if failures.count >= self.policy.lockout_failures {
+            return Err(RateLimitError::LockedOut {
+                retry_after: self.policy.lockout.saturating_sub(elapsed).as_secs(),
+            });
+        }
+        let backoff = self.policy.backoff(failures.count);
+        if elapsed < backoff {
+            return Err(RateLimitError::TooManyAttempts {
+                retry_after: (backoff - elapsed).as_secs().max(1),
+            });
+        }
+        Ok(())
+    }
+}
+
+// Build a cache of failures that forgets them once a full lockout has passed since the last one
+fn failure_cache<K>(policy: &RateLimitPolicy) -> Cache<K, Failures>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+{
+    Cache::builder().time_to_live(policy.lockout).build()
+}
+
+// Atomically count another failure for the key, returning the new count
+fn increment<K>(cache: &Cache<K, Failures>, key: K) -> u32
+where
+    K: Hash + Eq + Send + Sync + 'static,
+{
+    let entry = cache.entry(key).and_compute_with(|existing| {
+        let count = existing.map_or(0, |entry| entry.into_value().count);
+        Op::Put(Failures {
+            count: count.saturating_add(1),
+            last: Instant::now(),
+        })
+    });
+    match entry {
+        CompResult::Inserted(entry) | CompResult::ReplacedWith(entry) => entry.into_value().count,
+        _ => 0,
+    }
+}
+
+// Unit tests for the failure counters.
+#[cfg(test)]
+mod tests {
+    use {super::*, test_case::test_case};
+
+    fn policy() -> RateLimitPolicy {
+        RateLimitPolicy {
+            free_failures: 2,
+            backoff_base: Duration::from_secs(10),
+            backoff_max: Duration::from_secs(25),
+            lockout_failures: 5,
+            lockout: Duration::from_secs(60),
+        }
+    }
+
+    // The backoff must start after the free failures and double up to the maximum.
+    #[test_case(0, 0; "when there are no failures")]
+    #[test_case(2, 0; "when failures are free")]
+    #[test_case(3, 10; "when backoff starts")]
+    #[test_case(4, 20; "when backoff doubles")]
+    #[test_case(5, 25; "when backoff reaches the maximum")]
+    #[test_case(40, 25; "when backoff would overflow")]
+    fn backoff_should_double_after_the_free_failures(failures: u32, seconds: u64) {
+        assert_eq!(policy().backoff(failures), Duration::from_secs(seconds));
+    }
+
+    // Failures must lead to a backoff and then a lockout of the user, which only a success for
+    // the user clears.
+    #[test]
+    fn failures_should_back_off_then_lock_out() {
+        let limiter = RateLimiter::new(policy());
+        for _ in 0..2 {
+            assert!(limiter.check("alice", None).is_ok());
+            assert_eq!(limiter.record_failure("alice", None), None);
+        }
+        assert!(limiter.check("alice", None).is_ok());
+        limiter.record_failure("alice", None);
+        assert!(matches!(
+            limiter.check("alice", None),
+            Err(RateLimitError::TooManyAttempts { .. })
+        ));
+        assert!(limiter.check("bob", None).is_ok());
+
+        limiter.record_failure("alice", None);
+        assert!(limiter.record_failure("alice", None).is_some());
+        assert!(matches!(
+            limiter.check("alice", None),
+            Err(RateLimitError::LockedOut { .. })
+        ));
+
+        limiter.record_success("alice");
+        assert!(limiter.check("alice", None).is_ok());
+    }
+
+    // Failures from an address must hold back every user tried from it, even after a success.
+    #[test]
+    fn failures_should_be_counted_per_address() {
+        let limiter = RateLimiter::new(policy());
+        let peer = Some(IpAddr::from([192, 0, 2, 1]));
+        for user in ["alice", "bob", "carol"] {
+            limiter.record_failure(user, peer);
+        }
+        limiter.record_success("carol");
+        assert!(matches!(
+            limiter.check("dave", peer),
+            Err(RateLimitError::TooManyAttempts { .. })
+        ));
+        assert!(limiter.check("dave", None).is_ok());
+    }
+}
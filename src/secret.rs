@@ -0,0 +1,217 @@
+use crate::errors::AuthenticationError;
+use rpassword::prompt_password; // To securely prompt for password input
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use zeroize::Zeroizing; // To wipe passwords from memory once they have been used
+
+// Which password the client needs, the user's password when registering or authenticating, or the
+// password that replaces it when changing it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecretPurpose {
+    Password,
+    NewPassword,
+}
+
+// A source of passwords for the client, so that the library never has to prompt on a terminal
+pub trait SecretProvider: Send + Sync {
+    // Get the password needed for the purpose, as it was given
+    fn secret(&self, purpose: SecretPurpose) -> Result<Zeroizing<Vec<u8>>, AuthenticationError>;
+
+    // Get the password needed for the purpose, normalised so that the same password gives the same
+    // secret whichever provider it comes from
+    fn password(&self, purpose: SecretPurpose) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        Ok(normalise(&self.secret(purpose)?))
+    }
+}
+
+// Remove the whitespace surrounding a password, including the line ending it was read with, as
+// passwords typed at the terminal always have been
+pub fn normalise(password: &[u8]) -> Zeroizing<Vec<u8>> {
+    let trimmed = match std::str::from_utf8(password) {
+        Ok(password) => password.trim().as_bytes(),
+        Err(_) => password.trim_ascii(),
+    };
+    Zeroizing::new(trimmed.to_vec())
+}
+
+// Any closure taking the purpose provides passwords, for callers that already hold them
+impl<F> SecretProvider for F
+where
+    F: Fn(SecretPurpose) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> + Send + Sync,
+{
+    fn secret(&self, purpose: SecretPurpose) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        self(purpose)
+    }
+}
+
+// The error for a password that could not be read from a source
+fn unreadable(from: impl Into<String>, reason: impl ToString) -> AuthenticationError {
+    AuthenticationError::CouldNotReadPassword {
+        from: from.into(),
+        reason: reason.to_string(),
+    }
+}
+
+// Prompt for passwords on the terminal without echoing them, asking for a new password twice so
+// that a typing mistake cannot lock the user out. The confirmation is compared once normalised
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TerminalSecret;
+
+impl TerminalSecret {
+    // Prompt for a password, returning the password bytes as they were typed
+    fn prompt(prompt: &str) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        let password = Zeroizing::new(
+            prompt_password(prompt).map_err(|_| AuthenticationError::CouldNotGetPassword)?,
+        );
+        Ok(Zeroizing::new(password.as_bytes().to_vec()))
+    }
+}
+
+impl SecretProvider for TerminalSecret {
+    fn secret(&self, purpose: SecretPurpose) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        match purpose {
+            SecretPurpose::Password => Self::prompt("Enter password: "),
+            SecretPurpose::NewPassword => {
+                let password = Self::prompt("Enter new password: ")?;
+                if normalise(&Self::prompt("Confirm new password: ")?) != normalise(&password) {
+                    return Err(AuthenticationError::PasswordsDoNotMatch);
+                }
+                Ok(password)
+            }
+        }
+    }
+}
+
+// Read the password from an environment variable, whatever the purpose
+#[derive(Clone, Debug)]
+pub struct EnvSecret(pub String);
+
+impl SecretProvider for EnvSecret {
+    fn secret(&self, _purpose: SecretPurpose) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        let password = env::var_os(&self.0).ok_or_else(|| {
+            unreadable(format!("environment variable {}", self.0), "it is not set")
+        })?;
+        Ok(Zeroizing::new(password.into_encoded_bytes()))
+    }
+}
+
+// Read the password from the first line of a file, whatever the purpose. The line ending is left
+// for the normalisation to remove
+#[derive(Clone, Debug)]
+pub struct FileSecret(pub PathBuf);
+
+impl SecretProvider for FileSecret {
+    fn secret(&self, _purpose: SecretPurpose) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        let contents = Zeroizing::new(
+            fs::read(&self.0).map_err(|e| unreadable(self.0.display().to_string(), e))?,
+        );
+        let line = contents
+            .split(|byte| *byte == b'\n')
+            .next()
+            .unwrap_or_default();
+        Ok(Zeroizing::new(line.to_vec()))
+    }
+}
+
+// Read each password from the next line of standard input, so that changing a password reads the
+// current password and then the new one
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdinSecret;
+
+impl SecretProvider for StdinSecret {
+    fn secret(&self, _purpose: SecretPurpose) -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        let mut line = Zeroizing::new(Vec::new());
+        let read = io::stdin()
+            .lock()
+            .read_until(b'\n', &mut line)
+            .map_err(|e| unreadable("standard input", e))?;
+        if read == 0 {
+            return Err(unreadable("standard input", "it is closed"));
+        }
+        Ok(line)
+    }
+}
+
+// Unit tests for the password sources that do not need a terminal or standard input.
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::storage::tests::temporary_path, test_case::test_case};
+
+    // Only the first line of a file must be read, without its line ending.
+    #[test]
+    fn file_secrets_should_read_the_first_line() {
+        let (_dir, path) = temporary_path("password");
+        fs::write(&path, "correct horse battery staple\r\nignored\n").unwrap();
+        let secret = FileSecret(path.clone())
+            .password(SecretPurpose::Password)
+            .unwrap();
+        assert_eq!(*secret, b"correct horse battery staple");
+
+        fs::remove_file(&path).unwrap();
+        let error = FileSecret(path).secret(SecretPurpose::Password);
+        assert!(matches!(
+            error,
+            Err(AuthenticationError::CouldNotReadPassword { .. })
+        ));
+    }
+
+    // A variable must be read, and a missing variable must be reported.
+    #[test]
+    fn env_secrets_should_read_the_variable() {
+        env::set_var("ACP_TEST_PASSWORD", "hunter2");
+        let secret = EnvSecret("ACP_TEST_PASSWORD".to_string())
+            .password(SecretPurpose::NewPassword)
+            .unwrap();
+        assert_eq!(*secret, b"hunter2");
+
+        let error = EnvSecret("ACP_TEST_MISSING_PASSWORD".to_string())
+            .secret(SecretPurpose::Password)
+            .unwrap_err();
+        assert!(error.to_string().contains("ACP_TEST_MISSING_PASSWORD"));
+    }
+
+    // A closure must be asked for each purpose in turn.
+    #[test]
+    fn closures_should_provide_secrets() {
+        let provider = |purpose| {
+            Ok(Zeroizing::new(match purpose {
+                SecretPurpose::Password => b"old".to_vec(),
+                SecretPurpose::NewPassword => b"new".to_vec(),
+            }))
+        };
+        let provider: &dyn SecretProvider = &provider;
+        assert_eq!(*provider.secret(SecretPurpose::Password).unwrap(), b"old");
+        assert_eq!(
+            *provider.secret(SecretPurpose::NewPassword).unwrap(),
+            b"new"
+        );
+    }
+
+    // The same password must give the same bytes from every provider, whatever surrounds it in
+    // the source.
+    #[test_case("pw"; "when there is no whitespace")]
+    #[test_case("pw "; "when there is a trailing space")]
+    #[test_case(" pw\t"; "when there is surrounding whitespace")]
+    #[test_case("pass word"; "when there is inner whitespace")]
+    fn providers_should_agree_on_passwords(password: &str) {
+        let (_dir, path) = temporary_path("password");
+        fs::write(&path, format!("{password}\r\n")).unwrap();
+        let variable = format!("ACP_TEST_PASSWORD_{}", password.len());
+        env::set_var(&variable, password);
+        let typed = password.to_string();
+        let closure = move |_| Ok(Zeroizing::new(typed.as_bytes().to_vec()));
+        let providers: [&dyn SecretProvider; 3] =
+            [&FileSecret(path), &EnvSecret(variable), &closure];
+        for provider in providers {
+            let secret = provider.password(SecretPurpose::Password).unwrap();
+            assert_eq!(*secret, password.trim().as_bytes());
+        }
+        // The terminal and standard input go through the same normalisation of what they read
+        assert_eq!(
+            *normalise(format!("{password}\n").as_bytes()),
+            password.trim().as_bytes()
+        );
+    }
+}
@@ -1,56 +1,439 @@
 use crate::{
-    authentication::{get_authentication, Authenticate},
+    authentication::{
+        common::generate_random_bytes, exponentiation::Exponentiation, get_authentication,
+        kdf::KeyDerivation, Authenticate, Transcript,
+    },
+    capabilities,
+    errors::{AcpError, AuthenticationError, RateLimitError, StorageError, TokenError},
+    ratelimit::{RateLimitPolicy, RateLimiter},
+    storage::{unix_now, Registration, RegistrationStore, Session, SessionStore},
+    token::{key_id, Claims, TokenSigner, ALGORITHM},
     zkp_auth::{
         auth_server::Auth, AuthTypeRequest, AuthTypeResponse, AuthenticationAnswerRequest,
         AuthenticationAnswerResponse, AuthenticationChallengeRequest,
-        AuthenticationChallengeResponse, AuthenticationType, RegisterRequest, RegisterResponse,
+        AuthenticationChallengeResponse, AuthenticationType, ChangeSecretChallengeRequest,
+        ChangeSecretRequest, ChangeSecretResponse, GroupParameters, HelloRequest, HelloResponse,
+        KdfParameters, KdfParametersRequest, KdfParametersResponse, NonInteractiveProof,
+        ProofNonceRequest, ProofNonceResponse, RegisterRequest, RegisterResponse,
+        RevokeSessionResponse, SessionRequest, SessionResponse, TokenKeyRequest, TokenKeyResponse,
     },
 };
 use moka::sync::Cache;
-use num_bigint::BigUint;
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::Duration;
 use tonic::{Request, Response, Status}; // Tonic library for gRPC support
 use tracing::{debug, info}; // Tracing library for logging
+use zeroize::Zeroizing;
 
-// Struct representing user registration data including initial setup parameters
+// Struct representing a challenge issued for authentication and the returned challenge, with the
+// authentication type it was issued for, the group elements and challenge kept in their canonical
+// encoding, and the registration that replaces the user's current one when the challenge was
+// issued to change their secret
 #[derive(Clone, Debug)]
-pub struct Registration {
+pub struct Challenge {
     user: String,
-    y1: BigUint,
-    y2: BigUint,
+    auth: AuthenticationType,
+    r1: Vec<u8>,
+    r2: Vec<u8>,
+    c: Vec<u8>,
+    replacement: Option<Registration>,
 }
 
-// Struct representing a challenge issued for authentication and the returned challenge
-#[derive(Clone, Debug)]
-pub struct Challenge {
-    user: String,
-    r1: BigUint,
-    r2: BigUint,
-    c: BigUint,
+// Size in bytes of the nonces issued for non-interactive proofs
+const PROOF_NONCE_SIZE: usize = 32;
+
+// Size in bytes of the key deriving the salts of unregistered users. It is drawn when the server
+// starts, so those salts only stay the same while it runs
+const DECOY_KEY_SIZE: usize = 32;
+
+// Default number of seconds a challenge or proof nonce can be answered for
+pub const DEFAULT_CHALLENGE_TTL_SECS: u64 = 60;
+
+// Default number of outstanding challenges, and separately of proof nonces, kept at once
+pub const DEFAULT_CHALLENGE_CAPACITY: u64 = 100_000;
+
+// Default number of seconds a session lasts after it is started or refreshed
+pub const DEFAULT_SESSION_TTL_SECS: u64 = 3600;
+
+// Limits on outstanding challenges and proof nonces, so that they cannot be answered long after
+// they were issued and cannot grow without bound
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChallengePolicy {
+    pub ttl: Duration,
+    pub capacity: u64,
+}
+
+impl Default for ChallengePolicy {
+    fn default() -> Self {
+        ChallengePolicy {
+            ttl: Duration::from_secs(DEFAULT_CHALLENGE_TTL_SECS),
+            capacity: DEFAULT_CHALLENGE_CAPACITY,
+        }
+    }
+}
+
+impl ChallengePolicy {
+    // Build a cache that expires and evicts entries according to the policy
+    fn cache<K, V>(&self) -> Cache<K, V>
+    where
+        K: Hash + Eq + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        Cache::builder()
+            .time_to_live(self.ttl)
+            .max_capacity(self.capacity)
+            .build()
+    }
+}
+
+// Remove and return an entry that has not expired. The lookup skips expired entries, which removal
+// alone does not, and the removal is atomic so that only one caller can ever take the entry.
+fn take<K, V, Q>(cache: &Cache<K, V>, key: &Q) -> Option<V>
+where
+    K: Borrow<Q> + Hash + Eq + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    Q: Hash + Eq + ?Sized,
+{
+    cache.get(key)?;
+    cache.remove(key)
 }
 
-// Server's state including authenticator, user registrations, and challenges
+// Server's state including authenticators, user registrations, and challenges
 pub struct ServerState {
-    authenticator: Box<dyn Authenticate>, // Authentication logic encapsulation
-    registrations: Cache<String, Registration>, // Cache for user registrations
+    authenticators: Vec<Box<dyn Authenticate>>, // Authentication logic of each type served, the first being the primary one
+    group: Option<GroupParameters>, // The exponentiation group advertised to clients, when exponentiation is served
+    registrations: Box<dyn RegistrationStore>, // Store for user registrations
     challenges: Cache<String, Challenge>, // Cache for authentication challenges
+    proof_nonces: Cache<Vec<u8>, String>, // Cache for unused proof nonces and the user they were issued to
+    sessions: Box<dyn SessionStore>,      // Store for the sessions of authenticated users
+    session_ttl: Duration, // How long a session lasts after it is started or refreshed
+    token_signer: Option<TokenSigner>, // Signer of session tokens, when they are issued
+    rate_limiter: RateLimiter, // Counters of failed attempts per user and per peer address
+    decoy_key: Zeroizing<Vec<u8>>, // Key deriving the salts reported for users who have not registered
+}
+
+// Refuse group elements that are malformed, the identity or outside the prime order subgroup,
+// which could otherwise be used to learn about the secret or to pass verification trivially
+fn check_elements(
+    authenticator: &dyn Authenticate,
+    elements: &[&[u8]],
+) -> Result<(), AuthenticationError> {
+    elements
+        .iter()
+        .try_for_each(|element| authenticator.check_element(element))
 }
 
 impl ServerState {
-    // Constructor for ServerState including choosing the type of authentication
-    pub fn new(use_ec: bool) -> Self {
-        let auth = if use_ec {
-            AuthenticationType::EllipticCurve
-        } else {
-            AuthenticationType::Exponentiation
+    // The authenticator new registrations use unless the client asks for another type, and which
+    // generates identifiers whatever the type
+    fn primary(&self) -> &dyn Authenticate {
+        self.authenticators[0].as_ref()
+    }
+
+    // The authenticator of a type the server serves, given as sent by a client or recorded in a
+    // registration
+    fn authenticator(&self, auth: i32) -> Result<&dyn Authenticate, AuthenticationError> {
+        self.authenticators
+            .iter()
+            .find(|authenticator| authenticator.auth_type() as i32 == auth)
+            .map(|authenticator| authenticator.as_ref())
+            .ok_or_else(|| {
+                AuthenticationError::UnsupportedAuthType(
+                    AuthenticationType::try_from(auth)
+                        .map(|auth| auth.to_string())
+                        .unwrap_or_else(|_| auth.to_string()),
+                )
+            })
+    }
+
+    // The authenticator of a registration, where registrations kept before the type was recorded
+    // use the primary type
+    fn registered_authenticator(
+        &self,
+        registration: &Registration,
+    ) -> Result<&dyn Authenticate, AuthenticationError> {
+        match registration.auth_type {
+            Some(auth) => self.authenticator(auth),
+            None => Ok(self.primary()),
+        }
+    }
+
+    // The authenticator of the type the user registered with, or of the primary type for users who
+    // have not registered, so that challenges to unknown users look like any other
+    fn user_authenticator(&self, user: &str) -> Result<&dyn Authenticate, AcpError> {
+        Ok(match self.registrations.get(user)? {
+            Some(registration) => self.registered_authenticator(&registration)?,
+            None => self.primary(),
+        })
+    }
+
+    // Build a registration from the values sent by a client for the authentication type it chose,
+    // refusing types that are not served, invalid group elements and passwords that were not
+    // stretched with a strong enough key derivation
+    fn registration(
+        &self,
+        user: String,
+        y1: Vec<u8>,
+        y2: Vec<u8>,
+        kdf: KdfParameters,
+        auth: i32,
+    ) -> Result<Registration, AuthenticationError> {
+        check_elements(self.authenticator(auth)?, &[&y1, &y2])?;
+        let kdf = KeyDerivation::try_from(kdf)?;
+        kdf.validate()?;
+        Ok(Registration {
+            user,
+            y1,
+            y2,
+            kdf,
+            locked_until: None,
+            auth_type: Some(auth),
+        })
+    }
+
+    // Refuse an attempt by the user from the peer address while either is waiting out a backoff or
+    // locked out, or while a lockout recorded against the registration of the user lasts, which
+    // survives restarts of the server
+    fn check_allowed(&self, user: &str, peer: Option<IpAddr>) -> Result<(), AcpError> {
+        self.rate_limiter.check(user, peer)?;
+        let now = unix_now();
+        let locked_until = self
+            .registrations
+            .get(user)?
+            .filter(|registration| registration.is_locked(now))
+            .and_then(|registration| registration.locked_until);
+        if let Some(until) = locked_until {
+            return Err(RateLimitError::LockedOut {
+                retry_after: until - now,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    // Count the outcome of an attempt by the user from the peer address, recording a lockout
+    // against the registration of the user when too many attempts have failed
+    fn record_attempt(
+        &self,
+        user: &str,
+        peer: Option<IpAddr>,
+        verified: bool,
+    ) -> Result<(), StorageError> {
+        if verified {
+            self.rate_limiter.record_success(user);
+            return Ok(());
+        }
+        let Some(until) = self.rate_limiter.record_failure(user, peer) else {
+            return Ok(());
         };
+        info!("Locking out {} after repeated failed attempts", user);
+        match self.registrations.set_locked_until(user, Some(until)) {
+            Ok(()) | Err(StorageError::UnableToFindRegistration) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Issue a challenge to the user for the commitments r1 and r2, made with the authentication
+    // type the user registered with, which when answered replaces the user's registration with
+    // `replacement` if one is given
+    fn issue_challenge(
+        &self,
+        user: String,
+        r1: Vec<u8>,
+        r2: Vec<u8>,
+        replacement: Option<Registration>,
+    ) -> Result<AuthenticationChallengeResponse, AcpError> {
+        let authenticator = self.user_authenticator(&user)?;
+        check_elements(authenticator, &[&r1, &r2])?;
 
-        info!("Using {} as authentication type", &auth);
+        let auth_id = self.primary().auth_id(); // Generate an authentication ID
+        let challenge = authenticator.challenge(); // Generate a challenge value
 
-        let authenticator = get_authentication(auth); // Get the authenticator based on the chosen method
+        // Build the challenge type to be stored for the user
+        let chal = Challenge {
+            user,
+            auth: authenticator.auth_type(),
+            r1,
+            r2,
+            c: challenge.clone(),
+            replacement,
+        };
+
+        // Insert the challenge into the cache
+        self.challenges.insert(auth_id.clone(), chal);
+
+        Ok(AuthenticationChallengeResponse {
+            auth_id,
+            c: challenge, // Return the encoded challenge
+        })
+    }
+
+    // Consume the challenge issued as `auth_id` and check the answer `s` against the registration of
+    // the user it was issued to, returning the registration and the replacement the challenge was
+    // issued for when the answer is right. The challenge is consumed whatever the outcome, so that
+    // an auth_id can only ever be answered once, and wrong answers count against the user and the
+    // peer address they came from
+    fn verify_answer(
+        &self,
+        auth_id: &str,
+        s: &[u8],
+        peer: Option<IpAddr>,
+    ) -> Result<Option<(Registration, Option<Registration>)>, AcpError> {
+        let challenge =
+            take(&self.challenges, auth_id).ok_or(StorageError::UnableToFindChallenge)?;
+        self.check_allowed(&challenge.user, peer)?;
+
+        let registration = self
+            .registrations
+            .get(&challenge.user)?
+            .ok_or(StorageError::UnableToFindRegistration)?;
+
+        // The commitments were made for the type the user was registered with when the challenge
+        // was issued, which a change of secret since may have replaced
+        let authenticator = self.registered_authenticator(&registration)?;
+        if authenticator.auth_type() != challenge.auth {
+            return Err(StorageError::RegistrationChanged.into());
+        }
+
+        let verified = authenticator.verify(
+            &registration.y1,
+            &registration.y2,
+            &challenge.r1,
+            &challenge.r2,
+            s,
+            &challenge.c,
+        );
+        self.record_attempt(&challenge.user, peer, matches!(verified, Ok(true)))?;
+        Ok(verified?.then_some((registration, challenge.replacement)))
+    }
+
+    // Sign a token carrying the session, issued at the time `issued`, or return an empty token when
+    // the server does not issue tokens
+    fn token(&self, session: &Session, issued: u64) -> Result<String, TokenError> {
+        let Some(signer) = &self.token_signer else {
+            return Ok(String::new());
+        };
+        signer.sign(&Claims {
+            sub: session.user.clone(),
+            iat: issued,
+            exp: session.expires,
+            auth: AuthenticationType::try_from(session.auth_type)
+                .map(|auth| auth.to_string())
+                .unwrap_or_default(),
+        })
+    }
+
+    // Start a session for a user who has just authenticated with the authentication type
+    fn start_session(
+        &self,
+        user: String,
+        auth: AuthenticationType,
+    ) -> Result<AuthenticationAnswerResponse, AcpError> {
+        let created = unix_now();
+        let session = Session {
+            id: self.primary().session_id(), // Generate a session ID for the authenticated session
+            user,
+            auth_type: auth as i32,
+            created,
+            expires: created.saturating_add(self.session_ttl.as_secs()),
+        };
+        let response = AuthenticationAnswerResponse {
+            session_id: session.id.clone(),
+            expires: session.expires,
+            token: self.token(&session, created)?,
+        };
+        self.sessions.insert_session(session)?;
+        Ok(response)
+    }
+
+    // Look up a session that has not expired
+    fn find_session(&self, id: &str) -> Result<Session, StorageError> {
+        self.sessions
+            .get_session(id)?
+            .filter(|session| !session.is_expired(unix_now()))
+            .ok_or(StorageError::UnableToFindSession)
+    }
+
+    // Constructor for ServerState including choosing the types of authentication served, the first
+    // of which new registrations use by default, where to keep registrations and sessions, and how
+    // long challenges and sessions last
+    pub fn new(
+        auth_types: &[AuthenticationType],
+        exponentiation: Exponentiation,
+        registrations: Box<dyn RegistrationStore>,
+        sessions: Box<dyn SessionStore>,
+        challenge_policy: ChallengePolicy,
+        session_ttl: Duration,
+    ) -> Self {
+        assert!(
+            !auth_types.is_empty(),
+            "At least one authentication type must be served"
+        );
+
+        for auth in auth_types {
+            info!("Serving {} authentication", auth);
+        }
+        let group = auth_types
+            .contains(&AuthenticationType::Exponentiation)
+            .then(|| {
+                info!(
+                    "Using a {} bit exponentiation group with a {} bit subgroup and fingerprint {}",
+                    exponentiation.p.bits(),
+                    exponentiation.q.bits(),
+                    exponentiation.fingerprint()
+                );
+                GroupParameters::from(&exponentiation)
+            });
+
+        // Get the authenticator of each chosen method
+        let authenticators = auth_types
+            .iter()
+            .map(|auth| get_authentication(*auth, exponentiation.clone()))
+            .collect();
         Self {
-            authenticator,
-            registrations: Cache::builder().build(),
-            challenges: Cache::builder().build(),
+            authenticators,
+            group,
+            registrations,
+            challenges: challenge_policy.cache(),
+            proof_nonces: challenge_policy.cache(),
+            sessions,
+            session_ttl,
+            token_signer: None,
+            rate_limiter: RateLimiter::new(RateLimitPolicy::default()),
+            decoy_key: Zeroizing::new(generate_random_bytes(DECOY_KEY_SIZE)),
+        }
+    }
+
+    // Limit failed attempts according to the policy rather than the default one
+    pub fn with_rate_limit_policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.rate_limiter = RateLimiter::new(policy);
+        self
+    }
+
+    // Also issue a signed token with every session, which other services can verify with the
+    // public key of the signer
+    pub fn with_token_signer(mut self, signer: TokenSigner) -> Self {
+        info!(
+            "Signing session tokens with key {}",
+            key_id(&signer.public_key())
+        );
+        self.token_signer = Some(signer);
+        self
+    }
+}
+
+// Convert a stored session into the details returned to clients
+impl From<Session> for SessionResponse {
+    fn from(session: Session) -> Self {
+        SessionResponse {
+            session_id: session.id,
+            user: session.user,
+            auth: session.auth_type,
+            created: session.created,
+            expires: session.expires,
+            token: String::new(),
         }
     }
 }
@@ -58,14 +441,42 @@ impl ServerState {
 // Implementing asynchronous trait for handling authentication  gRPC calls
 #[tonic::async_trait]
 impl Auth for ServerState {
-    // Method to get the type of authentication being used by the server.  This wasn't in the
+    // Report the protocol versions and options the server supports, so that clients can pick the
+    // best ones they also support or explain why there are none
+    async fn hello(
+        &self,
+        request: Request<HelloRequest>,
+    ) -> Result<Response<HelloResponse>, Status> {
+        let inner_req = request.into_inner();
+
+        debug!(
+            "Received hello from a client speaking version {}",
+            inner_req.protocol_version
+        );
+
+        let auth_types: Vec<_> = self
+            .authenticators
+            .iter()
+            .map(|authenticator| authenticator.auth_type())
+            .collect();
+        Ok(Response::new(capabilities::offer(&auth_types)))
+    }
+
+    // Method to get the type of authentication a user registered with, or the primary type for users
+    // who have not, along with the primary type new registrations should use and the exponentiation
+    // group, so that clients never need to be configured with it.  This wasn't in the
     // initial protobuf spec but thought it was a nicer way to handle more than one auth type
     async fn get_auth_type(
         &self,
-        _request: Request<AuthTypeRequest>,
+        request: Request<AuthTypeRequest>,
     ) -> Result<Response<AuthTypeResponse>, Status> {
-        let auth = &self.authenticator.auth_type(); // Get authentication type from the authenticator currently being used.
-        Ok(Response::new(AuthTypeResponse { auth: *auth as i32 }))
+        let inner_req = request.into_inner();
+        let auth = self.user_authenticator(&inner_req.user)?.auth_type();
+        Ok(Response::new(AuthTypeResponse {
+            auth: auth as i32,
+            preferred: self.primary().auth_type() as i32,
+            group: self.group.clone(),
+        }))
     }
 
     // Registering a the user setup parameters to be used later for authentication
@@ -78,45 +489,62 @@ impl Auth for ServerState {
         debug!("Received register request: {:?}", &inner_req);
 
         // Build the registration type to be stored for the user
-        let reg = Registration {
-            user: inner_req.user,
-            y1: BigUint::from_bytes_be(&inner_req.y1),
-            y2: BigUint::from_bytes_be(&inner_req.y2),
-        };
+        let reg = self
+            .registration(
+                inner_req.user,
+                inner_req.y1,
+                inner_req.y2,
+                inner_req.kdf.unwrap_or_default(),
+                inner_req.auth,
+            )
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
-        // Insert the registration into the cache
-        self.registrations.insert(reg.user.clone(), reg);
+        // Insert the registration into the store, refusing users who have already registered so
+        // that nobody can take over an account by registering it again
+        self.registrations.insert(reg)?;
 
         Ok(Response::new(RegisterResponse {}))
     }
 
+    // Return the key derivation the user registered with, so that the client can derive the same secret
+    async fn get_kdf_parameters(
+        &self,
+        request: Request<KdfParametersRequest>,
+    ) -> Result<Response<KdfParametersResponse>, Status> {
+        let inner_req = request.into_inner();
+
+        debug!(
+            "Received key derivation parameters request: {:?}",
+            &inner_req
+        );
+
+        // Users who have not registered get a made up key derivation rather than an error, so that
+        // the answer does not tell whether the user exists
+        let kdf = match self.registrations.get(&inner_req.user)? {
+            Some(registration) => registration.kdf,
+            None => KeyDerivation::decoy(&inner_req.user, &self.decoy_key),
+        };
+
+        Ok(Response::new(KdfParametersResponse {
+            kdf: Some((&kdf).into()),
+        }))
+    }
+
     // Create a challenge for user authentication
     async fn create_authentication_challenge(
         &self,
         request: Request<AuthenticationChallengeRequest>,
     ) -> Result<Response<AuthenticationChallengeResponse>, Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
         let inner_req = request.into_inner();
 
         debug!("Received challenge request: {:?}", &inner_req);
 
-        let auth_id = self.authenticator.auth_id(); // Generate an authentication ID
-        let challenge = self.authenticator.challenge(); // Generate a challenge value
-                                                        //
-                                                        // Build the challenge type to be stored for the user
-        let chal = Challenge {
-            user: inner_req.user,
-            r1: BigUint::from_bytes_be(&inner_req.r1),
-            r2: BigUint::from_bytes_be(&inner_req.r2),
-            c: challenge.clone(),
-        };
+        self.check_allowed(&inner_req.user, peer)?;
 
-        // Insert the challenge into the cache
-        self.challenges.insert(auth_id.clone(), chal);
+        let response = self.issue_challenge(inner_req.user, inner_req.r1, inner_req.r2, None)?;
 
-        Ok(Response::new(AuthenticationChallengeResponse {
-            auth_id,
-            c: challenge.to_bytes_be(), // Return the challenge as bytes
-        }))
+        Ok(Response::new(response))
     }
 
     // Verify the response to an authentication challenge
@@ -124,38 +552,1306 @@ impl Auth for ServerState {
         &self,
         request: Request<AuthenticationAnswerRequest>,
     ) -> Result<Response<AuthenticationAnswerResponse>, Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
         let inner_req = request.into_inner();
 
         debug!("Received challenge answer request: {:?}", &inner_req);
 
-        let s = BigUint::from_bytes_be(&inner_req.s);
+        // Verify the user authentication, values that are not valid encodings are rejected
+        let verified = self.verify_answer(&inner_req.auth_id, &inner_req.s, peer)?;
+
+        // A challenge issued to change the secret cannot be used to sign in
+        if let Some((_, Some(_))) = verified {
+            return Err(Status::failed_precondition(
+                "Challenge was issued to change the secret",
+            ));
+        }
+
+        if let Some((registration, _)) = verified {
+            let auth = self
+                .registered_authenticator(&registration)
+                .map_err(AcpError::from)?
+                .auth_type();
+            Ok(Response::new(self.start_session(registration.user, auth)?))
+        } else {
+            Err(Status::unauthenticated("Unable to authenticate"))
+        }
+    }
+
+    // Issue a single use nonce for a non-interactive proof, binding the proof to this server
+    async fn create_proof_nonce(
+        &self,
+        request: Request<ProofNonceRequest>,
+    ) -> Result<Response<ProofNonceResponse>, Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        let inner_req = request.into_inner();
+
+        debug!("Received proof nonce request: {:?}", &inner_req);
+
+        self.check_allowed(&inner_req.user, peer)?;
+
+        let nonce = generate_random_bytes(PROOF_NONCE_SIZE);
+        self.proof_nonces.insert(nonce.clone(), inner_req.user);
+
+        Ok(Response::new(ProofNonceResponse { nonce }))
+    }
+
+    // Verify a non-interactive proof, where the client derived the challenge itself
+    async fn verify_proof(
+        &self,
+        request: Request<NonInteractiveProof>,
+    ) -> Result<Response<AuthenticationAnswerResponse>, Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        let inner_req = request.into_inner();
 
-        //In order to verify, we need the challenge and registration for the user from the cache
-        let challenge = self
-            .challenges
-            .get(&inner_req.auth_id)
-            .ok_or_else(|| Status::not_found("Unable to find prior challenge"))?;
+        debug!("Received non-interactive proof: {:?}", &inner_req);
+
+        // The nonce is consumed whatever the outcome, so that a proof can never be replayed
+        let user = take(&self.proof_nonces, &inner_req.nonce)
+            .ok_or_else(|| Status::not_found("Unable to find prior nonce"))?;
+        if user != inner_req.user {
+            return Err(Status::invalid_argument("Nonce was issued to another user"));
+        }
+        self.check_allowed(&inner_req.user, peer)?;
 
         let registration = self
             .registrations
-            .get(&challenge.user)
-            .ok_or_else(|| Status::not_found("Unable to find prior registration"))?;
+            .get(&inner_req.user)?
+            .ok_or(StorageError::UnableToFindRegistration)?;
 
-        // Verify the user authentication
-        let verified = self.authenticator.verify(
-            &registration.y1,
-            &registration.y2,
-            &challenge.r1,
-            &challenge.r2,
-            &s,
-            &challenge.c,
-        );
+        let transcript = Transcript {
+            user: &inner_req.user,
+            y1: &registration.y1,
+            y2: &registration.y2,
+            r1: &inner_req.r1,
+            r2: &inner_req.r2,
+            nonce: &inner_req.nonce,
+        };
+
+        // Verify the proof, values that are not valid encodings or group elements are rejected
+        let authenticator = self
+            .registered_authenticator(&registration)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let verified = authenticator.verify_proof(&transcript, &inner_req.s);
+        self.record_attempt(&inner_req.user, peer, matches!(verified, Ok(true)))?;
+        let verified = verified.map_err(|e| Status::invalid_argument(e.to_string()))?;
 
         if verified {
-            let session_id = self.authenticator.session_id(); // Generate a session ID for the authenticated session
-            Ok(Response::new(AuthenticationAnswerResponse { session_id }))
+            let auth = authenticator.auth_type();
+            Ok(Response::new(self.start_session(registration.user, auth)?))
         } else {
             Err(Status::unauthenticated("Unable to authenticate"))
         }
     }
+
+    // Issue a challenge for the current secret of a user, bound to the registration of the new
+    // secret so that answering it can only ever install that registration. The new secret may be
+    // registered with another authentication type, which moves the user over to it
+    async fn create_change_secret_challenge(
+        &self,
+        request: Request<ChangeSecretChallengeRequest>,
+    ) -> Result<Response<AuthenticationChallengeResponse>, Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        let inner_req = request.into_inner();
+
+        debug!("Received change secret challenge request: {:?}", &inner_req);
+
+        self.check_allowed(&inner_req.user, peer)?;
+
+        let replacement = self
+            .registration(
+                inner_req.user.clone(),
+                inner_req.y1,
+                inner_req.y2,
+                inner_req.kdf.unwrap_or_default(),
+                inner_req.auth,
+            )
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let response = self.issue_challenge(
+            inner_req.user,
+            inner_req.r1,
+            inner_req.r2,
+            Some(replacement),
+        )?;
+
+        Ok(Response::new(response))
+    }
+
+    // Replace the registration of a user who has answered a change secret challenge with their
+    // current secret, by the registration the challenge was issued for
+    async fn change_secret(
+        &self,
+        request: Request<ChangeSecretRequest>,
+    ) -> Result<Response<ChangeSecretResponse>, Status> {
+        let peer = request.remote_addr().map(|addr| addr.ip());
+        let inner_req = request.into_inner();
+
+        debug!("Received change secret request: {:?}", &inner_req);
+
+        // Only a user who proves knowledge of the current secret can replace it
+        let (current, replacement) = self
+            .verify_answer(&inner_req.auth_id, &inner_req.s, peer)?
+            .ok_or_else(|| Status::unauthenticated("Unable to authenticate"))?;
+        let reg = replacement.ok_or_else(|| {
+            Status::failed_precondition("Challenge was not issued to change the secret")
+        })?;
+
+        // The registration is only replaced if it is still the one the answer was checked against
+        info!("Changing the secret of {}", reg.user);
+        self.registrations.replace(&current, reg)?;
+
+        Ok(Response::new(ChangeSecretResponse {}))
+    }
+
+    // Check that a session exists and has not expired, returning its details
+    async fn validate_session(
+        &self,
+        request: Request<SessionRequest>,
+    ) -> Result<Response<SessionResponse>, Status> {
+        let inner_req = request.into_inner();
+
+        let session = self.find_session(&inner_req.session_id)?;
+
+        Ok(Response::new(session.into()))
+    }
+
+    // Extend a session that has not expired, so that it lasts for a full lifetime from now
+    async fn refresh_session(
+        &self,
+        request: Request<SessionRequest>,
+    ) -> Result<Response<SessionResponse>, Status> {
+        let inner_req = request.into_inner();
+
+        let now = unix_now();
+        let session = self
+            .sessions
+            .extend_session(
+                &inner_req.session_id,
+                now,
+                now.saturating_add(self.session_ttl.as_secs()),
+            )?
+            .ok_or(StorageError::UnableToFindSession)?;
+
+        // A refreshed session comes with a token carrying its new expiry
+        let token = self.token(&session, now).map_err(AcpError::from)?;
+        Ok(Response::new(SessionResponse {
+            token,
+            ..session.into()
+        }))
+    }
+
+    // End a session before it expires
+    async fn revoke_session(
+        &self,
+        request: Request<SessionRequest>,
+    ) -> Result<Response<RevokeSessionResponse>, Status> {
+        let inner_req = request.into_inner();
+
+        if !self.sessions.remove_session(&inner_req.session_id)? {
+            return Err(StorageError::UnableToFindSession.into());
+        }
+        info!("Revoked a session");
+
+        Ok(Response::new(RevokeSessionResponse {}))
+    }
+
+    // Publish the public key session tokens are signed with
+    async fn get_token_key(
+        &self,
+        _request: Request<TokenKeyRequest>,
+    ) -> Result<Response<TokenKeyResponse>, Status> {
+        let signer = self
+            .token_signer
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("Session tokens are not issued"))?;
+        let public_key = signer.public_key();
+
+        Ok(Response::new(TokenKeyResponse {
+            algorithm: ALGORITHM.to_string(),
+            key_id: key_id(&public_key),
+            public_key: public_key.to_bytes().to_vec(),
+        }))
+    }
+}
+
+// Unit tests for the server, calling the gRPC handlers directly.
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::authentication::{
+            ellipticcurve::EllipticCurve,
+            exponentiation::GroupId,
+            kdf::{Kdf, KdfAlgorithm},
+        },
+        crate::storage::{MemoryStore, SqliteStore},
+        crate::token::TokenVerifier,
+        crate::zkp_auth::KdfParameters,
+        num_bigint::BigUint,
+        num_traits::One,
+        test_case::test_case,
+    };
+
+    // A server keeping registrations in memory, using a small exponentiation group for speed.
+    fn server(use_ec: bool) -> ServerState {
+        serving(if use_ec {
+            &[AuthenticationType::EllipticCurve]
+        } else {
+            &[AuthenticationType::Exponentiation]
+        })
+    }
+
+    // A server like the above, serving each of the authentication types.
+    fn serving(auth_types: &[AuthenticationType]) -> ServerState {
+        ServerState::new(
+            auth_types,
+            Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256),
+            Box::new(MemoryStore::new()),
+            Box::new(MemoryStore::new()),
+            ChallengePolicy::default(),
+            Duration::from_secs(DEFAULT_SESSION_TTL_SECS),
+        )
+    }
+
+    // A registration request for a random secret, stretched with the given key derivation.
+    fn register_request(state: &ServerState, user: &str, kdf: KdfParameters) -> RegisterRequest {
+        let secret = state.primary().get_random();
+        let (y1, y2) = state.primary().registration(&secret).unwrap();
+        RegisterRequest {
+            user: user.to_string(),
+            y1,
+            y2,
+            kdf: Some(kdf),
+            auth: state.primary().auth_type() as i32,
+        }
+    }
+
+    // The key derivation given at registration must be handed back unchanged.
+    #[tokio::test]
+    async fn kdf_parameters_should_be_returned_for_registered_users() {
+        let state = server(true);
+        let kdf = KeyDerivation::new(KdfAlgorithm::Scrypt);
+        let request = register_request(&state, "alice", (&kdf).into());
+        state.register(Request::new(request)).await.unwrap();
+
+        let response = state
+            .get_kdf_parameters(Request::new(KdfParametersRequest {
+                user: "alice".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(KeyDerivation::try_from(response.kdf.unwrap()).unwrap(), kdf);
+    }
+
+    // Unregistered users must get key derivation parameters shaped like those of registered users,
+    // with a salt that does not change between requests but differs from user to user.
+    #[tokio::test]
+    async fn kdf_parameters_should_not_reveal_unknown_users() {
+        let state = server(true);
+        let kdf = KeyDerivation::new(KdfAlgorithm::default());
+        let request = register_request(&state, "alice", (&kdf).into());
+        state.register(Request::new(request)).await.unwrap();
+
+        let parameters = |user: &str| {
+            let request = Request::new(KdfParametersRequest {
+                user: user.to_string(),
+            });
+            let state = &state;
+            async move {
+                let response = state.get_kdf_parameters(request).await.unwrap();
+                KeyDerivation::try_from(response.into_inner().kdf.unwrap()).unwrap()
+            }
+        };
+        let registered = parameters("alice").await;
+        let unknown = parameters("bob").await;
+        assert_eq!(unknown.kdf, registered.kdf);
+        assert_eq!(unknown.salt.len(), registered.salt.len());
+        assert!(unknown.validate().is_ok());
+        assert_eq!(parameters("bob").await, unknown);
+        assert_ne!(parameters("carol").await.salt, unknown.salt);
+    }
+
+    // Encodings of zero, the identity and an element of order two in the given group.
+    fn invalid_elements(use_ec: bool) -> Vec<Vec<u8>> {
+        let e = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256);
+        if use_ec {
+            return vec![vec![0u8; 32], vec![0xff; 32], vec![1u8; 16]];
+        }
+        let size = (e.p.bits() as usize).div_ceil(8);
+        let encode = |value: BigUint| {
+            let bytes = value.to_bytes_be();
+            let mut encoded = vec![0u8; size - bytes.len()];
+            encoded.extend_from_slice(&bytes);
+            encoded
+        };
+        vec![
+            vec![0u8; size],
+            encode(BigUint::one()),
+            encode(&e.p - 1u32),
+            e.p.to_bytes_be(),
+        ]
+    }
+
+    // Registrations must only be accepted for elements of the prime order subgroup.
+    #[test_case(true; "when using elliptic curve")]
+    #[test_case(false; "when using exponentiation")]
+    #[tokio::test]
+    async fn registration_should_reject_invalid_elements(use_ec: bool) {
+        let state = server(use_ec);
+        let valid = register_request(
+            &state,
+            "alice",
+            (&KeyDerivation::new(KdfAlgorithm::Argon2id)).into(),
+        );
+        for invalid in invalid_elements(use_ec) {
+            for request in [
+                RegisterRequest {
+                    y1: invalid.clone(),
+                    ..valid.clone()
+                },
+                RegisterRequest {
+                    y2: invalid.clone(),
+                    ..valid.clone()
+                },
+            ] {
+                let status = state.register(Request::new(request)).await.unwrap_err();
+                assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            }
+        }
+        assert!(state.register(Request::new(valid)).await.is_ok());
+    }
+
+    // Challenges must only be created for commitments in the prime order subgroup.
+    #[test_case(true; "when using elliptic curve")]
+    #[test_case(false; "when using exponentiation")]
+    #[tokio::test]
+    async fn challenge_should_reject_invalid_elements(use_ec: bool) {
+        let state = server(use_ec);
+        let (r1, r2) = state
+            .primary()
+            .authentication(&state.primary().get_random())
+            .unwrap();
+        let valid = AuthenticationChallengeRequest {
+            user: "alice".to_string(),
+            r1,
+            r2,
+        };
+        for invalid in invalid_elements(use_ec) {
+            for request in [
+                AuthenticationChallengeRequest {
+                    r1: invalid.clone(),
+                    ..valid.clone()
+                },
+                AuthenticationChallengeRequest {
+                    r2: invalid.clone(),
+                    ..valid.clone()
+                },
+            ] {
+                let status = state
+                    .create_authentication_challenge(Request::new(request))
+                    .await
+                    .unwrap_err();
+                assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            }
+        }
+        assert!(state
+            .create_authentication_challenge(Request::new(valid))
+            .await
+            .is_ok());
+    }
+
+    // A proof answering the hash of the transcript must authenticate once, and never again with
+    // the same nonce.
+    #[test_case(true; "when using elliptic curve")]
+    #[test_case(false; "when using exponentiation")]
+    #[tokio::test]
+    async fn proofs_should_verify_once(use_ec: bool) {
+        let state = server(use_ec);
+        let auth = state.primary();
+        let secret = auth.get_random();
+        let (y1, y2) = auth.registration(&secret).unwrap();
+        let kdf = KeyDerivation::new(KdfAlgorithm::Argon2id);
+        let request = RegisterRequest {
+            user: "alice".to_string(),
+            y1: y1.clone(),
+            y2: y2.clone(),
+            kdf: Some((&kdf).into()),
+            auth: auth.auth_type() as i32,
+        };
+        state.register(Request::new(request)).await.unwrap();
+
+        let prove = |user: &str, nonce: Vec<u8>| {
+            let k = auth.get_random();
+            let (r1, r2) = auth.authentication(&k).unwrap();
+            let transcript = Transcript {
+                user: "alice",
+                y1: &y1,
+                y2: &y2,
+                r1: &r1,
+                r2: &r2,
+                nonce: &nonce,
+            };
+            let c = auth.proof_challenge(&transcript).unwrap();
+            let s = auth.response(&k, &secret, &c).unwrap();
+            NonInteractiveProof {
+                user: user.to_string(),
+                nonce,
+                r1,
+                r2,
+                s,
+            }
+        };
+        let nonce = |user: &str| {
+            let request = ProofNonceRequest {
+                user: user.to_string(),
+            };
+            async {
+                state
+                    .create_proof_nonce(Request::new(request))
+                    .await
+                    .unwrap()
+                    .into_inner()
+                    .nonce
+            }
+        };
+
+        let proof = prove("alice", nonce("alice").await);
+        assert!(state
+            .verify_proof(Request::new(proof.clone()))
+            .await
+            .is_ok());
+        let replayed = state.verify_proof(Request::new(proof)).await.unwrap_err();
+        assert_eq!(replayed.code(), tonic::Code::NotFound);
+
+        let unissued = state
+            .verify_proof(Request::new(prove("alice", vec![0u8; 32])))
+            .await
+            .unwrap_err();
+        assert_eq!(unissued.code(), tonic::Code::NotFound);
+
+        let stolen = prove("alice", nonce("bob").await);
+        let status = state.verify_proof(Request::new(stolen)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let mut forged = prove("alice", nonce("alice").await);
+        forged.s = auth.get_random();
+        let status = state.verify_proof(Request::new(forged)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+
+    // Registrations kept in a persistent store must survive a restart of the server.
+    #[tokio::test]
+    async fn registrations_should_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registrations.db");
+        let open = || {
+            ServerState::new(
+                &[AuthenticationType::EllipticCurve],
+                Exponentiation::new(),
+                Box::new(SqliteStore::open(&path).unwrap()),
+                Box::new(SqliteStore::open(&path).unwrap()),
+                ChallengePolicy::default(),
+                Duration::from_secs(DEFAULT_SESSION_TTL_SECS),
+            )
+        };
+        let kdf = KeyDerivation::new(KdfAlgorithm::Argon2id);
+        {
+            let state = open();
+            let request = register_request(&state, "alice", (&kdf).into());
+            state.register(Request::new(request)).await.unwrap();
+        }
+        let response = open()
+            .get_kdf_parameters(Request::new(KdfParametersRequest {
+                user: "alice".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(KeyDerivation::try_from(response.kdf.unwrap()).unwrap(), kdf);
+    }
+
+    // Register a user with a random secret, returning the secret.
+    async fn register(state: &ServerState, user: &str) -> Vec<u8> {
+        register_as(state, user, state.primary()).await
+    }
+
+    // Register a user with a random secret for the authentication type, returning the secret.
+    async fn register_as(state: &ServerState, user: &str, auth: &dyn Authenticate) -> Vec<u8> {
+        let secret = auth.get_random();
+        let (y1, y2) = auth.registration(&secret).unwrap();
+        let kdf = KeyDerivation::new(KdfAlgorithm::Argon2id);
+        let request = RegisterRequest {
+            user: user.to_string(),
+            y1,
+            y2,
+            kdf: Some((&kdf).into()),
+            auth: auth.auth_type() as i32,
+        };
+        state.register(Request::new(request)).await.unwrap();
+        secret
+    }
+
+    // Ask for a challenge, returning its auth_id and the correct answer to it.
+    async fn challenge(state: &ServerState, user: &str, secret: &[u8]) -> (String, Vec<u8>) {
+        challenge_as(state, user, secret, state.primary()).await
+    }
+
+    // Ask for a challenge for commitments of the authentication type, returning its auth_id and
+    // the correct answer to it.
+    async fn challenge_as(
+        state: &ServerState,
+        user: &str,
+        secret: &[u8],
+        auth: &dyn Authenticate,
+    ) -> (String, Vec<u8>) {
+        let k = auth.get_random();
+        let (r1, r2) = auth.authentication(&k).unwrap();
+        let request = AuthenticationChallengeRequest {
+            user: user.to_string(),
+            r1,
+            r2,
+        };
+        let response = state
+            .create_authentication_challenge(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        let s = auth.response(&k, secret, &response.c).unwrap();
+        (response.auth_id, s)
+    }
+
+    // Answer a challenge, returning the status code.
+    async fn answer(state: &ServerState, auth_id: &str, s: Vec<u8>) -> tonic::Code {
+        let request = AuthenticationAnswerRequest {
+            auth_id: auth_id.to_string(),
+            s,
+        };
+        match state.verify_authentication(Request::new(request)).await {
+            Ok(_) => tonic::Code::Ok,
+            Err(status) => status.code(),
+        }
+    }
+
+    // An auth_id must only be answerable once, whether the first answer was right or wrong.
+    #[test_case(true; "when using elliptic curve")]
+    #[test_case(false; "when using exponentiation")]
+    #[tokio::test]
+    async fn challenges_should_only_be_answered_once(use_ec: bool) {
+        let state = server(use_ec);
+        let secret = register(&state, "alice").await;
+
+        let (auth_id, s) = challenge(&state, "alice", &secret).await;
+        assert_eq!(answer(&state, &auth_id, s.clone()).await, tonic::Code::Ok);
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::NotFound);
+
+        let (auth_id, s) = challenge(&state, "alice", &secret).await;
+        let wrong = state.primary().get_random();
+        assert_eq!(
+            answer(&state, &auth_id, wrong).await,
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::NotFound);
+    }
+
+    // Challenges must not be answerable once they have expired.
+    #[tokio::test]
+    async fn expired_challenges_should_not_be_found() {
+        let state = ServerState::new(
+            &[AuthenticationType::EllipticCurve],
+            Exponentiation::new(),
+            Box::new(MemoryStore::new()),
+            Box::new(MemoryStore::new()),
+            ChallengePolicy {
+                ttl: Duration::from_millis(50),
+                ..ChallengePolicy::default()
+            },
+            Duration::from_secs(DEFAULT_SESSION_TTL_SECS),
+        );
+        let secret = register(&state, "alice").await;
+        let (auth_id, s) = challenge(&state, "alice", &secret).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::NotFound);
+    }
+
+    // Registrations without a key derivation, or with one too weak to slow down a dictionary
+    // attack, must be refused.
+    #[test_case(KdfParameters::default(); "when kdf is missing")]
+    #[test_case((&KeyDerivation { kdf: Kdf::Pbkdf2 { rounds: 1 }, ..KeyDerivation::new(KdfAlgorithm::Pbkdf2) }).into(); "when kdf is too weak")]
+    #[test_case((&KeyDerivation { salt: Vec::new(), ..KeyDerivation::new(KdfAlgorithm::Argon2id) }).into(); "when salt is missing")]
+    #[tokio::test]
+    async fn weak_kdf_parameters_should_be_rejected(kdf: KdfParameters) {
+        let state = server(true);
+        let request = register_request(&state, "alice", kdf);
+        let status = state.register(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    // Registering a user a second time must fail and keep the original registration.
+    #[tokio::test]
+    async fn registering_twice_should_be_refused() {
+        let state = server(true);
+        let secret = register(&state, "alice").await;
+
+        let request = register_request(
+            &state,
+            "alice",
+            (&KeyDerivation::new(KdfAlgorithm::Argon2id)).into(),
+        );
+        let status = state.register(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+
+        let (auth_id, s) = challenge(&state, "alice", &secret).await;
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::Ok);
+    }
+
+    // Ask for a challenge to change the secret of the user to a new random secret, returning its
+    // auth_id, the answer to it computed with `secret` and the new secret.
+    async fn change_challenge(
+        state: &ServerState,
+        user: &str,
+        secret: &[u8],
+    ) -> (String, Vec<u8>, Vec<u8>) {
+        let auth = state.primary();
+        let new = auth.get_random();
+        let (y1, y2) = auth.registration(&new).unwrap();
+        let k = auth.get_random();
+        let (r1, r2) = auth.authentication(&k).unwrap();
+        let request = ChangeSecretChallengeRequest {
+            user: user.to_string(),
+            r1,
+            r2,
+            y1,
+            y2,
+            kdf: Some((&KeyDerivation::new(KdfAlgorithm::Argon2id)).into()),
+            auth: auth.auth_type() as i32,
+        };
+        let response = state
+            .create_change_secret_challenge(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        let s = auth.response(&k, secret, &response.c).unwrap();
+        (response.auth_id, s, new)
+    }
+
+    // Answer a change secret challenge, returning the status code.
+    async fn change_secret(state: &ServerState, auth_id: &str, s: Vec<u8>) -> tonic::Code {
+        let request = ChangeSecretRequest {
+            auth_id: auth_id.to_string(),
+            s,
+        };
+        match state.change_secret(Request::new(request)).await {
+            Ok(_) => tonic::Code::Ok,
+            Err(status) => status.code(),
+        }
+    }
+
+    // The secret must only be changed by a user who proves knowledge of the current secret, after
+    // which only the new secret authenticates.
+    #[test_case(true; "when using elliptic curve")]
+    #[test_case(false; "when using exponentiation")]
+    #[tokio::test]
+    async fn secrets_should_only_change_with_a_proof_of_the_current_secret(use_ec: bool) {
+        let state = server(use_ec);
+        let old = register(&state, "alice").await;
+
+        let wrong = state.primary().get_random();
+        let (auth_id, s, _) = change_challenge(&state, "alice", &wrong).await;
+        assert_eq!(
+            change_secret(&state, &auth_id, s).await,
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            change_secret(&state, "unknown", old.clone()).await,
+            tonic::Code::NotFound
+        );
+
+        let (auth_id, s) = challenge(&state, "alice", &old).await;
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::Ok);
+
+        let (auth_id, s, new) = change_challenge(&state, "alice", &old).await;
+        assert_eq!(change_secret(&state, &auth_id, s).await, tonic::Code::Ok);
+
+        let (auth_id, s) = challenge(&state, "alice", &old).await;
+        assert_eq!(
+            answer(&state, &auth_id, s).await,
+            tonic::Code::Unauthenticated
+        );
+        let (auth_id, s) = challenge(&state, "alice", &new).await;
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::Ok);
+    }
+
+    // A sign in challenge must not change the secret, and a change secret challenge must not sign
+    // in, so that neither answer can be put to the other use.
+    #[tokio::test]
+    async fn challenges_should_only_be_used_for_their_purpose() {
+        let state = server(true);
+        let secret = register(&state, "alice").await;
+
+        let (auth_id, s) = challenge(&state, "alice", &secret).await;
+        assert_eq!(
+            change_secret(&state, &auth_id, s).await,
+            tonic::Code::FailedPrecondition
+        );
+        let (auth_id, s, _) = change_challenge(&state, "alice", &secret).await;
+        assert_eq!(
+            answer(&state, &auth_id, s).await,
+            tonic::Code::FailedPrecondition
+        );
+
+        let (auth_id, s) = challenge(&state, "alice", &secret).await;
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::Ok);
+    }
+
+    // Of two changes issued for the same current secret only the first answered must succeed, the
+    // second answers for a secret that has since been replaced.
+    #[tokio::test]
+    async fn concurrent_changes_should_only_apply_once() {
+        let state = server(true);
+        let old = register(&state, "alice").await;
+
+        let (first_id, first_s, first) = change_challenge(&state, "alice", &old).await;
+        let (second_id, second_s, _) = change_challenge(&state, "alice", &old).await;
+        assert_eq!(
+            change_secret(&state, &first_id, first_s).await,
+            tonic::Code::Ok
+        );
+        assert_eq!(
+            change_secret(&state, &second_id, second_s).await,
+            tonic::Code::Unauthenticated
+        );
+
+        let (auth_id, s) = challenge(&state, "alice", &first).await;
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::Ok);
+    }
+
+    // A change secret challenge must not be issued for a new registration that is invalid.
+    #[tokio::test]
+    async fn change_challenges_should_reject_invalid_registrations() {
+        let state = server(true);
+        register(&state, "alice").await;
+        let auth = state.primary();
+        let (r1, r2) = auth.authentication(&auth.get_random()).unwrap();
+        let (y1, y2) = auth.registration(&auth.get_random()).unwrap();
+        for (y1, y2, kdf) in [
+            (
+                invalid_elements(true).remove(0),
+                y2.clone(),
+                Some((&KeyDerivation::new(KdfAlgorithm::Argon2id)).into()),
+            ),
+            (y1, y2, None),
+        ] {
+            let request = ChangeSecretChallengeRequest {
+                user: "alice".to_string(),
+                r1: r1.clone(),
+                r2: r2.clone(),
+                y1,
+                y2,
+                kdf,
+                auth: auth.auth_type() as i32,
+            };
+            let status = state
+                .create_change_secret_challenge(Request::new(request))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    // Sign in with a fresh challenge, returning the session started for the user.
+    async fn sign_in(
+        state: &ServerState,
+        user: &str,
+        secret: &[u8],
+    ) -> AuthenticationAnswerResponse {
+        let (auth_id, s) = challenge(state, user, secret).await;
+        let request = AuthenticationAnswerRequest { auth_id, s };
+        state
+            .verify_authentication(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    // A session request for the session id.
+    fn session_request(session_id: &str) -> Request<SessionRequest> {
+        Request::new(SessionRequest {
+            session_id: session_id.to_string(),
+        })
+    }
+
+    // A session must be valid for the user who signed in until it is revoked, and refreshing it
+    // must push back its expiry.
+    #[test_case(true; "when using elliptic curve")]
+    #[test_case(false; "when using exponentiation")]
+    #[tokio::test]
+    async fn sessions_should_be_valid_until_revoked(use_ec: bool) {
+        let state = server(use_ec);
+        let secret = register(&state, "alice").await;
+        let signed_in = sign_in(&state, "alice", &secret).await;
+        assert!(signed_in.expires > unix_now());
+
+        let session = state
+            .validate_session(session_request(&signed_in.session_id))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(session.user, "alice");
+        assert_eq!(session.auth(), state.primary().auth_type());
+        assert_eq!(session.expires, signed_in.expires);
+
+        let refreshed = state
+            .refresh_session(session_request(&signed_in.session_id))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(refreshed.expires >= session.expires);
+        assert_eq!(refreshed.created, session.created);
+
+        state
+            .revoke_session(session_request(&signed_in.session_id))
+            .await
+            .unwrap();
+        let validated = state
+            .validate_session(session_request(&signed_in.session_id))
+            .await;
+        assert_eq!(validated.unwrap_err().code(), tonic::Code::NotFound);
+        let refreshed = state
+            .refresh_session(session_request(&signed_in.session_id))
+            .await;
+        assert_eq!(refreshed.unwrap_err().code(), tonic::Code::NotFound);
+        let revoked = state
+            .revoke_session(session_request(&signed_in.session_id))
+            .await;
+        assert_eq!(revoked.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    // Sessions must neither validate nor be refreshed once they have expired.
+    #[tokio::test]
+    async fn expired_sessions_should_not_be_found() {
+        let state = ServerState::new(
+            &[AuthenticationType::EllipticCurve],
+            Exponentiation::new(),
+            Box::new(MemoryStore::new()),
+            Box::new(MemoryStore::new()),
+            ChallengePolicy::default(),
+            Duration::ZERO,
+        );
+        let secret = register(&state, "alice").await;
+        let signed_in = sign_in(&state, "alice", &secret).await;
+
+        let validated = state
+            .validate_session(session_request(&signed_in.session_id))
+            .await;
+        assert_eq!(validated.unwrap_err().code(), tonic::Code::NotFound);
+        let refreshed = state
+            .refresh_session(session_request(&signed_in.session_id))
+            .await;
+        assert_eq!(refreshed.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    // Tokens must only be issued when the server has a signing key, and must verify against the
+    // published key with the details of the session.
+    #[tokio::test]
+    async fn tokens_should_verify_against_the_published_key() {
+        let state = server(true);
+        let secret = register(&state, "alice").await;
+        assert!(sign_in(&state, "alice", &secret).await.token.is_empty());
+        let status = state
+            .get_token_key(Request::new(TokenKeyRequest {}))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let state = state.with_token_signer(TokenSigner::generate());
+        let signed_in = sign_in(&state, "alice", &secret).await;
+        let key = state
+            .get_token_key(Request::new(TokenKeyRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(key.algorithm, ALGORITHM);
+        let verifier = TokenVerifier::new(&key.public_key).unwrap();
+        let claims = verifier.verify(&signed_in.token, unix_now()).unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.auth, "EllipticCurve");
+        assert_eq!(claims.exp, signed_in.expires);
+
+        let refreshed = state
+            .refresh_session(session_request(&signed_in.session_id))
+            .await
+            .unwrap()
+            .into_inner();
+        let claims = verifier.verify(&refreshed.token, unix_now()).unwrap();
+        assert_eq!(claims.exp, refreshed.expires);
+    }
+
+    // A server limiting failed attempts with the policy.
+    fn limited_server(policy: RateLimitPolicy) -> ServerState {
+        server(true).with_rate_limit_policy(policy)
+    }
+
+    // A request carrying the message, made from the peer address if one is given.
+    fn from_peer<T>(message: T, peer: Option<IpAddr>) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(peer) = peer {
+            request
+                .extensions_mut()
+                .insert(tonic::transport::server::TcpConnectInfo {
+                    local_addr: None,
+                    remote_addr: Some((peer, 50051).into()),
+                });
+        }
+        request
+    }
+
+    // Ask for a challenge for the user from the peer address, returning its auth_id or the status
+    // code of the refusal.
+    async fn challenge_from(
+        state: &ServerState,
+        user: &str,
+        peer: Option<IpAddr>,
+    ) -> Result<String, tonic::Code> {
+        let (r1, r2) = state
+            .primary()
+            .authentication(&state.primary().get_random())
+            .unwrap();
+        let request = AuthenticationChallengeRequest {
+            user: user.to_string(),
+            r1,
+            r2,
+        };
+        state
+            .create_authentication_challenge(from_peer(request, peer))
+            .await
+            .map(|response| response.into_inner().auth_id)
+            .map_err(|status| status.code())
+    }
+
+    // Answer a fresh challenge for the user wrongly from the peer address, returning the status
+    // code of the answer.
+    async fn fail(state: &ServerState, user: &str, peer: Option<IpAddr>) -> tonic::Code {
+        let auth_id = challenge_from(state, user, peer).await.unwrap();
+        let request = AuthenticationAnswerRequest {
+            auth_id,
+            s: state.primary().get_random(),
+        };
+        match state.verify_authentication(from_peer(request, peer)).await {
+            Ok(_) => tonic::Code::Ok,
+            Err(status) => status.code(),
+        }
+    }
+
+    // Once the free failures are used up, further attempts must be refused until the backoff has
+    // passed, without affecting other users.
+    #[tokio::test]
+    async fn failures_should_back_off() {
+        let state = limited_server(RateLimitPolicy {
+            free_failures: 1,
+            backoff_base: Duration::from_secs(60),
+            ..RateLimitPolicy::default()
+        });
+        register(&state, "alice").await;
+        let secret = register(&state, "bob").await;
+
+        assert_eq!(
+            fail(&state, "alice", None).await,
+            tonic::Code::Unauthenticated
+        );
+        let (auth_id, s) = challenge(&state, "alice", &secret).await;
+        assert_eq!(
+            fail(&state, "alice", None).await,
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            challenge_from(&state, "alice", None).await,
+            Err(tonic::Code::ResourceExhausted)
+        );
+        // Challenges issued before the backoff cannot be answered during it
+        assert_eq!(
+            answer(&state, &auth_id, s).await,
+            tonic::Code::ResourceExhausted
+        );
+        sign_in(&state, "bob", &secret).await;
+    }
+
+    // Too many failures must lock the user out, with the lockout recorded in the registration
+    // store so that it outlasts the counters kept in memory.
+    #[tokio::test]
+    async fn failures_should_lock_out() {
+        let state = limited_server(RateLimitPolicy {
+            free_failures: 0,
+            backoff_base: Duration::ZERO,
+            lockout_failures: 2,
+            ..RateLimitPolicy::default()
+        });
+        register(&state, "alice").await;
+
+        assert_eq!(
+            fail(&state, "alice", None).await,
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            fail(&state, "alice", None).await,
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            challenge_from(&state, "alice", None).await,
+            Err(tonic::Code::PermissionDenied)
+        );
+        let registration = state.registrations.get("alice").unwrap().unwrap();
+        assert!(registration.is_locked(unix_now()));
+
+        let state = state.with_rate_limit_policy(RateLimitPolicy::default());
+        assert_eq!(
+            challenge_from(&state, "alice", None).await,
+            Err(tonic::Code::PermissionDenied)
+        );
+    }
+
+    // Failures must be counted per peer address as well, holding back guesses at other users from
+    // the same address.
+    #[tokio::test]
+    async fn failures_should_be_counted_per_address() {
+        let state = limited_server(RateLimitPolicy {
+            free_failures: 1,
+            backoff_base: Duration::from_secs(60),
+            ..RateLimitPolicy::default()
+        });
+        let peer = Some(IpAddr::from([192, 0, 2, 1]));
+        register(&state, "alice").await;
+        register(&state, "bob").await;
+
+        assert_eq!(
+            fail(&state, "alice", peer).await,
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            fail(&state, "bob", peer).await,
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            challenge_from(&state, "carol", peer).await,
+            Err(tonic::Code::ResourceExhausted)
+        );
+        assert!(challenge_from(&state, "carol", None).await.is_ok());
+        let other = Some(IpAddr::from([192, 0, 2, 2]));
+        assert!(challenge_from(&state, "carol", other).await.is_ok());
+    }
+
+    // The type the user registered with, or the primary type for a user who has not, and the
+    // primary type that new registrations should use.
+    async fn auth_types(state: &ServerState, user: &str) -> (i32, i32) {
+        let response = state
+            .get_auth_type(Request::new(AuthTypeRequest {
+                user: user.to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        (response.auth, response.preferred)
+    }
+
+    // Users registered with different types must each be told their own type, and authenticate
+    // with it into sessions of that type.
+    #[tokio::test]
+    async fn several_types_should_be_served_at_once() {
+        let state = serving(&[
+            AuthenticationType::Exponentiation,
+            AuthenticationType::EllipticCurve,
+        ]);
+        let exponentiation = state.authenticator(0).unwrap();
+        let elliptic_curve = state.authenticator(1).unwrap();
+        let alice = register_as(&state, "alice", exponentiation).await;
+        let bob = register_as(&state, "bob", elliptic_curve).await;
+
+        assert_eq!(auth_types(&state, "alice").await, (0, 0));
+        assert_eq!(auth_types(&state, "bob").await, (1, 0));
+        assert_eq!(auth_types(&state, "carol").await, (0, 0));
+
+        for (user, secret, auth) in [
+            ("alice", &alice, exponentiation),
+            ("bob", &bob, elliptic_curve),
+        ] {
+            let (auth_id, s) = challenge_as(&state, user, secret, auth).await;
+            let request = AuthenticationAnswerRequest { auth_id, s };
+            let signed_in = state
+                .verify_authentication(Request::new(request))
+                .await
+                .unwrap()
+                .into_inner();
+            let session = state
+                .validate_session(session_request(&signed_in.session_id))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(session.auth(), auth.auth_type());
+        }
+
+        // Commitments of the other type are not group elements of the user's type
+        let (r1, r2) = exponentiation
+            .authentication(&exponentiation.get_random())
+            .unwrap();
+        let request = AuthenticationChallengeRequest {
+            user: "bob".to_string(),
+            r1,
+            r2,
+        };
+        let status = state
+            .create_authentication_challenge(Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    // Registrations for a type the server does not serve must be refused.
+    #[tokio::test]
+    async fn unserved_types_should_be_refused() {
+        let state = server(false);
+        let elliptic_curve = EllipticCurve::new();
+        let secret = elliptic_curve.get_random();
+        let (y1, y2) = elliptic_curve.registration(&secret).unwrap();
+        for auth in [AuthenticationType::EllipticCurve as i32, 7] {
+            let request = RegisterRequest {
+                user: "alice".to_string(),
+                y1: y1.clone(),
+                y2: y2.clone(),
+                kdf: Some((&KeyDerivation::new(KdfAlgorithm::Argon2id)).into()),
+                auth,
+            };
+            let status = state.register(Request::new(request)).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert!(status.message().contains("not served"));
+        }
+    }
+
+    // Changing the secret with a registration of another type must move the user over to that
+    // type, after which only the new type authenticates.
+    #[tokio::test]
+    async fn changing_the_secret_should_migrate_the_type() {
+        let state = serving(&[
+            AuthenticationType::EllipticCurve,
+            AuthenticationType::Exponentiation,
+        ]);
+        let exponentiation = state.authenticator(0).unwrap();
+        let elliptic_curve = state.authenticator(1).unwrap();
+        let old = register_as(&state, "alice", exponentiation).await;
+        assert_eq!(auth_types(&state, "alice").await, (0, 1));
+
+        let new = elliptic_curve.get_random();
+        let (y1, y2) = elliptic_curve.registration(&new).unwrap();
+        let k = exponentiation.get_random();
+        let (r1, r2) = exponentiation.authentication(&k).unwrap();
+        let request = ChangeSecretChallengeRequest {
+            user: "alice".to_string(),
+            r1,
+            r2,
+            y1,
+            y2,
+            kdf: Some((&KeyDerivation::new(KdfAlgorithm::Argon2id)).into()),
+            auth: elliptic_curve.auth_type() as i32,
+        };
+        let response = state
+            .create_change_secret_challenge(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        let s = exponentiation.response(&k, &old, &response.c).unwrap();
+        assert_eq!(
+            change_secret(&state, &response.auth_id, s).await,
+            tonic::Code::Ok
+        );
+
+        assert_eq!(auth_types(&state, "alice").await, (1, 1));
+        let (auth_id, s) = challenge_as(&state, "alice", &new, elliptic_curve).await;
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::Ok);
+    }
+
+    // A challenge must not be answered for a registration whose type changed after it was issued.
+    #[tokio::test]
+    async fn challenges_should_not_outlive_a_change_of_type() {
+        let state = serving(&[
+            AuthenticationType::Exponentiation,
+            AuthenticationType::EllipticCurve,
+        ]);
+        let exponentiation = state.authenticator(0).unwrap();
+        let secret = register_as(&state, "alice", exponentiation).await;
+        let (auth_id, s) = challenge_as(&state, "alice", &secret, exponentiation).await;
+
+        let current = state.registrations.get("alice").unwrap().unwrap();
+        let replacement = Registration {
+            auth_type: Some(AuthenticationType::EllipticCurve as i32),
+            ..current.clone()
+        };
+        state.registrations.replace(&current, replacement).unwrap();
+        assert_eq!(answer(&state, &auth_id, s).await, tonic::Code::Aborted);
+    }
+
+    // Registrations kept before the type was recorded must use the primary type.
+    #[tokio::test]
+    async fn unrecorded_types_should_use_the_primary_type() {
+        let state = serving(&[
+            AuthenticationType::EllipticCurve,
+            AuthenticationType::Exponentiation,
+        ]);
+        let secret = register(&state, "alice").await;
+        let current = state.registrations.get("alice").unwrap().unwrap();
+        let replacement = Registration {
+            auth_type: None,
+            ..current.clone()
+        };
+        state.registrations.replace(&current, replacement).unwrap();
+
+        assert_eq!(auth_types(&state, "alice").await, (1, 1));
+        let signed_in = sign_in(&state, "alice", &secret).await;
+        assert!(!signed_in.session_id.is_empty());
+    }
+
+    // The exponentiation group must be advertised exactly when exponentiation is served.
+    #[test_case(true; "when using elliptic curve")]
+    #[test_case(false; "when using exponentiation")]
+    #[tokio::test]
+    async fn the_group_should_be_advertised_with_exponentiation(use_ec: bool) {
+        let state = server(use_ec);
+        let response = state
+            .get_auth_type(Request::new(AuthTypeRequest {
+                user: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let expected = Exponentiation::with_group(GroupId::Rfc5114Modp2048Q256);
+        assert_eq!(
+            response
+                .group
+                .map(|group| Exponentiation::from(group).fingerprint()),
+            (!use_ec).then(|| expected.fingerprint())
+        );
+    }
+
+    // A client of the same build must agree with the server on every type it serves.
+    #[tokio::test]
+    async fn hello_should_offer_the_types_served() {
+        let state = serving(&[
+            AuthenticationType::EllipticCurve,
+            AuthenticationType::Exponentiation,
+        ]);
+        let offer = state
+            .hello(Request::new(HelloRequest {
+                protocol_version: capabilities::PROTOCOL_VERSION,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let negotiated = capabilities::negotiate(&offer).unwrap();
+        assert_eq!(
+            negotiated.auth_types,
+            vec![
+                AuthenticationType::EllipticCurve,
+                AuthenticationType::Exponentiation
+            ]
+        );
+    }
 }
@@ -0,0 +1,405 @@
+use super::{unix_now, Registration, RegistrationStore, Session, SessionStore};
+use crate::errors::StorageError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Define a registration store backed by an append-only file holding one JSON registration per
+// line. Registrations are never rewritten in place, a later line for the same user replaces the
+// earlier ones, so an interrupted write can at worst lose the registration being written.
+pub struct FileStore {
+    inner: Mutex<FileStoreInner>,
+}
+
+// The open file and the registrations read from it, kept together behind one lock so that the
+// file and the index never disagree.
+struct FileStoreInner {
+    file: File,
+    registrations: HashMap<String, Registration>,
+}
+
+impl FileStore {
+    // Open the file, creating it if it does not exist, and read every registration in it.
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut registrations = HashMap::new();
+        for (number, line) in BufReader::new(&file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let registration: Registration = serde_json::from_str(&line).map_err(|e| {
+                StorageError::Corrupt(format!("{} line {}: {}", path.display(), number + 1, e))
+            })?;
+            registrations.insert(registration.user.clone(), registration);
+        }
+        Ok(FileStore {
+            inner: Mutex::new(FileStoreInner {
+                file,
+                registrations,
+            }),
+        })
+    }
+}
+
+impl FileStore {
+    // Append a registration, which must replace the registration `current` of the user if it is
+    // given, and must be the first registration of the user otherwise. The check and the write
+    // happen under one lock.
+    fn append(
+        &self,
+        current: Option<&Registration>,
+        registration: Registration,
+    ) -> Result<(), StorageError> {
+        let mut line = serde_json::to_string(&registration)
+            .map_err(|e| StorageError::Corrupt(e.to_string()))?;
+        line.push('\n');
+        let mut inner = self.inner.lock().map_err(|_| StorageError::LockPoisoned)?;
+        match (inner.registrations.get(&registration.user), current) {
+            (Some(_), None) => return Err(StorageError::AlreadyRegistered),
+            (None, Some(_)) => return Err(StorageError::UnableToFindRegistration),
+            (Some(existing), Some(current)) if existing != current => {
+                return Err(StorageError::RegistrationChanged)
+            }
+            _ => {}
+        }
+        // The registration is only visible once it is safely on disk
+        inner.file.write_all(line.as_bytes())?;
+        inner.file.sync_data()?;
+        inner
+            .registrations
+            .insert(registration.user.clone(), registration);
+        Ok(())
+    }
+}
+
+impl RegistrationStore for FileStore {
+    fn get(&self, user: &str) -> Result<Option<Registration>, StorageError> {
+        let inner = self.inner.lock().map_err(|_| StorageError::LockPoisoned)?;
+        Ok(inner.registrations.get(user).cloned())
+    }
+
+    fn insert(&self, registration: Registration) -> Result<(), StorageError> {
+        self.append(None, registration)
+    }
+
+    fn replace(
+        &self,
+        current: &Registration,
+        replacement: Registration,
+    ) -> Result<(), StorageError> {
+        self.append(Some(current), replacement)
+    }
+
+    fn set_locked_until(&self, user: &str, until: Option<u64>) -> Result<(), StorageError> {
+        // Retry if the registration changed between reading it and appending the new line
+        loop {
+            let current = self
+                .get(user)?
+                .ok_or(StorageError::UnableToFindRegistration)?;
+            let locked = Registration {
+                locked_until: until,
+                ..current.clone()
+            };
+            match self.append(Some(&current), locked) {
+                Err(StorageError::RegistrationChanged) => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+// A change to the sessions, appended to the sessions file as one JSON line. A later line for the same
+// session replaces the earlier ones.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SessionRecord {
+    // A session that started or whose expiry moved
+    Session(Session),
+    // A session that was revoked
+    Removed(String),
+}
+
+// Number of lines of the sessions file that no longer describe a live session past which the file is
+// rewritten, as long as they also outnumber the live sessions
+const SESSION_COMPACTION_THRESHOLD: usize = 1024;
+
+// Define a session store backed by an append-only file holding one JSON change per line, kept next
+// to the registrations of a file registration store. Expired sessions are forgotten as new ones
+// start, and the file is rewritten with only the live sessions when it is opened or once enough of
+// its lines are dead, so that neither grows without bound.
+pub struct FileSessionStore {
+    inner: Mutex<FileSessionStoreInner>,
+}
+
+// The open file and the sessions read from it, kept together behind one lock so that the file and
+// the index never disagree.
+struct FileSessionStoreInner {
+    path: PathBuf,
+    file: File,
+    sessions: HashMap<String, Session>,
+    lines: usize,                // Number of lines in the file
+    compaction_threshold: usize, // Number of dead lines past which the file is rewritten
+}
+
+impl FileSessionStore {
+    // The sessions file that goes with the registrations file at `path`
+    pub fn path_for(path: &Path) -> PathBuf {
+        path.with_extension("sessions.jsonl")
+    }
+
+    // Open the file, creating it if it does not exist, read every session in it and compact it.
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        let mut sessions = HashMap::new();
+        match File::open(path) {
+            Ok(file) => {
+                for (number, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let record: SessionRecord = serde_json::from_str(&line).map_err(|e| {
+                        StorageError::Corrupt(format!(
+                            "{} line {}: {}",
+                            path.display(),
+                            number + 1,
+                            e
+                        ))
+                    })?;
+                    match record {
+                        SessionRecord::Session(session) => {
+                            sessions.insert(session.id.clone(), session)
+                        }
+                        SessionRecord::Removed(id) => sessions.remove(&id),
+                    };
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let now = unix_now();
+        sessions.retain(|_, session| !session.is_expired(now));
+
+        let file = compact(path, &sessions)?;
+        Ok(FileSessionStore {
+            inner: Mutex::new(FileSessionStoreInner {
+                path: path.to_path_buf(),
+                file,
+                lines: sessions.len(),
+                sessions,
+                compaction_threshold: SESSION_COMPACTION_THRESHOLD,
+            }),
+        })
+    }
+
+    // Append the record and apply it to the sessions
+    fn append(
+        inner: &mut FileSessionStoreInner,
+        record: SessionRecord,
+    ) -> Result<(), StorageError> {
+        // The change is only visible once it is safely on disk
+        inner.file.write_all(record_line(&record)?.as_bytes())?;
+        inner.file.sync_data()?;
+        inner.lines += 1;
+        match record {
+            SessionRecord::Session(session) => inner.sessions.insert(session.id.clone(), session),
+            SessionRecord::Removed(id) => inner.sessions.remove(&id),
+        };
+
+        let dead = inner.lines - inner.sessions.len();
+        if dead > inner.compaction_threshold && dead > inner.sessions.len() {
+            inner.file = compact(&inner.path, &inner.sessions)?;
+            inner.lines = inner.sessions.len();
+        }
+        Ok(())
+    }
+}
+
+// Write the sessions to a new file and swap it in for the one at `path`, so that an interrupted
+// compaction leaves the old file in place, returning the new file opened for appending
+fn compact(path: &Path, sessions: &HashMap<String, Session>) -> Result<File, StorageError> {
+    let compacted = path.with_extension("partial");
+    let mut file = File::create(&compacted)?;
+    for session in sessions.values() {
+        file.write_all(record_line(&SessionRecord::Session(session.clone()))?.as_bytes())?;
+    }
+    file.sync_data()?;
+    fs::rename(&compacted, path)?;
+    Ok(OpenOptions::new().append(true).open(path)?)
+}
+
+// Serialise a session record as a line of the sessions file
+fn record_line(record: &SessionRecord) -> Result<String, StorageError> {
+    let mut line =
+        serde_json::to_string(record).map_err(|e| StorageError::Corrupt(e.to_string()))?;
+    line.push('\n');
+    Ok(line)
+}
+
+impl SessionStore for FileSessionStore {
+    fn insert_session(&self, session: Session) -> Result<(), StorageError> {
+        let mut inner = self.inner.lock().map_err(|_| StorageError::LockPoisoned)?;
+        // Forget expired sessions as new ones start, so that the index does not grow without bound.
+        // Their lines are left to the next compaction
+        let now = unix_now();
+        inner.sessions.retain(|_, session| !session.is_expired(now));
+        Self::append(&mut inner, SessionRecord::Session(session))
+    }
+
+    fn get_session(&self, id: &str) -> Result<Option<Session>, StorageError> {
+        let inner = self.inner.lock().map_err(|_| StorageError::LockPoisoned)?;
+        Ok(inner.sessions.get(id).cloned())
+    }
+
+    fn extend_session(
+        &self,
+        id: &str,
+        now: u64,
+        expires: u64,
+    ) -> Result<Option<Session>, StorageError> {
+        let mut inner = self.inner.lock().map_err(|_| StorageError::LockPoisoned)?;
+        let Some(session) = inner
+            .sessions
+            .get(id)
+            .filter(|session| !session.is_expired(now))
+        else {
+            return Ok(None);
+        };
+        let extended = Session {
+            expires,
+            ..session.clone()
+        };
+        Self::append(&mut inner, SessionRecord::Session(extended.clone()))?;
+        Ok(Some(extended))
+    }
+
+    fn remove_session(&self, id: &str) -> Result<bool, StorageError> {
+        let mut inner = self.inner.lock().map_err(|_| StorageError::LockPoisoned)?;
+        if !inner.sessions.contains_key(id) {
+            return Ok(false);
+        }
+        Self::append(&mut inner, SessionRecord::Removed(id.to_string()))?;
+        Ok(true)
+    }
+}
+
+// Unit tests for the append-only file registration and session stores.
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::storage::tests::{
+            registration, session, store_should_insert_and_replace, store_should_keep_sessions,
+            store_should_record_lockouts, temporary_path,
+        },
+    };
+
+    #[test]
+    fn file_store_should_insert_and_replace() {
+        let (_dir, path) = temporary_path("registrations.jsonl");
+        store_should_insert_and_replace(&FileStore::open(&path).unwrap());
+    }
+
+    #[test]
+    fn file_store_should_record_lockouts() {
+        let (_dir, path) = temporary_path("registrations.jsonl");
+        store_should_record_lockouts(&FileStore::open(&path).unwrap());
+    }
+
+    // Registrations must survive reopening the file, with the latest registration winning.
+    #[test]
+    fn registrations_should_persist() {
+        let (_dir, path) = temporary_path("registrations.jsonl");
+        let alice = registration("alice", 1);
+        let bob = registration("bob", 2);
+        let replacement = registration("alice", 3);
+        {
+            let store = FileStore::open(&path).unwrap();
+            store.insert(alice.clone()).unwrap();
+            store.insert(bob.clone()).unwrap();
+            store.replace(&alice, replacement.clone()).unwrap();
+        }
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.get("alice").unwrap(), Some(replacement));
+        assert_eq!(store.get("bob").unwrap(), Some(bob));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+    }
+
+    // A file that does not hold registrations must be refused rather than silently ignored.
+    #[test]
+    fn corrupt_files_should_be_rejected() {
+        let (_dir, path) = temporary_path("registrations.jsonl");
+        std::fs::write(&path, "not a registration\n").unwrap();
+        assert!(matches!(
+            FileStore::open(&path),
+            Err(StorageError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn file_store_should_keep_sessions() {
+        let (_dir, path) = temporary_path("registrations.sessions.jsonl");
+        store_should_keep_sessions(&FileSessionStore::open(&path).unwrap());
+    }
+
+    // Sessions must survive reopening the file, while revoked and expired ones are left out of it.
+    #[test]
+    fn sessions_should_persist() {
+        let (_dir, path) = temporary_path("registrations.sessions.jsonl");
+        let now = unix_now();
+        let alice = session("a", "alice", now + 60);
+        {
+            let store = FileSessionStore::open(&path).unwrap();
+            store.insert_session(alice.clone()).unwrap();
+            store.insert_session(session("b", "bob", now + 60)).unwrap();
+            store.insert_session(session("c", "carol", now)).unwrap();
+            store.extend_session("a", now, now + 120).unwrap();
+            store.remove_session("b").unwrap();
+        }
+        let store = FileSessionStore::open(&path).unwrap();
+        let extended = Session {
+            expires: now + 120,
+            ..alice
+        };
+        assert_eq!(store.get_session("a").unwrap(), Some(extended));
+        assert_eq!(store.get_session("b").unwrap(), None);
+        assert_eq!(store.get_session("c").unwrap(), None);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+
+    // Expired sessions must be forgotten as new ones start, and the file rewritten once most of its
+    // lines are dead.
+    #[test]
+    fn expired_sessions_should_be_dropped() {
+        let (_dir, path) = temporary_path("registrations.sessions.jsonl");
+        let now = unix_now();
+        let store = FileSessionStore::open(&path).unwrap();
+        store.inner.lock().unwrap().compaction_threshold = 3;
+        for id in ["a", "b", "c"] {
+            store.insert_session(session(id, "alice", now)).unwrap();
+        }
+        store.insert_session(session("d", "bob", now + 60)).unwrap();
+        {
+            let inner = store.inner.lock().unwrap();
+            assert_eq!(inner.sessions.keys().collect::<Vec<_>>(), ["d"]);
+            assert_eq!(inner.lines, 4);
+        }
+
+        store.extend_session("d", now, now + 120).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        assert_eq!(store.inner.lock().unwrap().lines, 1);
+        store
+            .insert_session(session("e", "carol", now + 60))
+            .unwrap();
+        let store = FileSessionStore::open(&path).unwrap();
+        assert_eq!(store.get_session("d").unwrap().unwrap().expires, now + 120);
+        assert!(store.get_session("e").unwrap().is_some());
+    }
+}
@@ -0,0 +1,176 @@
+use super::{unix_now, Registration, RegistrationStore, Session, SessionStore};
+use crate::errors::StorageError;
+use moka::ops::compute::{CompResult, Op};
+use moka::sync::Cache;
+use moka::Expiry;
+use std::time::{Duration, Instant};
+
+// Define a registration and session store that only keeps them in memory, so they are lost when
+// the server stops.
+pub struct MemoryStore {
+    registrations: Cache<String, Registration>,
+    sessions: Cache<String, Session>,
+}
+
+// Expire each session from the cache at its own expiry, which moves when it is extended
+struct SessionExpiry;
+
+impl SessionExpiry {
+    fn remaining(session: &Session) -> Option<Duration> {
+        Some(Duration::from_secs(
+            session.expires.saturating_sub(unix_now()),
+        ))
+    }
+}
+
+impl Expiry<String, Session> for SessionExpiry {
+    fn expire_after_create(
+        &self,
+        _id: &String,
+        session: &Session,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Self::remaining(session)
+    }
+
+    fn expire_after_update(
+        &self,
+        _id: &String,
+        session: &Session,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Self::remaining(session)
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            registrations: Cache::builder().build(),
+            sessions: Cache::builder().expire_after(SessionExpiry).build(),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegistrationStore for MemoryStore {
+    fn get(&self, user: &str) -> Result<Option<Registration>, StorageError> {
+        Ok(self.registrations.get(user))
+    }
+
+    fn insert(&self, registration: Registration) -> Result<(), StorageError> {
+        // Only a fresh entry was inserted by this call, otherwise the user already existed
+        let entry = self
+            .registrations
+            .entry(registration.user.clone())
+            .or_insert(registration);
+        if !entry.is_fresh() {
+            return Err(StorageError::AlreadyRegistered);
+        }
+        Ok(())
+    }
+
+    fn replace(
+        &self,
+        current: &Registration,
+        replacement: Registration,
+    ) -> Result<(), StorageError> {
+        let user = replacement.user.clone();
+        match self
+            .registrations
+            .entry(user)
+            .and_compute_with(|existing| match existing {
+                Some(existing) if existing.value() == current => Op::Put(replacement),
+                _ => Op::Nop,
+            }) {
+            CompResult::ReplacedWith(_) => Ok(()),
+            CompResult::Unchanged(_) => Err(StorageError::RegistrationChanged),
+            _ => Err(StorageError::UnableToFindRegistration),
+        }
+    }
+
+    fn set_locked_until(&self, user: &str, until: Option<u64>) -> Result<(), StorageError> {
+        match self
+            .registrations
+            .entry(user.to_string())
+            .and_compute_with(|existing| match existing {
+                Some(existing) => Op::Put(Registration {
+                    locked_until: until,
+                    ..existing.into_value()
+                }),
+                None => Op::Nop,
+            }) {
+            CompResult::ReplacedWith(_) => Ok(()),
+            _ => Err(StorageError::UnableToFindRegistration),
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn insert_session(&self, session: Session) -> Result<(), StorageError> {
+        self.sessions.insert(session.id.clone(), session);
+        Ok(())
+    }
+
+    fn get_session(&self, id: &str) -> Result<Option<Session>, StorageError> {
+        Ok(self.sessions.get(id))
+    }
+
+    fn extend_session(
+        &self,
+        id: &str,
+        now: u64,
+        expires: u64,
+    ) -> Result<Option<Session>, StorageError> {
+        match self
+            .sessions
+            .entry(id.to_string())
+            .and_compute_with(|existing| match existing {
+                Some(existing) if !existing.value().is_expired(now) => Op::Put(Session {
+                    expires,
+                    ..existing.into_value()
+                }),
+                _ => Op::Nop,
+            }) {
+            CompResult::ReplacedWith(entry) => Ok(Some(entry.into_value())),
+            _ => Ok(None),
+        }
+    }
+
+    fn remove_session(&self, id: &str) -> Result<bool, StorageError> {
+        Ok(self.sessions.remove(id).is_some())
+    }
+}
+
+// Unit tests for the in-memory registration and session store.
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::storage::tests::{
+            store_should_insert_and_replace, store_should_keep_sessions,
+            store_should_record_lockouts,
+        },
+    };
+
+    #[test]
+    fn memory_store_should_insert_and_replace() {
+        store_should_insert_and_replace(&MemoryStore::new());
+    }
+
+    #[test]
+    fn memory_store_should_record_lockouts() {
+        store_should_record_lockouts(&MemoryStore::new());
+    }
+
+    #[test]
+    fn memory_store_should_keep_sessions() {
+        store_should_keep_sessions(&MemoryStore::new());
+    }
+}
@@ -0,0 +1,299 @@
+pub mod file;
+pub mod memory;
+pub mod sqlite;
+
+use crate::authentication::kdf::KeyDerivation;
+use crate::errors::StorageError;
+use clap::ValueEnum;
+pub use file::{FileSessionStore, FileStore};
+pub use memory::MemoryStore;
+use serde::{Deserialize, Serialize};
+pub use sqlite::SqliteStore;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Struct representing user registration data including initial setup parameters, with the
+// group elements kept in their canonical encoding, the key derivation the client used to turn
+// the password into the secret, when a lockout after too many failed attempts ends in seconds
+// since the Unix epoch, and the authentication type the user registered with. Registrations kept
+// before the type was recorded have none, and use the primary type the server serves
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registration {
+    pub user: String,
+    #[serde(with = "hex::serde")]
+    pub y1: Vec<u8>,
+    #[serde(with = "hex::serde")]
+    pub y2: Vec<u8>,
+    pub kdf: KeyDerivation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_type: Option<i32>,
+}
+
+impl Registration {
+    // Whether the user is locked out at the time `now`
+    pub fn is_locked(&self, now: u64) -> bool {
+        self.locked_until.is_some_and(|until| now < until)
+    }
+}
+
+// Define a trait for somewhere to keep registrations, so that the server can keep them in memory
+// or persist them across restarts.
+pub trait RegistrationStore: Send + Sync {
+    // Look up the registration of a user, returning None when they have not registered
+    fn get(&self, user: &str) -> Result<Option<Registration>, StorageError>;
+    // Store the registration of a new user, failing if the user has already registered
+    fn insert(&self, registration: Registration) -> Result<(), StorageError>;
+    // Replace the registration `current` of an existing user, failing if the user has not
+    // registered or if their registration is no longer `current`, so that a replacement is never
+    // based on a registration that has since changed
+    fn replace(
+        &self,
+        current: &Registration,
+        replacement: Registration,
+    ) -> Result<(), StorageError>;
+    // Record when the lockout of a registered user ends, or clear it, failing if the user has not
+    // registered
+    fn set_locked_until(&self, user: &str, until: Option<u64>) -> Result<(), StorageError>;
+}
+
+// Struct representing a session started by a successful authentication, with times in seconds
+// since the Unix epoch and the authentication type as its protocol value
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub user: String,
+    pub auth_type: i32,
+    pub created: u64,
+    pub expires: u64,
+}
+
+impl Session {
+    // Whether the session has expired at the time `now`
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires <= now
+    }
+}
+
+// The current time in seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Define a trait for somewhere to keep sessions, so that they can be checked after they were
+// issued. Stores are free to forget expired sessions.
+pub trait SessionStore: Send + Sync {
+    // Store a newly started session
+    fn insert_session(&self, session: Session) -> Result<(), StorageError>;
+    // Look up a session by id, returning None when there is no such session
+    fn get_session(&self, id: &str) -> Result<Option<Session>, StorageError>;
+    // Move the expiry of a session that has not expired at `now` to `expires`, returning the
+    // updated session or None when there is no such unexpired session
+    fn extend_session(
+        &self,
+        id: &str,
+        now: u64,
+        expires: u64,
+    ) -> Result<Option<Session>, StorageError>;
+    // Remove a session, returning whether there was one to remove
+    fn remove_session(&self, id: &str) -> Result<bool, StorageError>;
+}
+
+// The kinds of registration store, selected when starting the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum StorageKind {
+    // Registrations are lost when the server stops
+    #[default]
+    Memory,
+    // An append-only file with one JSON registration per line
+    File,
+    // An embedded SQLite database
+    Sqlite,
+}
+
+// Open a registration store of the given kind, the file based stores require a path.
+pub fn open(
+    kind: StorageKind,
+    path: Option<&Path>,
+) -> Result<Box<dyn RegistrationStore>, StorageError> {
+    let path = || path.ok_or(StorageError::MissingPath);
+    Ok(match kind {
+        StorageKind::Memory => Box::new(MemoryStore::new()),
+        StorageKind::File => Box::new(FileStore::open(path()?)?),
+        StorageKind::Sqlite => Box::new(SqliteStore::open(path()?)?),
+    })
+}
+
+// Open the session store that goes with a registration store of the given kind. The file store
+// keeps sessions in a file next to the registrations, and the SQLite store in the same database.
+pub fn open_sessions(
+    kind: StorageKind,
+    path: Option<&Path>,
+) -> Result<Box<dyn SessionStore>, StorageError> {
+    let path = || path.ok_or(StorageError::MissingPath);
+    Ok(match kind {
+        StorageKind::Memory => Box::new(MemoryStore::new()),
+        StorageKind::File => Box::new(FileSessionStore::open(
+            &FileSessionStore::path_for(path()?),
+        )?),
+        StorageKind::Sqlite => Box::new(SqliteStore::open(path()?)?),
+    })
+}
+
+// Behaviour shared by every store, run against each implementation by their own tests.
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::authentication::kdf::KdfAlgorithm,
+        tempfile::{tempdir, TempDir},
+    };
+
+    // A registration with recognisable contents.
+    pub fn registration(user: &str, fill: u8) -> Registration {
+        Registration {
+            user: user.to_string(),
+            y1: vec![fill; 32],
+            y2: vec![fill.wrapping_add(1); 32],
+            kdf: KeyDerivation::new(KdfAlgorithm::Argon2id),
+            locked_until: None,
+            auth_type: Some(1),
+        }
+    }
+
+    // Registrations must be found by user, never overwritten by another registration of the same
+    // user, and only replaced when the user exists and their registration has not changed.
+    pub fn store_should_insert_and_replace(store: &dyn RegistrationStore) {
+        assert_eq!(store.get("alice").unwrap(), None);
+        let alice = registration("alice", 1);
+        let bob = registration("bob", 2);
+        store.insert(alice.clone()).unwrap();
+        store.insert(bob.clone()).unwrap();
+        assert_eq!(store.get("alice").unwrap(), Some(alice.clone()));
+        assert_eq!(store.get("bob").unwrap(), Some(bob));
+
+        let replacement = registration("alice", 3);
+        assert!(matches!(
+            store.insert(replacement.clone()),
+            Err(StorageError::AlreadyRegistered)
+        ));
+        assert_eq!(store.get("alice").unwrap(), Some(alice.clone()));
+        store.replace(&alice, replacement.clone()).unwrap();
+        assert_eq!(store.get("alice").unwrap(), Some(replacement.clone()));
+
+        assert!(matches!(
+            store.replace(&alice, registration("alice", 5)),
+            Err(StorageError::RegistrationChanged)
+        ));
+        assert_eq!(store.get("alice").unwrap(), Some(replacement.clone()));
+
+        // A lockout recorded after the registration was read must not be lost
+        store.set_locked_until("alice", Some(100)).unwrap();
+        let locked = Registration {
+            locked_until: Some(100),
+            ..replacement.clone()
+        };
+        assert!(matches!(
+            store.replace(&replacement, registration("alice", 6)),
+            Err(StorageError::RegistrationChanged)
+        ));
+        assert_eq!(store.get("alice").unwrap(), Some(locked));
+
+        let carol = registration("carol", 4);
+        assert!(matches!(
+            store.replace(&carol, carol.clone()),
+            Err(StorageError::UnableToFindRegistration)
+        ));
+        assert!(matches!(
+            store.set_locked_until("carol", Some(1)),
+            Err(StorageError::UnableToFindRegistration)
+        ));
+        assert_eq!(store.get("carol").unwrap(), None);
+    }
+
+    // A session for the user, expiring at the given time.
+    pub fn session(id: &str, user: &str, expires: u64) -> Session {
+        Session {
+            id: id.to_string(),
+            user: user.to_string(),
+            auth_type: 1,
+            created: expires.saturating_sub(60),
+            expires,
+        }
+    }
+
+    // Sessions must be found by id until they are removed, and only unexpired sessions extended.
+    pub fn store_should_keep_sessions(store: &dyn SessionStore) {
+        let now = unix_now();
+        let alice = session("a", "alice", now + 60);
+        let expired = session("b", "bob", now + 1);
+        store.insert_session(alice.clone()).unwrap();
+        store.insert_session(expired).unwrap();
+        assert_eq!(store.get_session("a").unwrap(), Some(alice.clone()));
+        assert_eq!(store.get_session("c").unwrap(), None);
+
+        let extended = store.extend_session("a", now, now + 120).unwrap();
+        assert_eq!(
+            extended,
+            Some(Session {
+                expires: now + 120,
+                ..alice
+            })
+        );
+        assert_eq!(store.get_session("a").unwrap(), extended);
+        assert_eq!(store.extend_session("b", now + 1, now + 120).unwrap(), None);
+        assert_eq!(store.extend_session("c", now, now + 120).unwrap(), None);
+
+        assert!(store.remove_session("a").unwrap());
+        assert!(!store.remove_session("a").unwrap());
+        assert_eq!(store.get_session("a").unwrap(), None);
+    }
+
+    // Lockouts must be recorded against the registration without changing anything else.
+    pub fn store_should_record_lockouts(store: &dyn RegistrationStore) {
+        let alice = registration("alice", 1);
+        store.insert(alice.clone()).unwrap();
+        store.set_locked_until("alice", Some(100)).unwrap();
+        let locked = store.get("alice").unwrap().unwrap();
+        assert_eq!(
+            locked,
+            Registration {
+                locked_until: Some(100),
+                ..alice.clone()
+            }
+        );
+        assert!(locked.is_locked(99));
+        assert!(!locked.is_locked(100));
+        store.set_locked_until("alice", None).unwrap();
+        assert_eq!(store.get("alice").unwrap(), Some(alice));
+    }
+
+    // A temporary directory and the path of a store inside it.
+    pub fn temporary_path(name: &str) -> (TempDir, std::path::PathBuf) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(name);
+        (dir, path)
+    }
+
+    // The file based stores cannot be opened without a path.
+    #[test]
+    fn file_stores_should_require_a_path() {
+        assert!(open(StorageKind::Memory, None).is_ok());
+        assert!(matches!(
+            open(StorageKind::File, None),
+            Err(StorageError::MissingPath)
+        ));
+        assert!(matches!(
+            open(StorageKind::Sqlite, None),
+            Err(StorageError::MissingPath)
+        ));
+        assert!(matches!(
+            open_sessions(StorageKind::File, None),
+            Err(StorageError::MissingPath)
+        ));
+    }
+}
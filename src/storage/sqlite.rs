@@ -0,0 +1,340 @@
+use super::{unix_now, Registration, RegistrationStore, Session, SessionStore};
+use crate::authentication::kdf::KeyDerivation;
+use crate::errors::StorageError;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::path::Path;
+use std::sync::Mutex;
+
+// Schema of the registrations table, the key derivation is kept as JSON so that new functions do
+// not need a migration.
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS registrations (
+    user TEXT PRIMARY KEY NOT NULL,
+    y1 BLOB NOT NULL,
+    y2 BLOB NOT NULL,
+    kdf TEXT NOT NULL,
+    locked_until INTEGER,
+    auth_type INTEGER
+);
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    user TEXT NOT NULL,
+    auth_type INTEGER NOT NULL,
+    created INTEGER NOT NULL,
+    expires INTEGER NOT NULL
+)";
+
+// Columns added to the registrations table after it was first created, which are added to older
+// databases when they are opened
+const ADDED_COLUMNS: &[(&str, &str)] = &[("locked_until", "INTEGER"), ("auth_type", "INTEGER")];
+
+// Define a registration and session store backed by an embedded SQLite database.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    // Open the database, creating it and its tables if they do not exist.
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        for (column, definition) in ADDED_COLUMNS {
+            if connection
+                .prepare(&format!("SELECT {column} FROM registrations LIMIT 0"))
+                .is_err()
+            {
+                connection.execute(
+                    &format!("ALTER TABLE registrations ADD COLUMN {column} {definition}"),
+                    [],
+                )?;
+            }
+        }
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl RegistrationStore for SqliteStore {
+    fn get(&self, user: &str) -> Result<Option<Registration>, StorageError> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| StorageError::LockPoisoned)?;
+        select_registration(&connection, user)
+    }
+
+    fn insert(&self, registration: Registration) -> Result<(), StorageError> {
+        let kdf = serde_json::to_string(&registration.kdf)
+            .map_err(|e| StorageError::Corrupt(e.to_string()))?;
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| StorageError::LockPoisoned)?;
+        // The primary key refuses a second registration of the same user
+        match connection.execute(
+            "INSERT INTO registrations (user, y1, y2, kdf, locked_until, auth_type)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                registration.user,
+                registration.y1,
+                registration.y2,
+                kdf,
+                registration.locked_until,
+                registration.auth_type
+            ],
+        ) {
+            Err(rusqlite::Error::SqliteFailure(error, _))
+                if error.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Err(StorageError::AlreadyRegistered)
+            }
+            result => result.map(|_| ()).map_err(StorageError::from),
+        }
+    }
+
+    fn replace(
+        &self,
+        current: &Registration,
+        replacement: Registration,
+    ) -> Result<(), StorageError> {
+        let kdf = serde_json::to_string(&replacement.kdf)
+            .map_err(|e| StorageError::Corrupt(e.to_string()))?;
+        let mut connection = self
+            .connection
+            .lock()
+            .map_err(|_| StorageError::LockPoisoned)?;
+        // Only the registration the replacement was based on is updated. It is compared in full,
+        // as the other stores do, so that a lockout recorded since it was read is not lost, within
+        // a transaction that holds the write lock from the start
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        match select_registration(&transaction, &replacement.user)? {
+            None => return Err(StorageError::UnableToFindRegistration),
+            Some(existing) if existing != *current => {
+                return Err(StorageError::RegistrationChanged)
+            }
+            Some(_) => {}
+        }
+        transaction.execute(
+            "UPDATE registrations
+                SET y1 = ?2, y2 = ?3, kdf = ?4, locked_until = ?5, auth_type = ?6
+                WHERE user = ?1",
+            params![
+                replacement.user,
+                replacement.y1,
+                replacement.y2,
+                kdf,
+                replacement.locked_until,
+                replacement.auth_type
+            ],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn set_locked_until(&self, user: &str, until: Option<u64>) -> Result<(), StorageError> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| StorageError::LockPoisoned)?;
+        let updated = connection.execute(
+            "UPDATE registrations SET locked_until = ?2 WHERE user = ?1",
+            params![user, until],
+        )?;
+        if updated == 0 {
+            return Err(StorageError::UnableToFindRegistration);
+        }
+        Ok(())
+    }
+}
+
+impl SessionStore for SqliteStore {
+    fn insert_session(&self, session: Session) -> Result<(), StorageError> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| StorageError::LockPoisoned)?;
+        // Forget expired sessions as new ones start, so that the table does not grow without bound
+        connection.execute(
+            "DELETE FROM sessions WHERE expires <= ?1",
+            params![unix_now()],
+        )?;
+        connection.execute(
+            "INSERT INTO sessions (id, user, auth_type, created, expires)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                session.id,
+                session.user,
+                session.auth_type,
+                session.created,
+                session.expires
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_session(&self, id: &str) -> Result<Option<Session>, StorageError> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| StorageError::LockPoisoned)?;
+        Ok(select_session(&connection, id)?)
+    }
+
+    fn extend_session(
+        &self,
+        id: &str,
+        now: u64,
+        expires: u64,
+    ) -> Result<Option<Session>, StorageError> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| StorageError::LockPoisoned)?;
+        let updated = connection.execute(
+            "UPDATE sessions SET expires = ?3 WHERE id = ?1 AND expires > ?2",
+            params![id, now, expires],
+        )?;
+        if updated == 0 {
+            return Ok(None);
+        }
+        Ok(select_session(&connection, id)?)
+    }
+
+    fn remove_session(&self, id: &str) -> Result<bool, StorageError> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| StorageError::LockPoisoned)?;
+        let removed = connection.execute("DELETE FROM sessions WHERE id = ?1", params![id])?;
+        Ok(removed > 0)
+    }
+}
+
+// Read the registration of a user
+fn select_registration(
+    connection: &Connection,
+    user: &str,
+) -> Result<Option<Registration>, StorageError> {
+    let row = connection
+        .query_row(
+            "SELECT y1, y2, kdf, locked_until, auth_type FROM registrations WHERE user = ?1",
+            params![user],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )
+        .optional()?;
+    row.map(|(y1, y2, kdf, locked_until, auth_type)| {
+        let kdf: KeyDerivation =
+            serde_json::from_str(&kdf).map_err(|e| StorageError::Corrupt(e.to_string()))?;
+        Ok(Registration {
+            user: user.to_string(),
+            y1,
+            y2,
+            kdf,
+            locked_until,
+            auth_type,
+        })
+    })
+    .transpose()
+}
+
+// Read a session by id
+fn select_session(connection: &Connection, id: &str) -> rusqlite::Result<Option<Session>> {
+    connection
+        .query_row(
+            "SELECT user, auth_type, created, expires FROM sessions WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok(Session {
+                    id: id.to_string(),
+                    user: row.get(0)?,
+                    auth_type: row.get(1)?,
+                    created: row.get(2)?,
+                    expires: row.get(3)?,
+                })
+            },
+        )
+        .optional()
+}
+
+// Unit tests for the SQLite registration and session store.
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::storage::tests::{
+            registration, session, store_should_insert_and_replace, store_should_keep_sessions,
+            store_should_record_lockouts, temporary_path,
+        },
+    };
+
+    #[test]
+    fn sqlite_store_should_record_lockouts() {
+        let (_dir, path) = temporary_path("registrations.db");
+        store_should_record_lockouts(&SqliteStore::open(&path).unwrap());
+    }
+
+    // Databases created before lockouts and authentication types were recorded must gain the
+    // columns when opened.
+    #[test]
+    fn databases_without_added_columns_should_be_migrated() {
+        let (_dir, path) = temporary_path("registrations.db");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE registrations (
+                    user TEXT PRIMARY KEY NOT NULL,
+                    y1 BLOB NOT NULL,
+                    y2 BLOB NOT NULL,
+                    kdf TEXT NOT NULL
+                )",
+            )
+            .unwrap();
+        store_should_record_lockouts(&SqliteStore::open(&path).unwrap());
+    }
+
+    #[test]
+    fn sqlite_store_should_insert_and_replace() {
+        let (_dir, path) = temporary_path("registrations.db");
+        store_should_insert_and_replace(&SqliteStore::open(&path).unwrap());
+    }
+
+    #[test]
+    fn sqlite_store_should_keep_sessions() {
+        let (_dir, path) = temporary_path("registrations.db");
+        store_should_keep_sessions(&SqliteStore::open(&path).unwrap());
+    }
+
+    // Sessions must survive reopening the database.
+    #[test]
+    fn sessions_should_persist() {
+        let (_dir, path) = temporary_path("registrations.db");
+        let alice = session("a", "alice", unix_now() + 60);
+        SqliteStore::open(&path)
+            .unwrap()
+            .insert_session(alice.clone())
+            .unwrap();
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.get_session("a").unwrap(), Some(alice));
+    }
+
+    // Registrations must survive reopening the database.
+    #[test]
+    fn registrations_should_persist() {
+        let (_dir, path) = temporary_path("registrations.db");
+        let alice = registration("alice", 1);
+        SqliteStore::open(&path)
+            .unwrap()
+            .insert(alice.clone())
+            .unwrap();
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.get("alice").unwrap(), Some(alice));
+    }
+}
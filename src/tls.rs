@@ -0,0 +1,64 @@
+use crate::errors::TlsError;
+use std::fs;
+use std::path::Path;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+// Read a PEM file holding certificates or a private key
+fn read_pem(path: &Path) -> Result<Vec<u8>, TlsError> {
+    fs::read(path).map_err(|source| TlsError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// Build the TLS configuration of the server from its PEM certificate chain and private key. When a
+// CA is given, clients must present a certificate signed by it, unless client certificates are
+// optional, in which case only the certificates clients do present are checked.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+    client_auth_optional: bool,
+) -> Result<ServerTlsConfig, TlsError> {
+    let config =
+        ServerTlsConfig::new().identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+    Ok(match client_ca {
+        Some(ca) => config
+            .client_ca_root(Certificate::from_pem(read_pem(ca)?))
+            .client_auth_optional(client_auth_optional),
+        None => config,
+    })
+}
+
+// Build the TLS configuration of a client that trusts servers whose certificate is signed by the
+// PEM CA and issued for `domain`, presenting its own certificate and private key for mutual TLS
+// when they are given.
+pub fn client_config(
+    ca: &Path,
+    identity: Option<(&Path, &Path)>,
+    domain: &str,
+) -> Result<ClientTlsConfig, TlsError> {
+    let config = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(read_pem(ca)?))
+        .domain_name(domain);
+    Ok(match identity {
+        Some((cert, key)) => config.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?)),
+        None => config,
+    })
+}
+
+// Unit tests for building TLS configurations, connections are covered by the integration tests.
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::storage::tests::temporary_path};
+
+    // A missing file must be reported with its path rather than when the first connection is made.
+    #[test]
+    fn missing_files_should_be_reported() {
+        let (_dir, path) = temporary_path("missing.pem");
+        let error = server_config(&path, &path, None, false).unwrap_err();
+        assert!(matches!(error, TlsError::Io { .. }));
+        assert!(error.to_string().contains("missing.pem"));
+        assert!(client_config(&path, None, "localhost").is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use crate::errors::TokenError;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, PUBLIC_KEY_LENGTH};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+// The JOSE name of the Ed25519 signature algorithm, the only one tokens are signed or accepted with
+pub const ALGORITHM: &str = "EdDSA";
+
+// The JOSE header of a token, naming the algorithm and the key it was signed with
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    typ: String,
+    kid: String,
+}
+
+// The claims carried by a session token: the user, when it was issued and when it expires in seconds
+// since the Unix epoch, and the authentication type the user signed in with
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub auth: String,
+}
+
+// Identify a public key by the first 8 bytes of its SHA-256 hash, so that verifiers can tell which
+// key a token was signed with when keys are rotated
+pub fn key_id(key: &VerifyingKey) -> String {
+    hex::encode(&Sha256::digest(key.as_bytes())[..8])
+}
+
+// Define a signer of session tokens, issued as JWTs signed with Ed25519 so that other services can
+// check them against the published public key without calling back to the server.
+pub struct TokenSigner {
+    key: SigningKey,
+}
+
+impl TokenSigner {
+    // Generate a new random signing key.
+    pub fn generate() -> Self {
+        TokenSigner {
+            key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    // Read the signing key from a file holding its hex encoded seed, creating the file with a new
+    // key, readable only by its owner, if it does not exist.
+    pub fn load_or_generate(path: &Path) -> Result<Self, TokenError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                let seed = Zeroizing::new(
+                    hex::decode(contents.trim())
+                        .map_err(|e| TokenError::InvalidKey(e.to_string()))?,
+                );
+                let seed =
+                    Zeroizing::new(<[u8; 32]>::try_from(seed.as_slice()).map_err(|_| {
+                        TokenError::InvalidKey("seed must be 32 bytes".to_string())
+                    })?);
+                Ok(TokenSigner {
+                    key: SigningKey::from_bytes(&seed),
+                })
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let signer = Self::generate();
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                let mut file = options.open(path)?;
+                let seed = Zeroizing::new(hex::encode(signer.key.to_bytes()));
+                file.write_all(seed.as_bytes())?;
+                file.sync_data()?;
+                Ok(signer)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // The public key tokens are verified with
+    pub fn public_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    // Sign the claims, returning the compact serialisation of the token
+    pub fn sign(&self, claims: &Claims) -> Result<String, TokenError> {
+        let header = Header {
+            alg: ALGORITHM.to_string(),
+            typ: "JWT".to_string(),
+            kid: key_id(&self.public_key()),
+        };
+        let signing_input = format!("{}.{}", encode(&header)?, encode(claims)?);
+        let signature = self.key.sign(signing_input.as_bytes());
+        Ok(format!(
+            "{}.{}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature.to_bytes())
+        ))
+    }
+}
+
+// Define a verifier of session tokens, holding only the published public key.
+pub struct TokenVerifier {
+    key: VerifyingKey,
+}
+
+impl TokenVerifier {
+    // Build a verifier from the raw bytes of an Ed25519 public key.
+    pub fn new(public_key: &[u8]) -> Result<Self, TokenError> {
+        let bytes = <[u8; PUBLIC_KEY_LENGTH]>::try_from(public_key).map_err(|_| {
+            TokenError::InvalidKey(format!("public key must be {PUBLIC_KEY_LENGTH} bytes"))
+        })?;
+        let key =
+            VerifyingKey::from_bytes(&bytes).map_err(|e| TokenError::InvalidKey(e.to_string()))?;
+        Ok(TokenVerifier { key })
+    }
+
+    // Check the signature and expiry of a token at the time `now`, returning its claims. Tokens
+    // naming any algorithm but Ed25519 are refused before their signature is looked at.
+    pub fn verify(&self, token: &str, now: u64) -> Result<Claims, TokenError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(claims), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(TokenError::Malformed);
+        };
+
+        let decoded: Header = decode(header)?;
+        if decoded.alg != ALGORITHM {
+            return Err(TokenError::UnsupportedAlgorithm(decoded.alg));
+        }
+
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| TokenError::Malformed)?;
+        let signature = Signature::from_slice(&signature).map_err(|_| TokenError::Malformed)?;
+        self.key
+            .verify_strict(format!("{header}.{claims}").as_bytes(), &signature)
+            .map_err(|_| TokenError::InvalidSignature)?;
+
+        let claims: Claims = decode(claims)?;
+        if claims.exp <= now {
+            return Err(TokenError::Expired);
+        }
+        Ok(claims)
+    }
+}
+
+// Encode a part of a token as base64url encoded JSON
+fn encode<T: Serialize>(value: &T) -> Result<String, TokenError> {
+    let json = serde_json::to_vec(value).map_err(|e| TokenError::Encoding(e.to_string()))?;
+    Ok(URL_SAFE_NO_PAD.encode(json))
+}
+
+// Decode a base64url encoded JSON part of a token
+fn decode<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, TokenError> {
+    let json = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| TokenError::Malformed)?;
+    serde_json::from_slice(&json).map_err(|_| TokenError::Malformed)
+}
+
+// Unit tests for signing and verifying session tokens.
+#[cfg(test)]
+mod tests {
+    use {super::*, test_case::test_case};
+
+    fn claims(exp: u64) -> Claims {
+        Claims {
+            sub: "alice".to_string(),
+            iat: exp - 60,
+            exp,
+            auth: "EllipticCurve".to_string(),
+        }
+    }
+
+    fn verifier(signer: &TokenSigner) -> TokenVerifier {
+        TokenVerifier::new(signer.public_key().as_bytes()).unwrap()
+    }
+
+    // A token must verify against the key it was signed with, giving back its claims.
+    #[test]
+    fn tokens_should_verify_with_the_public_key() {
+        let signer = TokenSigner::generate();
+        let token = signer.sign(&claims(1_000)).unwrap();
+        assert_eq!(
+            verifier(&signer).verify(&token, 999).unwrap(),
+            claims(1_000)
+        );
+    }
+
+    // Tokens must be refused once expired, or when checked against another key.
+    #[test]
+    fn tokens_should_be_refused_when_expired_or_signed_by_another_key() {
+        let signer = TokenSigner::generate();
+        let token = signer.sign(&claims(1_000)).unwrap();
+        assert!(matches!(
+            verifier(&signer).verify(&token, 1_000),
+            Err(TokenError::Expired)
+        ));
+        assert!(matches!(
+            verifier(&TokenSigner::generate()).verify(&token, 999),
+            Err(TokenError::InvalidSignature)
+        ));
+    }
+
+    // Changing any part of a token must make it fail verification.
+    #[test_case(0; "when header is changed")]
+    #[test_case(1; "when claims are changed")]
+    #[test_case(2; "when signature is changed")]
+    fn tampered_tokens_should_be_refused(part: usize) {
+        let signer = TokenSigner::generate();
+        let token = signer.sign(&claims(1_000)).unwrap();
+        let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+        parts[part] = match part {
+            0 => encode(&Header {
+                alg: ALGORITHM.to_string(),
+                typ: "JWT".to_string(),
+                kid: "another".to_string(),
+            })
+            .unwrap(),
+            1 => encode(&Claims {
+                sub: "mallory".to_string(),
+                ..claims(1_000)
+            })
+            .unwrap(),
+            _ => URL_SAFE_NO_PAD.encode([0u8; 64]),
+        };
+        assert!(verifier(&signer).verify(&parts.join("."), 999).is_err());
+    }
+
+    // Tokens claiming another algorithm, including none, must be refused.
+    #[test_case("none"; "when algorithm is none")]
+    #[test_case("HS256"; "when algorithm is symmetric")]
+    fn other_algorithms_should_be_refused(alg: &str) {
+        let signer = TokenSigner::generate();
+        let token = signer.sign(&claims(1_000)).unwrap();
+        let header = encode(&Header {
+            alg: alg.to_string(),
+            typ: "JWT".to_string(),
+            kid: key_id(&signer.public_key()),
+        })
+        .unwrap();
+        let forged = format!("{}.{}.", header, token.split('.').nth(1).unwrap());
+        assert!(matches!(
+            verifier(&signer).verify(&forged, 999),
+            Err(TokenError::UnsupportedAlgorithm(_))
+        ));
+    }
+
+    // A key file must be created on first use and give back the same key afterwards.
+    #[test]
+    fn key_files_should_be_created_and_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.key");
+        let created = TokenSigner::load_or_generate(&path).unwrap();
+        let loaded = TokenSigner::load_or_generate(&path).unwrap();
+        assert_eq!(created.public_key(), loaded.public_key());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// Integration tests driving the client library against a local server without a terminal, with
+// the passwords given by closures.
+use acp::authentication::exponentiation::{Exponentiation, GroupId};
+use acp::authentication::kdf::KdfAlgorithm;
+use acp::client::{
+    reuse_session, revoke_session, validate_session, ClientAuthenticator, ClientRegistrar,
+};
+use acp::credentials::SessionCache;
+use acp::errors::AuthenticationError;
+use acp::secret::{SecretProvider, SecretPurpose};
+use acp::server::{ChallengePolicy, ServerState, DEFAULT_SESSION_TTL_SECS};
+use acp::storage::{unix_now, MemoryStore};
+use acp::zkp_auth::auth_client::AuthClient;
+use acp::zkp_auth::auth_server::AuthServer;
+use acp::zkp_auth::AuthenticationType;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+use zeroize::Zeroizing;
+
+// Serve the authentication service in plaintext on a free local port, returning a client for it
+async fn serve() -> AuthClient<Channel> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address: SocketAddr = listener.local_addr().unwrap();
+    let state = ServerState::new(
+        &[AuthenticationType::EllipticCurve],
+        Exponentiation::with_group(GroupId::default()),
+        Box::new(MemoryStore::new()),
+        Box::new(MemoryStore::new()),
+        ChallengePolicy::default(),
+        Duration::from_secs(DEFAULT_SESSION_TTL_SECS),
+    );
+    let server = Server::builder().add_service(AuthServer::new(state));
+    tokio::spawn(server.serve_with_incoming(TcpListenerStream::new(listener)));
+    AuthClient::connect(format!("http://{address}"))
+        .await
+        .unwrap()
+}
+
+// A provider giving the same password whatever the purpose
+fn password(password: &'static str) -> impl SecretProvider {
+    move |_: SecretPurpose| -> Result<Zeroizing<Vec<u8>>, AuthenticationError> {
+        Ok(Zeroizing::new(password.as_bytes().to_vec()))
+    }
+}
+
+// Users must be able to register, authenticate and change their password with passwords given by
+// the caller, authenticating must return the session the server started, which can be cached and
+// reused, and a wrong password must be refused.
+#[tokio::test]
+async fn clients_should_run_without_a_terminal() {
+    let mut client = serve().await;
+    let registrar = ClientRegistrar::new(&mut client, None).await.unwrap();
+    assert!(registrar
+        .register(
+            "alice",
+            Some(KdfAlgorithm::Pbkdf2),
+            &password("correct horse"),
+            &mut client
+        )
+        .await
+        .unwrap());
+
+    let authenticator = ClientAuthenticator::new(&mut client, "alice", None)
+        .await
+        .unwrap();
+    let session = authenticator
+        .authenticate("alice", &password("correct horse"), &mut client)
+        .await
+        .unwrap();
+    assert_eq!(session.user, "alice");
+    assert_eq!(session.auth_type, AuthenticationType::EllipticCurve);
+    assert!(!session.is_expired(unix_now()));
+    let session = authenticator
+        .authenticate_non_interactive("alice", &password("correct horse"), &mut client)
+        .await
+        .unwrap();
+    assert_eq!(
+        validate_session(&mut client, &session.id)
+            .await
+            .unwrap()
+            .expires,
+        session.expires
+    );
+
+    // A cached session must be reused until the server no longer accepts it
+    let mut cache = SessionCache::default();
+    cache.insert("server", session.clone());
+    let reused = reuse_session(&mut client, &mut cache, "server", "alice")
+        .await
+        .unwrap();
+    assert_eq!(reused.map(|reused| reused.id), Some(session.id.clone()));
+    revoke_session(&mut client, &session.id).await.unwrap();
+    let reused = reuse_session(&mut client, &mut cache, "server", "alice")
+        .await
+        .unwrap();
+    assert!(reused.is_none());
+    assert!(cache.get("server", "alice").is_none());
+    assert!(authenticator
+        .authenticate("alice", &password("wrong horse"), &mut client)
+        .await
+        .is_err());
+
+    assert!(authenticator
+        .change_password(
+            "alice",
+            Some(KdfAlgorithm::Pbkdf2),
+            &password("correct horse"),
+            &password("battery staple"),
+            &mut client
+        )
+        .await
+        .unwrap());
+    assert!(authenticator
+        .authenticate("alice", &password("battery staple"), &mut client)
+        .await
+        .is_ok());
+    assert!(authenticator
+        .authenticate("alice", &password("correct horse"), &mut client)
+        .await
+        .is_err());
+}
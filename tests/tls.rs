@@ -0,0 +1,181 @@
+// Integration tests serving the authentication service over TLS and mutual TLS, with certificates
+// generated for each test.
+use acp::authentication::exponentiation::{Exponentiation, GroupId};
+use acp::server::{ChallengePolicy, ServerState, DEFAULT_SESSION_TTL_SECS};
+use acp::storage::MemoryStore;
+use acp::tls::{client_config, server_config};
+use acp::zkp_auth::auth_client::AuthClient;
+use acp::zkp_auth::auth_server::AuthServer;
+use acp::zkp_auth::{AuthTypeRequest, AuthenticationType};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, ClientTlsConfig, Server, ServerTlsConfig};
+
+// A certificate and private key written to PEM files
+struct Pem {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+// Sign a certificate with the CA, or self-sign it when no CA is given, and write it and its key to
+// the directory
+fn write_certificate(
+    dir: &Path,
+    name: &str,
+    params: CertificateParams,
+    ca: Option<&Certificate>,
+) -> (Certificate, Pem) {
+    let certificate = Certificate::from_params(params).unwrap();
+    let pem = Pem {
+        cert: dir.join(format!("{name}.pem")),
+        key: dir.join(format!("{name}.key")),
+    };
+    let cert = match ca {
+        Some(ca) => certificate.serialize_pem_with_signer(ca).unwrap(),
+        None => certificate.serialize_pem().unwrap(),
+    };
+    fs::write(&pem.cert, cert).unwrap();
+    fs::write(&pem.key, certificate.serialize_private_key_pem()).unwrap();
+    (certificate, pem)
+}
+
+// A CA, written to the directory, and a client certificate signed by it
+fn certificate_authority(dir: &Path, name: &str) -> (Certificate, Pem, Pem) {
+    let mut params = CertificateParams::new(Vec::new());
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let (ca, ca_pem) = write_certificate(dir, name, params, None);
+    let mut params = CertificateParams::new(vec!["client".to_string()]);
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let (_, client) = write_certificate(dir, &format!("{name}-client"), params, Some(&ca));
+    (ca, ca_pem, client)
+}
+
+// Certificates for a server and for clients, some signed by the trusted CA and some by another
+struct Pki {
+    _dir: TempDir,
+    ca: PathBuf,
+    server: Pem,
+    client: Pem,
+    other_ca: PathBuf,
+    other_client: Pem,
+}
+
+impl Pki {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let (ca, ca_pem, client) = certificate_authority(dir.path(), "ca");
+        let mut params =
+            CertificateParams::new(vec!["localhost".to_string(), "127.0.0.1".to_string()]);
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        let (_, server) = write_certificate(dir.path(), "server", params, Some(&ca));
+        let (_, other_ca, other_client) = certificate_authority(dir.path(), "other");
+        Pki {
+            _dir: dir,
+            ca: ca_pem.cert,
+            server,
+            client,
+            other_ca: other_ca.cert,
+            other_client,
+        }
+    }
+
+    // The TLS configuration of the server, checking client certificates against the CA if asked
+    fn server(&self, client_ca: Option<&Path>, client_auth_optional: bool) -> ServerTlsConfig {
+        server_config(
+            &self.server.cert,
+            &self.server.key,
+            client_ca,
+            client_auth_optional,
+        )
+        .unwrap()
+    }
+
+    // The TLS configuration of a client trusting the CA, presenting the certificate if one is given
+    fn client(&self, ca: &Path, identity: Option<&Pem>) -> ClientTlsConfig {
+        let identity = identity.map(|pem| (pem.cert.as_path(), pem.key.as_path()));
+        client_config(ca, identity, "localhost").unwrap()
+    }
+}
+
+// Serve the authentication service over TLS on a free local port, returning its address
+async fn serve(tls: ServerTlsConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let state = ServerState::new(
+        &[AuthenticationType::EllipticCurve],
+        Exponentiation::with_group(GroupId::default()),
+        Box::new(MemoryStore::new()),
+        Box::new(MemoryStore::new()),
+        ChallengePolicy::default(),
+        Duration::from_secs(DEFAULT_SESSION_TTL_SECS),
+    );
+    let server = Server::builder()
+        .tls_config(tls)
+        .unwrap()
+        .add_service(AuthServer::new(state));
+    tokio::spawn(server.serve_with_incoming(TcpListenerStream::new(listener)));
+    address
+}
+
+// Make a call to the server, over TLS when a configuration is given, returning whether it succeeded
+async fn call(address: SocketAddr, tls: Option<ClientTlsConfig>) -> bool {
+    let endpoint = match tls {
+        Some(tls) => Channel::from_shared(format!("https://{address}"))
+            .unwrap()
+            .tls_config(tls)
+            .unwrap(),
+        None => Channel::from_shared(format!("http://{address}")).unwrap(),
+    };
+    let Ok(channel) = endpoint.connect().await else {
+        return false;
+    };
+    AuthClient::new(channel)
+        .get_auth_type(AuthTypeRequest {
+            user: String::new(),
+        })
+        .await
+        .is_ok()
+}
+
+// Clients trusting the CA must be served, while plaintext clients and clients trusting another
+// CA must not be.
+#[tokio::test]
+async fn tls_should_serve_clients_that_trust_the_server() {
+    let pki = Pki::new();
+    let address = serve(pki.server(None, false)).await;
+
+    assert!(call(address, Some(pki.client(&pki.ca, None))).await);
+    assert!(call(address, Some(pki.client(&pki.ca, Some(&pki.client)))).await);
+    assert!(!call(address, None).await);
+    assert!(!call(address, Some(pki.client(&pki.other_ca, None))).await);
+}
+
+// With client certificate verification, only clients presenting a certificate signed by the CA
+// must be served.
+#[tokio::test]
+async fn mutual_tls_should_require_a_trusted_client_certificate() {
+    let pki = Pki::new();
+    let address = serve(pki.server(Some(&pki.ca), false)).await;
+
+    assert!(call(address, Some(pki.client(&pki.ca, Some(&pki.client)))).await);
+    assert!(!call(address, Some(pki.client(&pki.ca, None))).await);
+    assert!(!call(address, Some(pki.client(&pki.ca, Some(&pki.other_client)))).await);
+}
+
+// When client certificates are optional, clients without one must be served, but a certificate
+// that is presented must still be signed by the CA.
+#[tokio::test]
+async fn optional_client_certificates_should_be_checked_when_presented() {
+    let pki = Pki::new();
+    let address = serve(pki.server(Some(&pki.ca), true)).await;
+
+    assert!(call(address, Some(pki.client(&pki.ca, None))).await);
+    assert!(call(address, Some(pki.client(&pki.ca, Some(&pki.client)))).await);
+    assert!(!call(address, Some(pki.client(&pki.ca, Some(&pki.other_client)))).await);
+}